@@ -0,0 +1,46 @@
+; Functions and closures
+(function_item
+  body: (_) @function.inner) @function.outer
+
+(closure_expression
+  body: (_) @function.inner) @function.outer
+
+; Impl, struct, enum, trait and module bodies
+(impl_item
+  body: (_) @class.inner) @class.outer
+
+(struct_item
+  body: (_) @class.inner) @class.outer
+
+(enum_item
+  body: (_) @class.inner) @class.outer
+
+(union_item
+  body: (_) @class.inner) @class.outer
+
+(trait_item
+  body: (_) @class.inner) @class.outer
+
+(mod_item
+  body: (_) @class.inner) @class.outer
+
+; Arguments and parameters, the separators around them are added in code
+(parameters
+  (_) @parameter.inner)
+
+(closure_parameters
+  (_) @parameter.inner)
+
+(arguments
+  (_) @parameter.inner)
+
+(type_parameters
+  (_) @parameter.inner)
+
+(type_arguments
+  (_) @parameter.inner)
+
+; Comments
+(line_comment) @comment.outer
+
+(block_comment) @comment.outer
@@ -1,28 +1,90 @@
+use std::{collections::HashMap, ops::Range, path::PathBuf};
+
+use tree_sitter::{InputEdit, Point};
+
+use crate::{
+    buffer::{file::DiskState, large::Loading},
+    cursor::Cursor,
+    editor::options::BufferOptions,
+    highlighter::BracketCache,
+};
+
+/// A position in a buffer that moves along as text is inserted or deleted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mark {
+    /// Set with `m`, or automatically like `.` for the last change.
+    Named(char),
+    /// An entry in a window's jump list.
+    Jump(usize),
+}
+
 pub struct Buffer {
     pub buffer_name: String,
+    /// The file the buffer is loaded from and written to, if it has one.
+    pub path: Option<PathBuf>,
     pub text: String,
     pub line_offsets: Vec<usize>,
+    pub options: BufferOptions,
+    /// Where the cursor was when the buffer was last shown.
+    pub last_cursor: Cursor,
+    /// The text changed since it was loaded or last written.
+    pub modified: bool,
+    /// Byte offsets of the marks in this buffer.
+    pub marks: HashMap<Mark, usize>,
+    /// Where unsaved changes are kept in case the editor dies, if anywhere.
+    pub swap_file: Option<PathBuf>,
+    /// The text changed since the swap file was last written.
+    pub swap_stale: bool,
+    /// The file as it was when last read or written.
+    pub disk: Option<DiskState>,
+    /// A directory listing rather than a file, `path` is the directory.
+    pub listing: bool,
+    /// Opened in large-file mode: no highlighting and no swap file.
+    pub large: bool,
+    /// The file is still being read in the background.
+    pub loading: Option<Loading>,
+    /// How many times the text changed, to tell what was worked out from it is out of date.
+    pub changes: u64,
+    pub brackets: BracketCache,
 }
 
 impl Buffer {
     pub fn new(buffer_name: String, text: String) -> Buffer {
-        let mut line_offsets = vec![0];
-
-        line_offsets.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+        let line_offsets = index_lines(&text);
 
         Buffer {
             buffer_name,
+            path: None,
             text,
             line_offsets,
+            options: BufferOptions::default(),
+            last_cursor: Cursor::default(),
+            modified: false,
+            marks: HashMap::new(),
+            swap_file: None,
+            swap_stale: false,
+            disk: None,
+            listing: false,
+            large: false,
+            loading: None,
+            changes: 0,
+            brackets: BracketCache::default(),
         }
     }
 
     pub fn update_text(&mut self, text: String) {
+        let line_offsets = index_lines(&text);
+        self.set_indexed_text(text, line_offsets);
+    }
+
+    /// Like [`Buffer::update_text`] with the lines of `text` already indexed,
+    /// as large files are off the main thread.
+    pub fn set_indexed_text(&mut self, text: String, line_offsets: Vec<usize>) {
         self.text = text;
-        self.line_offsets.clear();
-        self.line_offsets.push(0);
-        self.line_offsets
-            .extend(self.text.match_indices('\n').map(|(i, _)| i + 1));
+        self.swap_stale = true;
+        self.line_offsets = line_offsets;
+        self.changes += 1;
+        self.brackets.reset();
     }
 
     pub fn line_count(&self) -> usize {
@@ -44,6 +106,11 @@ impl Buffer {
         Some(&self.text[start..end])
     }
 
+    /// Length of a line in chars, or 0 past the end of the buffer.
+    pub fn line_len(&self, line: usize) -> usize {
+        self.get_line(line).map(|l| l.chars().count()).unwrap_or(0)
+    }
+
     pub fn char_to_byte_position(&self, line: usize, col: usize) -> Option<usize> {
         if line >= self.line_offsets.len() {
             return None;
@@ -60,4 +127,214 @@ impl Buffer {
 
         Some(lstart + line_byte_pos)
     }
+
+    /// Line and char column of the char starting at `byte_pos`.
+    pub fn byte_to_cursor(&self, byte_pos: usize) -> Cursor {
+        let y = self.line_offsets.partition_point(|&o| o <= byte_pos) - 1;
+        let x = self.text[self.line_offsets[y]..byte_pos].chars().count();
+        Cursor { x, y }
+    }
+
+    /// Where `mark` is, if it is set. Past the end of the text it is on the last char.
+    pub fn mark(&self, mark: Mark) -> Option<Cursor> {
+        let mut pos = (*self.marks.get(&mark)?).min(self.text.len());
+        while !self.text.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        Some(self.byte_to_cursor(pos))
+    }
+
+    /// Inserts `text` at `byte_pos` and keeps `line_offsets` and marks in sync.
+    pub fn insert(&mut self, byte_pos: usize, text: &str) {
+        let start_position = self.point(byte_pos);
+        self.text.insert_str(byte_pos, text);
+        self.modified = true;
+        self.swap_stale = true;
+
+        // A mark on the char the text goes in front of stays with that char
+        for pos in self.marks.values_mut() {
+            if *pos >= byte_pos {
+                *pos += text.len();
+            }
+        }
+        self.marks.insert(Mark::Named('.'), byte_pos);
+
+        // A line starting exactly at `byte_pos` keeps its start, the text goes into it
+        let idx = self.line_offsets.partition_point(|&o| o <= byte_pos);
+        for offset in &mut self.line_offsets[idx..] {
+            *offset += text.len();
+        }
+
+        let new_lines: Vec<usize> = text
+            .match_indices('\n')
+            .map(|(i, _)| byte_pos + i + 1)
+            .collect();
+        self.line_offsets.splice(idx..idx, new_lines);
+
+        self.changes += 1;
+        self.brackets.edit(&InputEdit {
+            start_byte: byte_pos,
+            old_end_byte: byte_pos,
+            new_end_byte: byte_pos + text.len(),
+            start_position,
+            old_end_position: start_position,
+            new_end_position: self.point(byte_pos + text.len()),
+        });
+    }
+
+    /// Removes the bytes in `range` and keeps `line_offsets` and marks in sync.
+    pub fn delete(&mut self, range: Range<usize>) {
+        let len = range.end - range.start;
+        let (start_position, old_end_position) = (self.point(range.start), self.point(range.end));
+        self.text.replace_range(range.clone(), "");
+        self.modified = true;
+        self.swap_stale = true;
+
+        // Marks in the deleted text end up where it was
+        for pos in self.marks.values_mut() {
+            if *pos >= range.end {
+                *pos -= len;
+            } else if *pos > range.start {
+                *pos = range.start;
+            }
+        }
+        self.marks.insert(Mark::Named('.'), range.start);
+
+        // Lines starting inside (start, end] lost their newline
+        let first = self.line_offsets.partition_point(|&o| o <= range.start);
+        let last = self.line_offsets.partition_point(|&o| o <= range.end);
+        self.line_offsets.drain(first..last);
+        for offset in &mut self.line_offsets[first..] {
+            *offset -= len;
+        }
+
+        self.changes += 1;
+        self.brackets.edit(&InputEdit {
+            start_byte: range.start,
+            old_end_byte: range.end,
+            new_end_byte: range.start,
+            start_position,
+            old_end_position,
+            new_end_position: start_position,
+        });
+    }
+
+    /// Line and byte column of `byte_pos`, as the syntax tree counts them.
+    fn point(&self, byte_pos: usize) -> Point {
+        let row = self.line_offsets.partition_point(|&o| o <= byte_pos) - 1;
+        Point::new(row, byte_pos - self.line_offsets[row])
+    }
+
+    /// Screen column at which the char at `col` starts, with tabs expanded.
+    pub fn visual_col(&self, line: usize, col: usize) -> usize {
+        let tabstop = self.options.tabstop;
+        self.get_line(line)
+            .unwrap_or("")
+            .chars()
+            .take(col)
+            .fold(0, |vcol, ch| vcol + char_width(ch, vcol, tabstop))
+    }
+
+    /// Char index covering the screen column `vcol`, clamped to the line length.
+    pub fn col_at_visual(&self, line: usize, vcol: usize) -> usize {
+        let tabstop = self.options.tabstop;
+        let mut current = 0;
+        for (col, ch) in self.get_line(line).unwrap_or("").chars().enumerate() {
+            current += char_width(ch, current, tabstop);
+            if current > vcol {
+                return col;
+            }
+        }
+        self.line_len(line)
+    }
+}
+
+/// Byte offsets of the starts of the lines in `text`.
+pub fn index_lines(text: &str) -> Vec<usize> {
+    let mut line_offsets = vec![0];
+    line_offsets.extend(text.match_indices('\n').map(|(i, _)| i + 1));
+    line_offsets
+}
+
+/// Number of screen cells `ch` takes when it starts at screen column `vcol`.
+pub fn char_width(ch: char, vcol: usize, tabstop: usize) -> usize {
+    if ch == '\t' {
+        let tabstop = tabstop.max(1);
+        tabstop - vcol % tabstop
+    } else {
+        1
+    }
+}
+
+/// Tabs and spaces spanning screen columns `from..to`, using as many tabs as fit.
+pub fn whitespace_fill(from: usize, to: usize, tabstop: usize) -> String {
+    let tabstop = tabstop.max(1);
+    let mut fill = String::new();
+    let mut vcol = from;
+
+    while (vcol / tabstop + 1) * tabstop <= to {
+        fill.push('\t');
+        vcol = (vcol / tabstop + 1) * tabstop;
+    }
+    fill.push_str(&" ".repeat(to.saturating_sub(vcol)));
+
+    fill
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_visual_col_expands_tabs() {
+        let buffer = Buffer::new("test".to_string(), "\tab\tc".to_string());
+
+        assert_eq!(buffer.visual_col(0, 0), 0);
+        assert_eq!(buffer.visual_col(0, 1), 8);
+        assert_eq!(buffer.visual_col(0, 3), 10);
+        assert_eq!(buffer.visual_col(0, 4), 16);
+        assert_eq!(buffer.col_at_visual(0, 5), 0);
+        assert_eq!(buffer.col_at_visual(0, 12), 3);
+        assert_eq!(buffer.col_at_visual(0, 100), 5);
+    }
+
+    #[test]
+    fn test_whitespace_fill() {
+        assert_eq!(whitespace_fill(0, 12, 8), "\t    ");
+        assert_eq!(whitespace_fill(3, 16, 8), "\t\t");
+        assert_eq!(whitespace_fill(9, 12, 8), "   ");
+    }
+
+    #[test]
+    fn test_insert_and_delete_keep_line_offsets() {
+        let mut buffer = Buffer::new("test".to_string(), "one\ntwo".to_string());
+
+        buffer.insert(2, "x\ny");
+        assert!(buffer.modified);
+        assert_eq!(buffer.text, "onx\nye\ntwo");
+        assert_eq!(buffer.line_offsets, vec![0, 4, 7]);
+
+        buffer.delete(1..5);
+        assert_eq!(buffer.text, "oe\ntwo");
+        assert_eq!(buffer.line_offsets, vec![0, 3]);
+        assert_eq!(buffer.byte_to_cursor(5), Cursor { x: 2, y: 1 });
+    }
+
+    #[test]
+    fn test_marks_move_with_edits() {
+        let mut buffer = Buffer::new("test".to_string(), "one\ntwo\nthree".to_string());
+        buffer.marks.insert(Mark::Named('a'), 4);
+        buffer.marks.insert(Mark::Named('b'), 9);
+
+        buffer.insert(0, "zero\n");
+        assert_eq!(buffer.mark(Mark::Named('a')), Some(Cursor { x: 0, y: 2 }));
+        assert_eq!(buffer.mark(Mark::Named('.')), Some(Cursor { x: 0, y: 0 }));
+
+        // Deleting the line with `a` on it leaves it where the line was
+        buffer.delete(9..13);
+        assert_eq!(buffer.mark(Mark::Named('a')), Some(Cursor { x: 0, y: 2 }));
+        assert_eq!(buffer.mark(Mark::Named('b')), Some(Cursor { x: 1, y: 2 }));
+        assert_eq!(buffer.text, "zero\none\nthree");
+        assert_eq!(buffer.mark(Mark::Named('c')), None);
+    }
 }
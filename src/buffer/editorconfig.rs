@@ -0,0 +1,300 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::editor::options::BufferOptions;
+
+use super::format::{FileEncoding, FileFormat};
+
+/// One `.editorconfig` file.
+struct EditorConfig {
+    /// `root = true`: files further up are not looked at.
+    root: bool,
+    /// The directory it is in, which its globs are relative to.
+    dir: PathBuf,
+    /// Each `[glob]` section with its properties, in order.
+    sections: Vec<(Regex, Vec<(String, String)>)>,
+}
+
+impl EditorConfig {
+    fn parse(text: &str, dir: PathBuf) -> EditorConfig {
+        let mut config = EditorConfig {
+            root: false,
+            dir,
+            sections: Vec::new(),
+        };
+        // Properties of a section whose glob didn't make a regex are skipped
+        let mut in_section = false;
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(['#', ';']) {
+                continue;
+            }
+            if let Some(glob) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_section = match glob_regex(glob) {
+                    Some(regex) => {
+                        config.sections.push((regex, Vec::new()));
+                        true
+                    }
+                    None => false,
+                };
+                continue;
+            }
+            let Some((key, value)) = line.split_once('=') else {
+                continue;
+            };
+            let key = key.trim().to_ascii_lowercase();
+            let value = value.trim().to_string();
+
+            match config.sections.last_mut() {
+                Some((_, properties)) if in_section => properties.push((key, value)),
+                None if key == "root" => config.root = value.eq_ignore_ascii_case("true"),
+                _ => {}
+            }
+        }
+        config
+    }
+}
+
+/// The properties `.editorconfig` files give the file at `path`. The files
+/// are looked for in its directory and all above it up to one with
+/// `root = true`, nearer ones win over those further up.
+pub fn properties(path: &Path) -> HashMap<String, String> {
+    let Ok(path) = std::path::absolute(path) else {
+        return HashMap::new();
+    };
+
+    let mut configs = Vec::new();
+    for dir in path.ancestors().skip(1) {
+        if let Ok(text) = fs::read_to_string(dir.join(".editorconfig")) {
+            let config = EditorConfig::parse(&text, dir.to_path_buf());
+            let root = config.root;
+            configs.push(config);
+            if root {
+                break;
+            }
+        }
+    }
+
+    let mut properties = HashMap::new();
+    for config in configs.iter().rev() {
+        let Ok(relative) = path.strip_prefix(&config.dir) else {
+            continue;
+        };
+        let relative = relative.to_string_lossy().replace('\\', "/");
+        for (regex, section) in &config.sections {
+            if regex.is_match(&relative) {
+                for (key, value) in section {
+                    properties.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    properties
+}
+
+/// Sets the buffer options the editorconfig `properties` stand for. Values
+/// that aren't understood, and `unset`, leave the option alone.
+/// `end_of_line` and `charset` only count for a `new_file`: writing an
+/// existing file with other ones than it was read with would convert it.
+pub fn apply(properties: &HashMap<String, String>, options: &mut BufferOptions, new_file: bool) {
+    let get = |key: &str| {
+        properties
+            .get(key)
+            .map(|value| value.to_ascii_lowercase())
+            .filter(|value| value != "unset")
+    };
+    let flag = |key: &str| match get(key).as_deref() {
+        Some("true") => Some(true),
+        Some("false") => Some(false),
+        _ => None,
+    };
+
+    match get("indent_style").as_deref() {
+        Some("tab") => options.expandtab = false,
+        Some("space") => options.expandtab = true,
+        _ => {}
+    }
+    let tab_width = get("tab_width").and_then(|width| width.parse().ok());
+    match get("indent_size").as_deref() {
+        Some("tab") => options.shiftwidth = 0,
+        Some(size) => {
+            if let Ok(size) = size.parse::<usize>() {
+                options.shiftwidth = size;
+                // Tabs are as wide as an indent unless said otherwise
+                if tab_width.is_none() && size > 0 {
+                    options.tabstop = size;
+                }
+            }
+        }
+        None => {}
+    }
+    if let Some(width) = tab_width.filter(|&width| width > 0) {
+        options.tabstop = width;
+    }
+
+    if new_file {
+        match get("end_of_line").as_deref() {
+            Some("lf") => options.fileformat = FileFormat::Unix,
+            Some("crlf") => options.fileformat = FileFormat::Dos,
+            Some("cr") => options.fileformat = FileFormat::Mac,
+            _ => {}
+        }
+        if let Some(encoding) =
+            get("charset").and_then(|charset| FileEncoding::parse(&charset).ok())
+        {
+            options.fileencoding = encoding;
+        }
+    }
+    if let Some(fix) = flag("insert_final_newline") {
+        options.fixendofline = fix;
+    }
+    if let Some(trim) = flag("trim_trailing_whitespace") {
+        options.trimwhitespace = trim;
+    }
+}
+
+/// The regex for an editorconfig section glob, matched against the path
+/// relative to the `.editorconfig`. A glob without a `/` matches the file
+/// name in any directory.
+fn glob_regex(glob: &str) -> Option<Regex> {
+    let (anchored, glob) = match glob.strip_prefix('/') {
+        Some(glob) => (true, glob),
+        None => (glob.contains('/'), glob),
+    };
+
+    let mut pattern = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let chars: Vec<char> = glob.chars().collect();
+    let mut braces = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                pattern.push_str(".*");
+                i += 1;
+            }
+            '*' => pattern.push_str("[^/]*"),
+            '?' => pattern.push_str("[^/]"),
+            '[' => {
+                let end = chars[i..].iter().position(|&ch| ch == ']')? + i;
+                let class: String = chars[i + 1..end].iter().collect();
+                let class = match class.strip_prefix('!') {
+                    Some(rest) => format!("^{}", rest),
+                    None => class,
+                };
+                pattern.push_str(&format!("[{}]", class.replace('\\', "\\\\")));
+                i = end;
+            }
+            '{' => {
+                let end = chars[i..]
+                    .iter()
+                    .position(|&ch| ch == '}')
+                    .map(|end| end + i);
+                let inner: String = end
+                    .map(|end| chars[i + 1..end].iter().collect())
+                    .unwrap_or_default();
+                match (end, number_range(&inner)) {
+                    (Some(end), Some(range)) => {
+                        pattern.push_str(&range);
+                        i = end;
+                    }
+                    _ => {
+                        pattern.push_str("(?:");
+                        braces += 1;
+                    }
+                }
+            }
+            ',' if braces > 0 => pattern.push('|'),
+            '}' if braces > 0 => {
+                pattern.push(')');
+                braces -= 1;
+            }
+            '\\' if i + 1 < chars.len() => {
+                i += 1;
+                pattern.push_str(&regex::escape(&chars[i].to_string()));
+            }
+            ch => pattern.push_str(&regex::escape(&ch.to_string())),
+        }
+        i += 1;
+    }
+    pattern.push('$');
+
+    Regex::new(&pattern).ok()
+}
+
+/// `{1..3}` in a glob: any of the whole numbers from one end to the other.
+fn number_range(inner: &str) -> Option<String> {
+    let (from, to) = inner.split_once("..")?;
+    let (from, to): (i64, i64) = (from.parse().ok()?, to.parse().ok()?);
+    let (from, to) = (from.min(to), from.max(to));
+    if to - from > 1000 {
+        return None;
+    }
+    let numbers: Vec<String> = (from..=to).map(|n| n.to_string()).collect();
+    Some(format!("(?:{})", numbers.join("|")))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::temp_dir::TempDir;
+
+    #[test]
+    fn test_globs_match_like_editorconfig() {
+        let matches = |glob: &str, path: &str| glob_regex(glob).unwrap().is_match(path);
+
+        assert!(matches("*", "src/main.rs"));
+        assert!(matches("*.rs", "src/main.rs"));
+        assert!(!matches("*.rs", "src/main.rsx"));
+        assert!(matches("*.{js,ts}", "web/app.ts"));
+        assert!(matches("src/*.rs", "src/lib.rs"));
+        assert!(!matches("src/*.rs", "src/editor/mod.rs"));
+        assert!(matches("/src/**.rs", "src/editor/mod.rs"));
+        assert!(!matches("/src/*.rs", "lib/src/main.rs"));
+        assert!(matches("file[0-9].txt", "file7.txt"));
+        assert!(!matches("file[!0-9].txt", "file7.txt"));
+        assert!(matches("part{1..12}.md", "part10.md"));
+        assert!(!matches("part{1..12}.md", "part13.md"));
+        assert!(matches("Makefile", "sub/dir/Makefile"));
+    }
+
+    #[test]
+    fn test_nearer_files_win_up_to_the_root() {
+        let dir = TempDir::new("editorconfig");
+        fs::create_dir_all(dir.join("project/src")).unwrap();
+        fs::write(dir.join(".editorconfig"), "[*]\nindent_size = 8\n").unwrap();
+        fs::write(
+            dir.join("project/.editorconfig"),
+            "root = true\n\n[*]\nindent_style = space\nindent_size = 4\nend_of_line = lf\n\n\
+             # Makefiles need tabs\n[Makefile]\nindent_style = tab\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.join("project/src/.editorconfig"),
+            "[*.rs]\ntrim_trailing_whitespace = true\ninsert_final_newline = true\nend_of_line = crlf\n",
+        )
+        .unwrap();
+
+        let main = properties(&dir.join("project/src/main.rs"));
+        assert_eq!(main["indent_size"], "4");
+        assert_eq!(main["end_of_line"], "crlf");
+
+        let mut options = BufferOptions::default();
+        apply(&main, &mut options, true);
+        assert!(options.expandtab && !options.fixindent);
+        assert_eq!((options.shiftwidth, options.tabstop), (4, 4));
+        assert_eq!(options.fileformat, FileFormat::Dos);
+        assert!(options.trimwhitespace && options.fixendofline);
+        // An existing file keeps the line endings it has
+        let mut options = BufferOptions::default();
+        apply(&main, &mut options, false);
+        assert_eq!(options.fileformat, FileFormat::Unix);
+
+        let makefile = properties(&dir.join("project/Makefile"));
+        assert_eq!(makefile["indent_style"], "tab");
+    }
+}
@@ -0,0 +1,402 @@
+use std::{
+    fs::{self, File, Metadata, OpenOptions},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::editor::options::BackupCopy;
+
+/// What a file on disk looked like when a buffer was loaded from or written
+/// to it, to notice other programs changing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiskState {
+    pub modified: Option<SystemTime>,
+    pub size: u64,
+    pub hash: u64,
+}
+
+impl DiskState {
+    pub fn new(metadata: &Metadata, contents: &[u8]) -> DiskState {
+        let mut hasher = DefaultHasher::new();
+        contents.hash(&mut hasher);
+        DiskState {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            hash: hasher.finish(),
+        }
+    }
+
+    /// The state of the file at `path` right now, along with its contents.
+    pub fn read(path: &Path) -> io::Result<(DiskState, Vec<u8>)> {
+        let contents = fs::read(path)?;
+        let metadata = fs::metadata(path)?;
+        Ok((DiskState::new(&metadata, &contents), contents))
+    }
+
+    /// This state with the time and size of `metadata`, for a file too large
+    /// to read again just to hash it.
+    pub fn with_metadata(&self, metadata: &Metadata) -> DiskState {
+        DiskState {
+            modified: metadata.modified().ok(),
+            size: metadata.len(),
+            hash: self.hash,
+        }
+    }
+
+    /// Whether the file may have changed, without reading it.
+    pub fn looks_changed(&self, metadata: &Metadata) -> bool {
+        self.modified != metadata.modified().ok() || self.size != metadata.len()
+    }
+}
+
+/// Writes `contents` to `path` so that a crash or a full disk never leaves a
+/// half written file behind.
+///
+/// Normally the text goes into a temp file next to the original, which is
+/// synced and renamed over it. When that would break hard links or lose the
+/// owner, or `backupcopy` says so, the original is copied aside and
+/// overwritten in place instead. With `backup` the old contents are kept as
+/// `name~`. Writing through a symlink changes the file it points to. A
+/// device, FIFO or socket is only ever written to, never replaced.
+pub fn write_file(
+    path: &Path,
+    contents: &[u8],
+    backup: bool,
+    backupcopy: BackupCopy,
+) -> io::Result<()> {
+    let path = resolve_symlinks(path);
+    let metadata = match fs::metadata(&path) {
+        Ok(metadata) => metadata,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            return replace(&path, contents, None, false);
+        }
+        Err(e) => return Err(e),
+    };
+    if metadata.is_dir() {
+        return Err(io::Error::other(format!(
+            "{} is a directory",
+            path.display()
+        )));
+    }
+    if !metadata.file_type().is_file() {
+        // There are no old contents to keep or sync, and a rename would put
+        // a plain file where the device was
+        let mut file = OpenOptions::new().write(true).open(&path)?;
+        return file.write_all(contents);
+    }
+
+    let in_place = match backupcopy {
+        BackupCopy::Yes => true,
+        BackupCopy::No => false,
+        BackupCopy::Auto => has_hard_links(&metadata),
+    };
+    if !in_place {
+        if backup {
+            fs::copy(&path, backup_path(&path))?;
+        }
+        let auto = backupcopy == BackupCopy::Auto;
+        match replace(&path, contents, Some(&metadata), auto) {
+            Ok(()) => return Ok(()),
+            // A directory we can't create files in, or an owner we can't give
+            // the new file: the original has to be written in place
+            Err(e) if auto && e.kind() == io::ErrorKind::PermissionDenied => {}
+            Err(e) => return Err(e),
+        }
+    }
+
+    overwrite(&path, contents, &metadata, backup)
+}
+
+/// Whether the file at `path` can be written to, as far as permissions go.
+/// The file isn't opened for it, so a FIFO or device is left alone; those
+/// only say whether they take the text when written to.
+pub fn is_writable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.file_type().is_file() => may_write(path, &metadata),
+        _ => true,
+    }
+}
+
+#[cfg(unix)]
+fn may_write(path: &Path, _metadata: &Metadata) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return true;
+    };
+    // SAFETY: `path` is a valid NUL terminated string for the whole call
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(not(unix))]
+fn may_write(_path: &Path, metadata: &Metadata) -> bool {
+    !metadata.permissions().readonly()
+}
+
+/// `path` made absolute with symlinks and `..` resolved, to tell whether two
+/// names are the same file. One that doesn't exist yet is only made absolute.
+pub fn canonical_path(path: &Path) -> PathBuf {
+    fs::canonicalize(path)
+        .or_else(|_| std::path::absolute(path))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// Where the backup of `path` goes, `name~` next to it.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push("~");
+    path.with_file_name(name)
+}
+
+/// Follows `path` through any symlinks to the file they end up at, which
+/// may not exist yet.
+fn resolve_symlinks(path: &Path) -> PathBuf {
+    let mut path = path.to_path_buf();
+    // The OS gives up on link loops after 40 links as well
+    for _ in 0..40 {
+        match fs::read_link(&path) {
+            Ok(target) => {
+                path = match path.parent() {
+                    Some(dir) => dir.join(target),
+                    None => target,
+                }
+            }
+            Err(_) => break,
+        }
+    }
+    path
+}
+
+/// Writes a temp file and renames it over `path`, giving it the mode and
+/// owner in `metadata`. With `keep_owner` failing to set the owner is an error.
+fn replace(
+    path: &Path,
+    contents: &[u8],
+    metadata: Option<&Metadata>,
+    keep_owner: bool,
+) -> io::Result<()> {
+    let (temp_path, mut file) = create_temp(path, metadata)?;
+    let written = (|| {
+        file.write_all(contents)?;
+        if let Some(metadata) = metadata {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+            copy_owner(&temp_path, metadata, keep_owner)?;
+        }
+        file.sync_all()?;
+        fs::rename(&temp_path, path)
+    })();
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written?;
+
+    // Make the rename itself stick, the file is already complete either way
+    if let Some(dir) = parent_dir(path)
+        && let Ok(dir) = File::open(dir)
+    {
+        let _ = dir.sync_all();
+    }
+    Ok(())
+}
+
+/// Writes over `path` itself, with a copy of the old contents kept aside
+/// until the write went through. With `keep_backup` the copy is `name~` and
+/// stays, otherwise it gets a temp name so no `name~` of the user's is lost.
+/// When writing fails after the file was emptied, the error says where the
+/// copy is.
+fn overwrite(
+    path: &Path,
+    contents: &[u8],
+    metadata: &Metadata,
+    keep_backup: bool,
+) -> io::Result<()> {
+    let copy = if keep_backup {
+        let backup = backup_path(path);
+        fs::copy(path, &backup)?;
+        backup
+    } else {
+        let (temp_path, mut file) = create_temp(path, Some(metadata))?;
+        let copied = File::open(path).and_then(|mut original| io::copy(&mut original, &mut file));
+        if let Err(e) = copied {
+            let _ = fs::remove_file(&temp_path);
+            return Err(e);
+        }
+        temp_path
+    };
+
+    let mut file = match OpenOptions::new().write(true).truncate(true).open(path) {
+        Ok(file) => file,
+        // The original is untouched, the copy isn't needed
+        Err(e) => {
+            if !keep_backup {
+                let _ = fs::remove_file(&copy);
+            }
+            return Err(e);
+        }
+    };
+    if let Err(e) = file.write_all(contents).and_then(|()| file.sync_all()) {
+        return Err(io::Error::new(
+            e.kind(),
+            format!("{}, the old contents are in {}", e, copy.display()),
+        ));
+    }
+
+    if !keep_backup {
+        fs::remove_file(&copy)?;
+    }
+    Ok(())
+}
+
+/// Creates a new file next to `path` to write into before renaming. It
+/// starts out with the mode in `metadata`, so the contents of a private file
+/// are never readable by others in the meantime.
+fn create_temp(path: &Path, metadata: Option<&Metadata>) -> io::Result<(PathBuf, File)> {
+    let dir = parent_dir(path).unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+
+    for attempt in 0..100 {
+        let temp_path = dir.join(format!(".{}.{}-{}.tmp", name, std::process::id(), attempt));
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        if let Some(metadata) = metadata {
+            set_create_mode(&mut options, metadata);
+        }
+        match options.open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("no free temp file name next to {}", path.display()),
+    ))
+}
+
+fn parent_dir(path: &Path) -> Option<&Path> {
+    path.parent().filter(|dir| !dir.as_os_str().is_empty())
+}
+
+#[cfg(unix)]
+fn has_hard_links(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_hard_links(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn set_create_mode(options: &mut OpenOptions, metadata: &Metadata) {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    options.mode(metadata.permissions().mode() & 0o777);
+}
+
+#[cfg(not(unix))]
+fn set_create_mode(_options: &mut OpenOptions, _metadata: &Metadata) {}
+
+/// Gives the file at `path` the owner and group in `metadata`.
+#[cfg(unix)]
+fn copy_owner(path: &Path, metadata: &Metadata, required: bool) -> io::Result<()> {
+    use std::os::unix::fs::{MetadataExt, chown};
+
+    let created = fs::metadata(path)?;
+    let uid = (created.uid() != metadata.uid()).then_some(metadata.uid());
+    let gid = (created.gid() != metadata.gid()).then_some(metadata.gid());
+    if uid.is_none() && gid.is_none() {
+        return Ok(());
+    }
+
+    match chown(path, uid, gid) {
+        Err(_) if !required => Ok(()),
+        result => result,
+    }
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_path: &Path, _metadata: &Metadata, _required: bool) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::temp_dir::TempDir;
+
+    #[test]
+    fn test_write_replaces_contents_and_leaves_no_temp_files() {
+        let dir = TempDir::new("write");
+        let path = dir.join("file.txt");
+        write_file(&path, b"one", false, BackupCopy::Auto).unwrap();
+        write_file(&path, b"two", false, BackupCopy::Auto).unwrap();
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "two");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        write_file(&path, b"three", true, BackupCopy::Auto).unwrap();
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "two");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_write_keeps_mode_links_and_symlinks() {
+        use std::os::unix::fs::{FileTypeExt, PermissionsExt, symlink};
+
+        let dir = TempDir::new("keep");
+        let path = dir.join("script.sh");
+        fs::write(&path, "old").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o750)).unwrap();
+
+        write_file(&path, b"new", false, BackupCopy::No).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o750);
+
+        // The link stays a link and the file it points to gets the text
+        let link = dir.join("link.sh");
+        symlink("script.sh", &link).unwrap();
+        write_file(&link, b"linked", false, BackupCopy::Auto).unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().is_symlink());
+        assert_eq!(fs::read_to_string(&path).unwrap(), "linked");
+
+        // Both names of a hard linked file see the change
+        let hard = dir.join("hard.sh");
+        fs::hard_link(&path, &hard).unwrap();
+        write_file(&path, b"shared", false, BackupCopy::Auto).unwrap();
+        assert_eq!(fs::read_to_string(&hard).unwrap(), "shared");
+        assert!(!backup_path(&path).exists());
+
+        // A FIFO stays one, the text goes to whoever reads it
+        let fifo = dir.join("fifo");
+        let made = std::process::Command::new("mkfifo").arg(&fifo).status();
+        assert!(made.unwrap().success());
+        // Finds out without opening it, which would wait for a reader
+        assert!(is_writable(&fifo));
+        let reader = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || fs::read(fifo).unwrap())
+        };
+        write_file(&fifo, b"piped", false, BackupCopy::Auto).unwrap();
+        assert_eq!(reader.join().unwrap(), b"piped");
+        assert!(fs::symlink_metadata(&fifo).unwrap().file_type().is_fifo());
+
+        // Writing in place leaves a `name~` the user made alone
+        fs::write(backup_path(&path), "mine").unwrap();
+        write_file(&path, b"in place", false, BackupCopy::Yes).unwrap();
+        assert_eq!(fs::read_to_string(backup_path(&path)).unwrap(), "mine");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "in place");
+
+        // A private file stays private
+        let secret = dir.join("secret");
+        fs::write(&secret, "old").unwrap();
+        fs::set_permissions(&secret, fs::Permissions::from_mode(0o600)).unwrap();
+        write_file(&secret, b"new", false, BackupCopy::No).unwrap();
+        let mode = fs::metadata(&secret).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}
@@ -0,0 +1,333 @@
+use std::borrow::Cow;
+
+use anyhow::bail;
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+/// How lines end in the file, the buffer itself always uses `\n`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileFormat {
+    /// `\n`
+    #[default]
+    Unix,
+    /// `\r\n`
+    Dos,
+    /// `\r`
+    Mac,
+}
+
+impl FileFormat {
+    /// The format of `text`. Only a file where every line ends in `\r\n` is
+    /// dos, and only one without `\n` whose last line ends in `\r` is mac.
+    /// Anything else stays unix so no `\r` gets lost or added on writing.
+    pub fn detect(text: &str) -> FileFormat {
+        let newlines = text.matches('\n').count();
+        if newlines == 0 {
+            if text.ends_with('\r') {
+                FileFormat::Mac
+            } else {
+                FileFormat::Unix
+            }
+        } else if text.matches("\r\n").count() == newlines {
+            FileFormat::Dos
+        } else {
+            FileFormat::Unix
+        }
+    }
+
+    pub fn line_ending(self) -> &'static str {
+        match self {
+            FileFormat::Unix => "\n",
+            FileFormat::Dos => "\r\n",
+            FileFormat::Mac => "\r",
+        }
+    }
+
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "unix" => FileFormat::Unix,
+            "dos" => FileFormat::Dos,
+            "mac" => FileFormat::Mac,
+            _ => bail!("Invalid fileformat: {}", value),
+        })
+    }
+}
+
+impl std::fmt::Display for FileFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileFormat::Unix => "unix",
+            FileFormat::Dos => "dos",
+            FileFormat::Mac => "mac",
+        })
+    }
+}
+
+/// How the text of the file is stored as bytes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FileEncoding {
+    #[default]
+    Utf8,
+    /// UTF-8 starting with a byte order mark.
+    Utf8Bom,
+    /// UTF-16 little endian, written with a byte order mark.
+    Utf16Le,
+    /// UTF-16 big endian, written with a byte order mark.
+    Utf16Be,
+    /// One byte per char, what files that aren't valid UTF-8 are read as.
+    Latin1,
+}
+
+impl FileEncoding {
+    pub fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => FileEncoding::Utf8,
+            "utf-8-bom" | "utf8-bom" => FileEncoding::Utf8Bom,
+            "utf-16" | "utf-16le" | "utf16le" => FileEncoding::Utf16Le,
+            "utf-16be" | "utf16be" => FileEncoding::Utf16Be,
+            "latin1" | "latin-1" | "iso-8859-1" => FileEncoding::Latin1,
+            _ => bail!("Invalid fileencoding: {}", value),
+        })
+    }
+
+    /// The bytes for `text`, failing on chars the encoding has no room for.
+    pub fn encode(self, text: &str) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            FileEncoding::Utf8 => text.as_bytes().to_vec(),
+            FileEncoding::Utf8Bom => [UTF8_BOM, text.as_bytes()].concat(),
+            FileEncoding::Utf16Le => {
+                let units = text.encode_utf16().flat_map(u16::to_le_bytes);
+                UTF16LE_BOM.iter().copied().chain(units).collect()
+            }
+            FileEncoding::Utf16Be => {
+                let units = text.encode_utf16().flat_map(u16::to_be_bytes);
+                UTF16BE_BOM.iter().copied().chain(units).collect()
+            }
+            FileEncoding::Latin1 => text
+                .chars()
+                .map(|ch| match u8::try_from(ch) {
+                    Ok(byte) => Ok(byte),
+                    Err(_) => bail!("Can't write {:?} as latin1", ch),
+                })
+                .collect::<anyhow::Result<_>>()?,
+        })
+    }
+}
+
+impl std::fmt::Display for FileEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            FileEncoding::Utf8 => "utf-8",
+            FileEncoding::Utf8Bom => "utf-8-bom",
+            FileEncoding::Utf16Le => "utf-16le",
+            FileEncoding::Utf16Be => "utf-16be",
+            FileEncoding::Latin1 => "latin1",
+        })
+    }
+}
+
+/// The contents of a file as buffer text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Decoded {
+    /// The text with every line ending turned into `\n`.
+    pub text: String,
+    pub format: FileFormat,
+    pub encoding: FileEncoding,
+    /// The file has NUL bytes, so it is most likely not text at all.
+    pub binary: bool,
+    /// Some bytes weren't valid in the encoding and became U+FFFD, so
+    /// writing the text back would not give the file again.
+    pub lossy: bool,
+}
+
+/// Reads file contents in whichever encoding they are in. A byte order mark
+/// says which, otherwise anything that isn't valid UTF-8 is Latin-1, so
+/// every file can be opened.
+pub fn decode(bytes: &[u8]) -> Decoded {
+    let (text, encoding, lossy) = if let Some(rest) = bytes.strip_prefix(UTF8_BOM) {
+        let text = String::from_utf8_lossy(rest);
+        let lossy = matches!(text, Cow::Owned(_));
+        (text.into_owned(), FileEncoding::Utf8Bom, lossy)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16LE_BOM) {
+        let units = rest.chunks(2).map(|pair| match pair {
+            [low, high] => Some(u16::from_le_bytes([*low, *high])),
+            _ => None,
+        });
+        let (text, lossy) = decode_utf16(units);
+        (text, FileEncoding::Utf16Le, lossy)
+    } else if let Some(rest) = bytes.strip_prefix(UTF16BE_BOM) {
+        let units = rest.chunks(2).map(|pair| match pair {
+            [high, low] => Some(u16::from_be_bytes([*high, *low])),
+            _ => None,
+        });
+        let (text, lossy) = decode_utf16(units);
+        (text, FileEncoding::Utf16Be, lossy)
+    } else {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => (text.to_string(), FileEncoding::Utf8, false),
+            Err(_) => (
+                bytes.iter().map(|&byte| char::from(byte)).collect(),
+                FileEncoding::Latin1,
+                false,
+            ),
+        }
+    };
+
+    finish(text, encoding, lossy)
+}
+
+/// Like [`decode`], but takes the bytes so a UTF-8 file, by far the most
+/// common kind, becomes the text without being copied. For large files.
+pub fn decode_owned(mut bytes: Vec<u8>) -> Decoded {
+    if [UTF16LE_BOM, UTF16BE_BOM]
+        .iter()
+        .any(|bom| bytes.starts_with(bom))
+    {
+        return decode(&bytes);
+    }
+    let bom = bytes.starts_with(UTF8_BOM);
+    if bom {
+        bytes.drain(..UTF8_BOM.len());
+    }
+    match (String::from_utf8(bytes), bom) {
+        (Ok(text), false) => finish(text, FileEncoding::Utf8, false),
+        (Ok(text), true) => finish(text, FileEncoding::Utf8Bom, false),
+        (Err(e), false) => decode(e.as_bytes()),
+        (Err(e), true) => decode(&[UTF8_BOM, e.as_bytes()].concat()),
+    }
+}
+
+/// Turns the line endings of decoded `text` into `\n`. This happens in
+/// place, a large file never needs room for a second copy.
+fn finish(text: String, encoding: FileEncoding, lossy: bool) -> Decoded {
+    let format = FileFormat::detect(&text);
+    let text = match format {
+        FileFormat::Unix => text,
+        format => unix_line_endings(text, format),
+    };
+    let binary = text.contains('\0');
+
+    Decoded {
+        text,
+        format,
+        encoding,
+        binary,
+        lossy,
+    }
+}
+
+fn unix_line_endings(text: String, format: FileFormat) -> String {
+    let mut bytes = text.into_bytes();
+    match format {
+        FileFormat::Unix => {}
+        FileFormat::Dos => {
+            let mut kept = 0;
+            for i in 0..bytes.len() {
+                if bytes[i] == b'\r' && bytes.get(i + 1) == Some(&b'\n') {
+                    continue;
+                }
+                bytes[kept] = bytes[i];
+                kept += 1;
+            }
+            bytes.truncate(kept);
+        }
+        FileFormat::Mac => {
+            for byte in bytes.iter_mut().filter(|byte| **byte == b'\r') {
+                *byte = b'\n';
+            }
+        }
+    }
+    // Only ASCII line endings changed, the rest is as valid as it was
+    String::from_utf8(bytes).expect("line endings keep UTF-8 valid")
+}
+
+/// UTF-16 `units`, `None` for a byte left over at the end, and whether any
+/// of them weren't valid.
+fn decode_utf16(units: impl Iterator<Item = Option<u16>>) -> (String, bool) {
+    let mut lossy = false;
+    let mut odd = false;
+    let units = units.map_while(|unit| {
+        odd = unit.is_none();
+        unit
+    });
+    let text = char::decode_utf16(units)
+        .map(|ch| {
+            ch.unwrap_or_else(|_| {
+                lossy = true;
+                char::REPLACEMENT_CHARACTER
+            })
+        })
+        .collect::<String>();
+    if odd {
+        return (text + "\u{FFFD}", true);
+    }
+    (text, lossy)
+}
+
+/// Buffer text as the bytes to write to a file with `format` and `encoding`.
+pub fn encode(text: &str, format: FileFormat, encoding: FileEncoding) -> anyhow::Result<Vec<u8>> {
+    match format {
+        FileFormat::Unix => encoding.encode(text),
+        format => encoding.encode(&text.replace('\n', format.line_ending())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_endings_are_detected_and_restored() {
+        let decoded = decode(b"one\r\ntwo\r\n");
+        assert_eq!(decoded.text, "one\ntwo\n");
+        assert_eq!(decoded.format, FileFormat::Dos);
+        let bytes = encode(&decoded.text, decoded.format, decoded.encoding).unwrap();
+        assert_eq!(bytes, b"one\r\ntwo\r\n");
+
+        let mac = decode_owned(b"one\rtwo\r".to_vec());
+        assert_eq!(
+            (mac.text.as_str(), mac.format),
+            ("one\ntwo\n", FileFormat::Mac)
+        );
+        // A CR within a line isn't a line ending
+        let one_line = decode(b"one\rtwo");
+        assert_eq!(
+            (one_line.text.as_str(), one_line.format),
+            ("one\rtwo", FileFormat::Unix)
+        );
+        let mixed = decode(b"one\r\ntwo\n");
+        assert_eq!(mixed.format, FileFormat::Unix);
+        assert_eq!(mixed.text, "one\r\ntwo\n");
+    }
+
+    #[test]
+    fn test_encodings_round_trip() {
+        for (bytes, encoding) in [
+            (&b"\xEF\xBB\xBFh\xC3\xA9"[..], FileEncoding::Utf8Bom),
+            (&b"\xFF\xFEh\x00\xE9\x00"[..], FileEncoding::Utf16Le),
+            (&b"\xFE\xFF\x00h\x00\xE9"[..], FileEncoding::Utf16Be),
+            (&b"h\xE9"[..], FileEncoding::Latin1),
+        ] {
+            let decoded = decode(bytes);
+            assert_eq!(decoded.text, "hé");
+            assert_eq!(decoded.encoding, encoding);
+            assert_eq!(encoding.encode(&decoded.text).unwrap(), bytes);
+            let owned = decode_owned(bytes.to_vec());
+            assert_eq!((owned.text, owned.encoding), (decoded.text, encoding));
+        }
+
+        assert!(decode(b"ELF\0\x01").binary);
+        for bytes in [
+            &b"\xEF\xBB\xBFh\xFF"[..],
+            b"\xFF\xFEh\x00\xE9",
+            b"\xFF\xFE\x00\xD8h\x00",
+        ] {
+            let decoded = decode(bytes);
+            assert!(decoded.lossy && decoded.text.contains('\u{FFFD}'));
+        }
+        assert!(!decode(b"h\xE9").lossy);
+        assert!(FileEncoding::Latin1.encode("日本").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::{
+    fs::File,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+        mpsc::{self, Receiver, TryRecvError},
+    },
+    thread,
+};
+
+use super::{
+    buffer::index_lines,
+    file::DiskState,
+    format::{self, FileEncoding, FileFormat},
+};
+
+/// Bytes read at a time, the progress moves on after each.
+const CHUNK: usize = 4 << 20;
+
+/// A large file being read and indexed on a thread of its own, so the editor
+/// keeps responding in the meantime. The text ends up in memory whole, but
+/// only once: the bytes read become the buffer text in place.
+pub struct Loading {
+    result: Receiver<io::Result<Loaded>>,
+    /// Bytes read so far.
+    read: Arc<AtomicU64>,
+    size: u64,
+    /// Line and column from the command line, for once the lines are there.
+    pub position: Option<(usize, Option<usize>)>,
+}
+
+/// A large file as read from disk.
+pub struct Loaded {
+    pub text: String,
+    pub line_offsets: Vec<usize>,
+    pub format: FileFormat,
+    pub encoding: FileEncoding,
+    pub binary: bool,
+    pub lossy: bool,
+    pub disk: DiskState,
+}
+
+impl Loading {
+    /// Starts reading the file at `path`, `size` bytes long as far as is known.
+    pub fn start(path: PathBuf, size: u64) -> Loading {
+        let (sender, result) = mpsc::channel();
+        let read = Arc::new(AtomicU64::new(0));
+        let progress = Arc::clone(&read);
+        thread::spawn(move || {
+            // Nobody is waiting any more if the buffer was closed
+            let _ = sender.send(load(&path, size, &progress));
+        });
+
+        Loading {
+            result,
+            read,
+            size,
+            position: None,
+        }
+    }
+
+    /// How far along reading is, in percent.
+    pub fn percent(&self) -> u64 {
+        let read = self.read.load(Ordering::Relaxed);
+        (read * 100).checked_div(self.size).unwrap_or(100).min(100)
+    }
+
+    /// The file once it is read, `None` while it still is being.
+    pub fn try_finish(&self) -> Option<io::Result<Loaded>> {
+        match self.result.try_recv() {
+            Ok(result) => Some(result),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(io::Error::other("reading stopped"))),
+        }
+    }
+
+    /// Blocks until the file is read, for when there is no one to keep responding to.
+    pub fn wait(&self) -> io::Result<Loaded> {
+        self.result
+            .recv()
+            .unwrap_or_else(|_| Err(io::Error::other("reading stopped")))
+    }
+}
+
+fn load(path: &Path, size: u64, read: &AtomicU64) -> io::Result<Loaded> {
+    let mut file = File::open(path)?;
+    let mut bytes = Vec::with_capacity(size as usize + CHUNK);
+    loop {
+        let start = bytes.len();
+        bytes.resize(start + CHUNK, 0);
+        let count = file.read(&mut bytes[start..])?;
+        bytes.truncate(start + count);
+        if count == 0 {
+            break;
+        }
+        read.fetch_add(count as u64, Ordering::Relaxed);
+    }
+
+    let disk = DiskState::new(&file.metadata()?, &bytes);
+    let decoded = format::decode_owned(bytes);
+    let line_offsets = index_lines(&decoded.text);
+    Ok(Loaded {
+        text: decoded.text,
+        line_offsets,
+        format: decoded.format,
+        encoding: decoded.encoding,
+        binary: decoded.binary,
+        lossy: decoded.lossy,
+        disk,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::temp_dir::TempDir;
+
+    #[test]
+    fn test_loading_reads_and_indexes_the_file() {
+        let dir = TempDir::new("large");
+        let path = dir.join("big.txt");
+        std::fs::write(&path, "one\r\ntwo\r\n").unwrap();
+
+        let loading = Loading::start(path.clone(), 10);
+        let loaded = loading.wait().unwrap();
+        assert_eq!(loaded.text, "one\ntwo\n");
+        assert_eq!(loaded.line_offsets, vec![0, 4, 8]);
+        assert_eq!(loaded.format, FileFormat::Dos);
+        assert_eq!(loading.percent(), 100);
+    }
+}
@@ -1,3 +1,12 @@
+#[allow(clippy::module_inception)]
 pub mod buffer;
+pub mod editorconfig;
+pub mod file;
+pub mod format;
+pub mod large;
 pub mod render_buffer;
 pub mod render_cell;
+pub mod swap;
+#[cfg(test)]
+pub(crate) mod temp_dir;
+pub mod watcher;
@@ -35,17 +35,27 @@ impl RenderBuffer {
         }
     }
 
+    /// Fills the buffer with cells that never match a real one, so diffing
+    /// against it repaints the whole screen.
+    pub fn invalidate(&mut self) {
+        let invalid = RenderCell {
+            ch: '\0',
+            ..Default::default()
+        };
+        for row in &mut self.buffer {
+            row.fill(invalid.clone());
+        }
+    }
+
     pub fn get_cell(&self, x: usize, y: usize) -> Option<&RenderCell> {
         self.buffer.get(y).and_then(|row| row.get(x))
     }
 
     pub fn set_cell(&mut self, x: usize, y: usize, cell: RenderCell) {
-        if y < self.buffer.len() && x < self.width {
-            if let Some(row) = self.buffer.get_mut(y) {
-                if x < row.len() {
-                    row[x] = cell;
-                }
-            }
+        if x < self.width
+            && let Some(slot) = self.buffer.get_mut(y).and_then(|row| row.get_mut(x))
+        {
+            *slot = cell;
         }
     }
 }
@@ -0,0 +1,181 @@
+use std::{
+    env, fs, io,
+    path::{Path, PathBuf},
+};
+
+use crate::editor::options::BackupCopy;
+
+use super::file::write_file;
+
+/// First line of every swap file, followed by the pid and the path.
+const HEADER: &str = "violet swap 1";
+
+/// Unsaved text of a buffer as last written to its swap file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Swap {
+    /// Process of the editor that wrote it.
+    pub pid: u32,
+    /// File the text belongs to.
+    pub path: PathBuf,
+    pub text: String,
+}
+
+impl Swap {
+    pub fn read(swap_file: &Path) -> io::Result<Swap> {
+        let contents = fs::read_to_string(swap_file)?;
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} is not a swap file", swap_file.display()),
+            )
+        };
+
+        let mut parts = contents.splitn(4, '\n');
+        if parts.next() != Some(HEADER) {
+            return Err(invalid());
+        }
+        let pid = parts
+            .next()
+            .and_then(|pid| pid.parse().ok())
+            .ok_or_else(invalid)?;
+        let path = PathBuf::from(parts.next().ok_or_else(invalid)?);
+        let text = parts.next().unwrap_or_default().to_string();
+
+        Ok(Swap { pid, path, text })
+    }
+
+    /// Writes the swap to `swap_file`. It holds unsaved text of files that
+    /// may be private, so only the user can read it.
+    pub fn write(&self, swap_file: &Path) -> io::Result<()> {
+        if let Some(dir) = swap_file.parent() {
+            create_private_dir(dir)?;
+        }
+        let created = !swap_file.exists();
+        make_private(swap_file)?;
+        let contents = format!(
+            "{}\n{}\n{}\n{}",
+            HEADER,
+            self.pid,
+            self.path.display(),
+            self.text
+        );
+        let written = write_file(swap_file, contents.as_bytes(), false, BackupCopy::No);
+        // An empty swap file would only be in the way
+        if written.is_err() && created {
+            let _ = fs::remove_file(swap_file);
+        }
+        written
+    }
+
+    /// The editor that wrote it is still running, so the swap isn't orphaned.
+    pub fn is_running(&self) -> bool {
+        self.pid == std::process::id() || Path::new("/proc").join(self.pid.to_string()).exists()
+    }
+}
+
+#[cfg(unix)]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    use std::os::unix::fs::DirBuilderExt;
+
+    fs::DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+}
+
+#[cfg(not(unix))]
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    fs::create_dir_all(dir)
+}
+
+/// Creates `swap_file` readable by the user alone, or makes it so. Writing
+/// keeps the mode of the file it replaces.
+#[cfg(unix)]
+fn make_private(swap_file: &Path) -> io::Result<()> {
+    use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+    fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .open(swap_file)?;
+    fs::set_permissions(swap_file, fs::Permissions::from_mode(0o600))
+}
+
+#[cfg(not(unix))]
+fn make_private(_swap_file: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+/// Where swap files go when the `directory` option is empty:
+/// `$XDG_STATE_HOME/violet/swap`, or `~/.local/state/violet/swap`.
+pub fn default_dir() -> PathBuf {
+    let state = env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(env::temp_dir);
+    state.join("violet").join("swap")
+}
+
+/// The swap file in `dir` for the file at `path`. The whole absolute path
+/// goes into the name, with `%` for each `/`, so files with the same name
+/// in different directories don't share one.
+pub fn swap_path(dir: &Path, path: &Path) -> PathBuf {
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let name = absolute.to_string_lossy().replace(['/', '\\'], "%");
+    dir.join(format!("{}.swp", name))
+}
+
+/// All swap files in `dir` that could be read.
+pub fn list(dir: &Path) -> Vec<(PathBuf, Swap)> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut swaps: Vec<(PathBuf, Swap)> = entries
+        .filter_map(|entry| {
+            let swap_file = entry.ok()?.path();
+            let swap = Swap::read(&swap_file).ok()?;
+            Some((swap_file, swap))
+        })
+        .collect();
+    swaps.sort_by(|a, b| a.1.path.cmp(&b.1.path));
+    swaps
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::temp_dir::TempDir;
+
+    #[test]
+    fn test_swap_round_trips_and_is_named_after_the_path() {
+        let temp = TempDir::new("swap");
+        // Not there yet, writing creates it
+        let dir = temp.join("swap");
+
+        let swap_file = swap_path(&dir, Path::new("/home/me/src/main.rs"));
+        assert_eq!(swap_file, dir.join("%home%me%src%main.rs.swp"));
+
+        let swap = Swap {
+            pid: 42,
+            path: PathBuf::from("/home/me/src/main.rs"),
+            text: "fn main() {}\n\nline\n".to_string(),
+        };
+        swap.write(&swap_file).unwrap();
+        assert_eq!(Swap::read(&swap_file).unwrap(), swap);
+        assert_eq!(list(&dir), vec![(swap_file.clone(), swap)]);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&swap_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        fs::write(dir.join("junk.swp"), "not a swap").unwrap();
+        assert_eq!(list(&dir).len(), 1);
+    }
+}
@@ -0,0 +1,53 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+};
+
+use notify::{Event, RecommendedWatcher, RecursiveMode, Watcher};
+
+/// Tells which open files other programs touched, through inotify on Linux
+/// and whatever the platform offers elsewhere.
+pub struct FileWatcher {
+    watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Directories being watched. Files are watched through their directory,
+    /// tools like `git checkout` replace a file rather than write to it.
+    dirs: HashSet<PathBuf>,
+}
+
+impl FileWatcher {
+    pub fn new() -> notify::Result<FileWatcher> {
+        let (sender, events) = mpsc::channel();
+        let watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })?;
+
+        Ok(FileWatcher {
+            watcher,
+            events,
+            dirs: HashSet::new(),
+        })
+    }
+
+    pub fn watch(&mut self, path: &Path) -> notify::Result<()> {
+        let path = std::path::absolute(path)?;
+        let Some(dir) = path.parent() else {
+            return Ok(());
+        };
+        if self.dirs.insert(dir.to_path_buf()) {
+            self.watcher.watch(dir, RecursiveMode::NonRecursive)?;
+        }
+        Ok(())
+    }
+
+    /// Absolute paths of the files that changed since the last call.
+    pub fn changed(&self) -> HashSet<PathBuf> {
+        self.events
+            .try_iter()
+            .filter_map(Result::ok)
+            .filter(|event| !event.kind.is_access())
+            .flat_map(|event| event.paths)
+            .collect()
+    }
+}
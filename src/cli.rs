@@ -0,0 +1,201 @@
+use std::path::{Path, PathBuf};
+
+pub const HELP: &str = "\
+Usage: violet [options] [file ...]
+
+  file                 open a file, several files open in buffers of their own
+  file:line[:col]      open a file at a line and column, like compiler output
+  -                    read the text from stdin
+  +N                   start at line N of the first file, `+` for the last line
+  +/pattern            start at the first match of pattern
+  +command             run an ex command once the files are open
+  -c command           same as +command, can be given several times
+  -s keys              type the keys in a file once the files are open, written
+                       like `dwjp` or `ihello<Esc>`
+  --headless           run the commands and keys without a terminal and exit,
+                       with status 1 if any of them failed
+  -R                   open files read-only
+  -r [file]            list swap files, or recover file from its swap file
+  -u config            read config instead of the default, NONE for no config
+  --clean              don't read any config
+  --version            print the version and exit
+  -h, --help           print this help and exit
+";
+
+/// A file named on the command line, with the position from `file:line:col`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileArg {
+    /// The file, `-` for stdin.
+    pub path: String,
+    pub line: Option<usize>,
+    pub col: Option<usize>,
+}
+
+impl FileArg {
+    /// Splits a trailing `:line` or `:line:col` off `arg`, unless a file by
+    /// the whole name exists.
+    pub fn parse(arg: &str) -> FileArg {
+        let whole = FileArg {
+            path: arg.to_string(),
+            line: None,
+            col: None,
+        };
+        if arg == "-" || Path::new(arg).exists() {
+            return whole;
+        }
+
+        // Compiler output often has a colon after the column as well
+        let trimmed = arg.strip_suffix(':').unwrap_or(arg);
+        let mut parts = trimmed.rsplitn(3, ':');
+        let last = parts.next().and_then(|part| part.parse::<usize>().ok());
+        let middle = parts.next();
+        let rest = parts.next();
+
+        match (last, middle, rest) {
+            (Some(col), Some(line), Some(path))
+                if !path.is_empty() && line.parse::<usize>().is_ok() =>
+            {
+                FileArg {
+                    path: path.to_string(),
+                    line: line.parse().ok(),
+                    col: Some(col),
+                }
+            }
+            (Some(line), Some(middle), rest) => {
+                let path = match rest {
+                    Some(rest) => format!("{}:{}", rest, middle),
+                    None => middle.to_string(),
+                };
+                if path.is_empty() {
+                    return whole;
+                }
+                FileArg {
+                    path,
+                    line: Some(line),
+                    col: None,
+                }
+            }
+            _ => whole,
+        }
+    }
+}
+
+/// What the command line asked for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Args {
+    pub files: Vec<FileArg>,
+    /// Ex commands from `+` and `-c` in order, like `42` for `+42` or
+    /// `/fn main` for `+/fn main`.
+    pub commands: Vec<String>,
+    /// File with keys to type after the commands.
+    pub script: Option<PathBuf>,
+    pub headless: bool,
+    pub read_only: bool,
+    pub recover: bool,
+    /// Config to read instead of the default one.
+    pub config: Option<PathBuf>,
+    /// No config at all.
+    pub clean: bool,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Args {
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        let mut only_files = false;
+
+        while let Some(arg) = args.next() {
+            if only_files {
+                parsed.files.push(FileArg::parse(&arg));
+                continue;
+            }
+
+            match arg.as_str() {
+                "--" => only_files = true,
+                "-h" | "--help" => parsed.help = true,
+                "--version" => parsed.version = true,
+                "-R" => parsed.read_only = true,
+                "-r" => parsed.recover = true,
+                "--clean" => parsed.clean = true,
+                "--headless" => parsed.headless = true,
+                "-c" => match args.next() {
+                    Some(command) => parsed.commands.push(command),
+                    None => return Err("-c needs a command".to_string()),
+                },
+                "-s" => match args.next() {
+                    Some(script) => parsed.script = Some(PathBuf::from(script)),
+                    None => return Err("-s needs a file of keys".to_string()),
+                },
+                "-u" => match args.next().as_deref() {
+                    Some("NONE") => parsed.clean = true,
+                    Some(config) => parsed.config = Some(PathBuf::from(config)),
+                    None => return Err("-u needs a config file".to_string()),
+                },
+                "+" => parsed.commands.push("$".to_string()),
+                "-" => parsed.files.push(FileArg::parse(&arg)),
+                _ if arg.starts_with('+') => parsed.commands.push(arg[1..].to_string()),
+                _ if arg.starts_with('-') => return Err(format!("Unknown option: {}", arg)),
+                _ => parsed.files.push(FileArg::parse(&arg)),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+/// The config read at startup: `$XDG_CONFIG_HOME/violet/config`, or
+/// `~/.config/violet/config`.
+pub fn default_config() -> Option<PathBuf> {
+    let config = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))?;
+    Some(config.join("violet").join("config"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn test_file_positions_are_split_off() {
+        let arg = FileArg::parse("src/main.rs:10:5:");
+        assert_eq!(arg.path, "src/main.rs");
+        assert_eq!((arg.line, arg.col), (Some(10), Some(5)));
+
+        let arg = FileArg::parse("notes.txt:7");
+        assert_eq!(
+            (arg.path.as_str(), arg.line, arg.col),
+            ("notes.txt", Some(7), None)
+        );
+
+        for name in ["plain.rs", "a:b", ":12", "-"] {
+            assert_eq!(FileArg::parse(name).path, name);
+        }
+    }
+
+    #[test]
+    fn test_options_and_files() {
+        let args = parse(&["-R", "+/fn main", "a.rs", "-", "-u", "my.conf", "--", "-b"]).unwrap();
+        assert!(args.read_only);
+        assert_eq!(args.commands, vec!["/fn main"]);
+        assert_eq!(args.config, Some(PathBuf::from("my.conf")));
+        let files: Vec<&str> = args.files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(files, vec!["a.rs", "-", "-b"]);
+
+        let args = parse(&["--headless", "+", "-c", ":wq", "-s", "keys.txt"]).unwrap();
+        assert!(args.headless);
+        assert_eq!(args.commands, vec!["$", ":wq"]);
+        assert_eq!(args.script, Some(PathBuf::from("keys.txt")));
+        assert!(parse(&["-u", "NONE"]).unwrap().clean);
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["-u"]).is_err());
+        assert!(parse(&["-c"]).is_err());
+    }
+}
@@ -1,5 +1,6 @@
 use crossterm::event::KeyCode;
 
+#[derive(Default)]
 pub struct CommandPrompt {
     pub command: String,
     pub cursor_pos: usize, // only need on 1 axis
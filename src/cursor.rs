@@ -1,11 +1,20 @@
+use std::cmp::Ordering;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
 pub struct Cursor {
     pub x: usize,
     pub y: usize,
 }
 
-impl Default for Cursor {
-    fn default() -> Self {
-        Self { x: 0, y: 0 }
+// Positions are ordered the way they appear in the buffer, line first
+impl Ord for Cursor {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.y, self.x).cmp(&(other.y, other.x))
     }
 }
 
+impl PartialOrd for Cursor {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
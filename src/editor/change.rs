@@ -0,0 +1,83 @@
+use crossterm::event::KeyCode;
+
+use super::motion::{Motion, Operator};
+
+/// How insert or replace mode was entered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InsertEntry {
+    /// `i`
+    Before,
+    /// `a`
+    After,
+    /// `I`, before the first non-blank of the line.
+    LineStart,
+    /// `A`
+    LineEnd,
+    /// `o`
+    OpenBelow,
+    /// `O`
+    OpenAbove,
+    /// `R`, typed chars take the place of the ones under the cursor.
+    Replace,
+}
+
+impl InsertEntry {
+    pub fn from_key(key: KeyCode) -> Option<InsertEntry> {
+        Some(match key {
+            KeyCode::Char('i') => InsertEntry::Before,
+            KeyCode::Char('a') => InsertEntry::After,
+            KeyCode::Char('I') => InsertEntry::LineStart,
+            KeyCode::Char('A') => InsertEntry::LineEnd,
+            KeyCode::Char('o') => InsertEntry::OpenBelow,
+            KeyCode::Char('O') => InsertEntry::OpenAbove,
+            KeyCode::Char('R') => InsertEntry::Replace,
+            _ => return None,
+        })
+    }
+}
+
+/// What an operator works on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    Motion(Motion),
+    /// The key after `i` or `a`, like `w` for `diw`.
+    TextObject {
+        key: char,
+        around: bool,
+    },
+    /// The operator doubled, like `dd` or `>>`, works on whole lines.
+    Lines,
+}
+
+/// A command that changes the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    Insert(InsertEntry),
+    Operator {
+        operator: Operator,
+        target: Target,
+    },
+    /// `p`, or `P` for `before`.
+    Put {
+        before: bool,
+    },
+    /// `r`
+    ReplaceChar(char),
+    /// `J`, or `gJ` which leaves white space alone.
+    Join {
+        spaces: bool,
+    },
+    /// `~`
+    ToggleCase,
+}
+
+/// A change as it was made, so `.` can make it again.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Change {
+    pub action: Action,
+    pub count: Option<usize>,
+    /// Register named with `"`, if any.
+    pub register: Option<char>,
+    /// Keys typed in the insert session the change started.
+    pub inserted: Vec<KeyCode>,
+}
@@ -1,99 +1,520 @@
-use crossterm::{
-    cursor::{self, MoveTo},
-    event::KeyCode,
-    queue,
-    style::{Color as CrosstermColor, Print, SetBackgroundColor, SetForegroundColor},
-    terminal,
-};
-use std::path::Path;
-use std::{
-    fs::read_to_string,
-    io::{self, Write},
-};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use regex::Regex;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use std::{io, ops::Range};
 
 use crate::{
-    buffer::{buffer::Buffer, render_buffer::RenderBuffer, render_cell::RenderCell},
+    buffer::{
+        buffer::{Buffer, Mark, char_width, whitespace_fill},
+        editorconfig,
+        file::{DiskState, canonical_path, is_writable, write_file},
+        format::{self, FileEncoding, FileFormat},
+        large::{Loaded, Loading},
+        render_buffer::RenderBuffer,
+        render_cell::RenderCell,
+        swap::{self, Swap},
+        watcher::FileWatcher,
+    },
     command_prompt::CommandPrompt,
     cursor::Cursor,
     highlighter::Highlighter,
+    terminal::{CursorShape, Frame, Terminal},
+};
+
+use super::{
+    change::{Action, Change, InsertEntry, Target},
+    color::Color,
+    keys,
+    mode::Mode,
+    motion::{
+        CharSearch, Motion, MotionKind, Operator, end_of_word, find_candidates, find_char,
+        match_bracket, word_backward, word_end, word_end_backward, word_forward,
+    },
+    options::{Options, parse_set_arg},
+    register::{Register, Registers, UNNAMED},
+    substitute::Substitute,
+    textobject::{self, TextObject},
+    theme::Theme,
+    window::{
+        Direction, Jump, Rect, SplitDirection, TabPage, Window, WindowId, WindowTree, neighbour,
+    },
+    wrap::Layout,
 };
 
-use super::{color::Color, mode::Mode, theme::Theme};
+/// An operator typed in normal mode, with the count typed before it.
+#[derive(Debug, Clone, Copy)]
+struct PendingOperator {
+    operator: Operator,
+    count: Option<usize>,
+}
+
+/// Entries a jump list keeps before dropping the oldest.
+const MAX_JUMPS: usize = 100;
+
+/// Splits the line range off the front of an ex command, like `%` off
+/// `%s/a/b/` or `1,$` off `1,$s/a/b/`.
+fn split_range(command: &str) -> (&str, &str) {
+    let end = command
+        .find(|ch: char| !(ch.is_ascii_digit() || matches!(ch, ',' | '.' | '$' | '%')))
+        .unwrap_or(command.len());
+    command.split_at(end)
+}
+
+/// The count for an operator and its motion together, like six for `2d3w`.
+fn combine_counts(before: Option<usize>, after: Option<usize>) -> Option<usize> {
+    match (before, after) {
+        (None, None) => None,
+        (before, after) => Some(before.unwrap_or(1) * after.unwrap_or(1)),
+    }
+}
+
+/// Insert or replace mode as entered by a command, with what was typed so far.
+struct InsertSession {
+    entry: InsertEntry,
+    /// Times the typed text goes in, like `3ihi<Esc>`.
+    count: usize,
+    keys: Vec<KeyCode>,
+    /// Chars overwritten in replace mode, `None` for ones typed past the line
+    /// end. Backspace puts them back.
+    replaced: Vec<Option<char>>,
+    /// The change that started the session, it is done once the session ends.
+    change: Option<Change>,
+}
+
+enum Message {
+    Info(String),
+    Error(String),
+}
+
+/// A question about a buffer's file the user is yet to answer, the next
+/// key is the answer.
+enum Prompt {
+    /// The buffer was opened while a swap file for its file was lying around.
+    Swap {
+        buffer: usize,
+        swap_file: PathBuf,
+        swap: Swap,
+    },
+    /// Another program changed the file while the buffer has changes too.
+    Reload { buffer: usize },
+}
 
 pub struct Editor {
-    buffer: Buffer,
-    cursor: Cursor,
+    buffers: Vec<Buffer>,
+    /// The window with the cursor in it.
+    window: Window,
+    /// All other windows on screen.
+    windows: HashMap<WindowId, Window>,
+    window_tree: WindowTree,
+    next_window_id: WindowId,
+    /// The other tab pages, in order with the current one sitting at `current_tab`.
+    tabs: Vec<TabPage>,
+    current_tab: usize,
     mode: Mode,
     command_prompt: CommandPrompt,
     render_buffer: RenderBuffer,
     prev_render_buffer: RenderBuffer,
-    viewport_x: usize,
-    viewport_y: usize,
     last_key: Option<KeyCode>,
+    pending_operator: Option<PendingOperator>,
+    /// The last `f`, `F`, `t` or `T`, for `;` and `,`.
+    last_find: Option<CharSearch>,
+    insert: Option<InsertSession>,
+    /// The change `.` repeats.
+    last_change: Option<Change>,
+    /// Register named with `"` for the next command.
+    register: Option<char>,
+    /// The register a macro is being recorded into, with the keys typed so far.
+    recording: Option<(char, Vec<KeyEvent>)>,
+    /// Register of the last macro run, for `@@`.
+    last_macro: Option<char>,
+    /// Keys of a running macro still to be handled.
+    queued_keys: VecDeque<KeyEvent>,
+    next_jump_id: usize,
+    /// `Ctrl-w` was pressed and the window command key is still to come.
+    window_command_pending: bool,
     motion_count: Option<usize>,
     highlighter: Highlighter,
     theme: Theme,
+    options: Options,
+    message: Option<Message>,
+    needs_clear: bool,
+    /// The other end of the visual selection, the cursor being the moving end.
+    visual_anchor: Cursor,
+    /// Where the left mouse button went down, for starting a selection by dragging.
+    drag_start: Option<Cursor>,
+    registers: Registers,
+    prompt: Option<Prompt>,
+    /// Prompts waiting for the one being asked to be answered, like those for
+    /// several files opened at once that all have swap files.
+    queued_prompts: VecDeque<Prompt>,
+    /// Notices other programs changing open files.
+    watcher: Option<FileWatcher>,
+    /// When the last key came in, swap files are written once it's been quiet for a while.
+    last_input: Instant,
+    /// Started with `-R`, files open read-only.
+    view_mode: bool,
+    /// Running without a terminal, see [`Editor::set_headless`].
+    headless: bool,
+    /// Errors shown while headless, for reporting them at the end.
+    errors: Vec<String>,
 }
 
 impl Editor {
-    pub fn new(filename: Option<String>) -> Self {
-        let (width, height) = terminal::size().unwrap_or((80, 24));
-
+    pub fn new(filename: Option<String>, width: usize, height: usize) -> Self {
         // initialize with empty buffer
-        let buffer = if let Some(path) = filename {
-            Self::open_file(&path).unwrap_or_else(|_| {
-                // if no file, create a new empty buffer with the filename
-                Buffer::new(path, String::new())
-            })
-        } else {
-            Buffer::new("Untitled".to_string(), String::new())
-        };
+        let buffer = Buffer::new("Untitled".to_string(), String::new());
 
         let def_theme = Theme::default();
         let h = Highlighter::new(def_theme.clone()).unwrap();
 
-        Self {
-            buffer,
-            cursor: Cursor::default(),
+        let mut editor = Self {
+            buffers: vec![buffer],
+            window: Window::new(0, 0),
+            windows: HashMap::new(),
+            window_tree: WindowTree::Leaf(0),
+            next_window_id: 1,
+            tabs: Vec::new(),
+            current_tab: 0,
             mode: Mode::Normal,
             command_prompt: CommandPrompt::new(),
-            render_buffer: RenderBuffer::new(width as usize, height as usize),
-            prev_render_buffer: RenderBuffer::new(width as usize, height as usize),
-            viewport_x: 0,
-            viewport_y: 0,
+            render_buffer: RenderBuffer::new(width, height),
+            prev_render_buffer: RenderBuffer::new(width, height),
             last_key: None,
+            pending_operator: None,
+            last_find: None,
+            insert: None,
+            last_change: None,
+            register: None,
+            recording: None,
+            last_macro: None,
+            queued_keys: VecDeque::new(),
+            next_jump_id: 0,
+            window_command_pending: false,
             motion_count: None,
             highlighter: h,
             theme: def_theme,
+            options: Options::default(),
+            message: None,
+            needs_clear: false,
+            visual_anchor: Cursor::default(),
+            drag_start: None,
+            registers: Registers::default(),
+            prompt: None,
+            queued_prompts: VecDeque::new(),
+            watcher: FileWatcher::new().ok(),
+            last_input: Instant::now(),
+            view_mode: false,
+            headless: false,
+            errors: Vec::new(),
+        };
+        editor.arrange_windows();
+        if let Some(path) = filename {
+            editor.open_first_file(&path);
+        }
+        editor
+    }
+
+    /// Puts the file named on the command line in the first buffer. One that
+    /// can't be read still gets a buffer, read-only so its empty text never
+    /// ends up written over the file.
+    fn open_first_file(&mut self, path: &str) {
+        self.buffers[0] = match self.load_file(path) {
+            Ok(buffer) => buffer,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.new_file_buffer(path),
+            Err(e) => {
+                let mut buffer = Self::file_buffer(path, String::new());
+                buffer.options.readonly = true;
+                self.buffers[0] = buffer;
+                self.error(format!("Can't open {}: {}", path, e));
+                return;
+            }
+        };
+        self.attach_swap(0);
+        self.watch_file(0);
+    }
+
+    fn buffer(&self) -> &Buffer {
+        &self.buffers[self.window.buffer]
+    }
+
+    fn buffer_mut(&mut self) -> &mut Buffer {
+        &mut self.buffers[self.window.buffer]
+    }
+
+    /// Reads the file at `path` into a buffer, or lists it if it is a directory.
+    fn load_file(&mut self, path: &str) -> Result<Buffer, io::Error> {
+        let metadata = std::fs::metadata(path)?;
+        if metadata.is_dir() {
+            return Self::directory_buffer(Path::new(path));
+        }
+        if metadata.len() >= self.options.largefile << 20 {
+            return self.large_file_buffer(path, metadata.len());
+        }
+
+        let (state, contents) = DiskState::read(Path::new(path))?;
+        let decoded = format::decode(&contents);
+        let mut buffer = Self::file_buffer(path, decoded.text);
+        buffer.options.fileformat = decoded.format;
+        buffer.options.fileencoding = decoded.encoding;
+        buffer.options.readonly = self.view_mode || !is_writable(Path::new(path));
+        buffer.disk = Some(state);
+        if let Some(warning) = Self::decode_warning(&mut buffer, decoded.binary, decoded.lossy) {
+            self.message = Some(warning);
+        }
+        Self::apply_editorconfig(&self.options, &mut buffer, false);
+        Ok(buffer)
+    }
+
+    /// A buffer for a file of `largefile` MiB or more, which is read and
+    /// indexed in the background and can't be changed until it is there.
+    /// It isn't highlighted and has no swap file.
+    fn large_file_buffer(&mut self, path: &str, size: u64) -> Result<Buffer, io::Error> {
+        // Fail now on a file that can't be read, like a small one would
+        std::fs::File::open(path)?;
+
+        let mut buffer = Self::file_buffer(path, String::new());
+        buffer.large = true;
+        buffer.options.readonly = self.view_mode || !is_writable(Path::new(path));
+        let loading = Loading::start(PathBuf::from(path), size);
+        if self.headless {
+            // Nothing to keep responding to, and the commands need the text
+            if let Some(warning) = Self::fill_large_buffer(&mut buffer, loading.wait()?) {
+                self.message = Some(warning);
+            }
+            Self::apply_editorconfig(&self.options, &mut buffer, false);
+        } else {
+            buffer.loading = Some(loading);
+        }
+        Ok(buffer)
+    }
+
+    /// Puts the text of a large file into `buffer` once it has been read,
+    /// returns a warning about what was in it if there is one.
+    fn fill_large_buffer(buffer: &mut Buffer, loaded: Loaded) -> Option<Message> {
+        buffer.set_indexed_text(loaded.text, loaded.line_offsets);
+        buffer.modified = false;
+        buffer.swap_stale = false;
+        buffer.options.fileformat = loaded.format;
+        buffer.options.fileencoding = loaded.encoding;
+        buffer.disk = Some(loaded.disk);
+        Self::decode_warning(buffer, loaded.binary, loaded.lossy)
+    }
+
+    /// The warning for a file that looks binary, or that had bytes the
+    /// encoding doesn't allow. Writing such text back would corrupt the file,
+    /// so the buffer becomes read-only.
+    fn decode_warning(buffer: &mut Buffer, binary: bool, lossy: bool) -> Option<Message> {
+        if lossy {
+            buffer.options.readonly = true;
+            return Some(Message::Error(format!(
+                "{} isn't valid {}, opened read-only",
+                buffer.buffer_name, buffer.options.fileencoding
+            )));
+        }
+        binary.then(|| Message::Error(format!("{} looks like a binary file", buffer.buffer_name)))
+    }
+
+    /// Takes in the large files that finished reading in the background.
+    fn poll_loading(&mut self) {
+        for index in 0..self.buffers.len() {
+            let Some(result) = self.buffers[index]
+                .loading
+                .as_ref()
+                .and_then(Loading::try_finish)
+            else {
+                continue;
+            };
+            let Some(loading) = self.buffers[index].loading.take() else {
+                continue;
+            };
+
+            let buffer = &mut self.buffers[index];
+            match result {
+                Ok(loaded) => {
+                    if let Some(warning) = Self::fill_large_buffer(buffer, loaded) {
+                        self.message = Some(warning);
+                    }
+                    Self::apply_editorconfig(&self.options, buffer, false);
+                    // A file read again may be shorter than it was
+                    if index == self.window.buffer {
+                        self.clamp_window_cursor();
+                    }
+                }
+                Err(e) => {
+                    // Its empty text must never end up written over the file
+                    buffer.options.readonly = true;
+                    let message = format!("Can't read {}: {}", buffer.buffer_name, e);
+                    self.error(message);
+                }
+            }
+
+            if let Some((line, col)) = loading.position {
+                self.place_cursor(index, line, col);
+            }
+        }
+    }
+
+    /// Gives `buffer` the options the `.editorconfig` files around its file
+    /// set, unless `editorconfig` is off. The line endings and encoding are
+    /// only set for a `new_file`, an existing one keeps those it has.
+    fn apply_editorconfig(options: &Options, buffer: &mut Buffer, new_file: bool) {
+        if !options.editorconfig || buffer.listing {
+            return;
         }
+        if let Some(path) = &buffer.path {
+            let properties = editorconfig::properties(path);
+            editorconfig::apply(&properties, &mut buffer.options, new_file);
+        }
+    }
+
+    /// A buffer for a file that doesn't exist yet, the first `:w` makes it.
+    fn new_file_buffer(&mut self, path: &str) -> Buffer {
+        let mut buffer = Self::file_buffer(path, String::new());
+        buffer.options.readonly = self.view_mode;
+        Self::apply_editorconfig(&self.options, &mut buffer, true);
+        self.message = Some(Message::Info(format!("\"{}\" [New]", buffer.buffer_name)));
+        buffer
+    }
+
+    /// A read-only buffer listing the directory at `path`, Enter opens the
+    /// entry under the cursor.
+    fn directory_buffer(path: &Path) -> Result<Buffer, io::Error> {
+        let dir = std::fs::canonicalize(path)?;
+        let mut buffer = Buffer::new(
+            format!("{}/", dir.display()).replace("//", "/"),
+            Self::list_directory(&dir)?,
+        );
+        buffer.path = Some(dir);
+        buffer.listing = true;
+        buffer.options.readonly = true;
+        buffer.options.modifiable = false;
+        Ok(buffer)
+    }
+
+    /// `../` and then the entries of `dir`, directories first and ending in `/`.
+    fn list_directory(dir: &Path) -> Result<String, io::Error> {
+        let mut entries: Vec<(bool, String)> = std::fs::read_dir(dir)?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                // Follows symlinks, so a link to a directory is opened as one
+                let is_dir = entry.path().is_dir();
+                (is_dir, entry.file_name().to_string_lossy().into_owned())
+            })
+            .collect();
+        entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+        let mut text = String::from("../");
+        for (is_dir, name) in entries {
+            text.push('\n');
+            text.push_str(&name);
+            if is_dir {
+                text.push('/');
+            }
+        }
+        Ok(text)
+    }
+
+    /// Enter in a directory listing: opens the file or directory under the cursor.
+    fn open_listing_entry(&mut self) -> anyhow::Result<()> {
+        let Some(dir) = self.buffer().path.clone() else {
+            return Ok(());
+        };
+        let entry = self
+            .buffer()
+            .get_line(self.window.cursor.y)
+            .unwrap_or("")
+            .trim_end_matches('/');
+        if entry.is_empty() {
+            return Ok(());
+        }
+
+        let path = dir.join(entry);
+        self.edit_file(&path.to_string_lossy())
+    }
+
+    /// A buffer for the file at `path`, named after the file.
+    fn file_buffer(path: &str, contents: String) -> Buffer {
+        let mut buffer = Buffer::new(Self::file_name(path), contents);
+        buffer.path = Some(PathBuf::from(path));
+        buffer
     }
 
-    fn open_file(path: &str) -> Result<Buffer, io::Error> {
-        let contents = read_to_string(path)?;
-        let filename = Path::new(path)
+    fn file_name(path: &str) -> String {
+        Path::new(path)
             .file_name()
             .map(|osstr| osstr.to_string_lossy().to_string())
-            .unwrap_or_else(|| path.to_string());
+            .unwrap_or_else(|| path.to_string())
+    }
 
-        Ok(Buffer::new(filename, contents))
+    pub fn handle_keypress(&mut self, key: KeyEvent) -> bool {
+        self.last_input = Instant::now();
+        if let Some((_, keys)) = &mut self.recording {
+            keys.push(key);
+        }
+
+        let mut quit = self.dispatch_key(key);
+        // A macro's keys go through the same way, until one of them fails
+        while !quit && let Some(key) = self.queued_keys.pop_front() {
+            quit = self.dispatch_key(key);
+        }
+        quit
     }
 
-    pub fn handle_keypress(&mut self, key: KeyCode) -> bool {
+    fn dispatch_key(&mut self, key: KeyEvent) -> bool {
+        if self.prompt.is_some() {
+            self.answer_prompt(key.code);
+            return false;
+        }
+        self.message = None;
+
         match self.mode {
             Mode::Normal => self.handle_normal_mode(key),
-            Mode::Insert => self.handle_insert_mode(key),
-            Mode::Command => self.handle_command_mode(key),
+            Mode::Insert | Mode::Replace => self.handle_insert_mode(key.code),
+            Mode::Command => self.handle_command_mode(key.code),
+            Mode::Visual | Mode::VisualLine => self.handle_visual_mode(key),
+        }
+    }
+
+    /// Shows `message` as an error, which also stops a running macro.
+    pub fn error(&mut self, message: String) {
+        if self.headless {
+            self.errors.push(message.clone());
         }
+        self.message = Some(Message::Error(message));
+        self.fail();
+    }
+
+    /// A command couldn't do what it was asked, like `l` at the end of the
+    /// line. The rest of a running macro is dropped.
+    fn fail(&mut self) {
+        self.queued_keys.clear();
+    }
+
+    /// Resizes the screen after a terminal resize and repaints everything.
+    pub fn resize(&mut self, width: usize, height: usize) {
+        self.render_buffer.resize(width, height);
+        self.prev_render_buffer.resize(width, height);
+        self.arrange_windows();
+        self.redraw();
+    }
+
+    /// Forces the next render to clear the terminal and repaint every cell.
+    pub fn redraw(&mut self) {
+        self.prev_render_buffer.invalidate();
+        self.needs_clear = true;
     }
 
     fn handle_command_mode(&mut self, key: KeyCode) -> bool {
         if let Some(command) = self.command_prompt.handle_key(key) {
-            if command == "q" || command == "quit" {
-                return true;
-            } else {
-                self.execute_command(&command);
-                self.mode = Mode::Normal;
+            match self.run_command(&command) {
+                Ok(true) => return true,
+                Ok(false) => {}
+                Err(e) => self.error(e.to_string()),
             }
+            self.mode = Mode::Normal;
         }
 
         if !self.command_prompt.is_active() {
@@ -103,178 +524,2756 @@ impl Editor {
         false
     }
 
-    fn handle_normal_mode(&mut self, key: KeyCode) -> bool {
-        if let Some(KeyCode::Char('d')) = self.last_key {
-            if key == KeyCode::Char('d') {
-                self.delete_current_line();
-                self.last_key = None;
-                self.motion_count = None;
+    fn handle_normal_mode(&mut self, event: KeyEvent) -> bool {
+        if self.window_command_pending {
+            self.window_command_pending = false;
+            if let Err(e) = self.window_command(event.code) {
+                self.error(e.to_string());
+            }
+            return false;
+        }
+
+        if event.modifiers.contains(KeyModifiers::CONTROL) {
+            self.last_key = None;
+            let count = self.motion_count.take().unwrap_or(1);
+
+            match event.code {
+                KeyCode::Char('l') => self.redraw(),
+                KeyCode::Char('w') => self.window_command_pending = true,
+                KeyCode::Char('o') => self.jump_older(count),
+                KeyCode::Char('i') => self.jump_newer(count),
+                _ => {}
+            }
+            return false;
+        }
+
+        let key = event.code;
+        if let Some(KeyCode::Char(prefix @ ('i' | 'a'))) = self.last_key {
+            self.last_key = None;
+            let Some(pending) = self.pending_operator.take() else {
+                self.cancel_pending();
                 return false;
+            };
+            let count = combine_counts(pending.count, self.motion_count.take());
+            match key {
+                KeyCode::Char(key) => {
+                    let around = prefix == 'a';
+                    self.operate(pending.operator, Target::TextObject { key, around }, count);
+                }
+                _ => self.cancel_pending(),
             }
+            return false;
+        }
+
+        if let Some(KeyCode::Char('g')) = self.last_key {
             self.last_key = None;
-            return self.handle_normal_mode(key);
+            match key {
+                KeyCode::Char('g') => self.apply_motion(Motion::FirstLine),
+                KeyCode::Char('j') => self.apply_motion(Motion::DisplayDown),
+                KeyCode::Char('k') => self.apply_motion(Motion::DisplayUp),
+                KeyCode::Char('e') => self.apply_motion(Motion::WordEndBackward { bigword: false }),
+                KeyCode::Char('E') => self.apply_motion(Motion::WordEndBackward { bigword: true }),
+                KeyCode::Char('t') if self.pending_operator.is_none() => {
+                    match self.motion_count.take() {
+                        // `{N}gt` goes to tab page N rather than N pages on
+                        Some(n) => self.switch_tab(n.saturating_sub(1)),
+                        None => self.switch_tab((self.current_tab + 1) % self.tab_count()),
+                    }
+                }
+                KeyCode::Char('J') if self.pending_operator.is_none() => {
+                    let count = self.motion_count.take();
+                    self.run_change(Action::Join { spaces: false }, count);
+                }
+                KeyCode::Char('T') if self.pending_operator.is_none() => {
+                    let steps = self.motion_count.take().unwrap_or(1) % self.tab_count();
+                    self.switch_tab(
+                        (self.current_tab + self.tab_count() - steps) % self.tab_count(),
+                    );
+                }
+                _ => {
+                    self.motion_count = None;
+                    self.pending_operator = None;
+                }
+            }
+            return false;
         }
 
-        match key {
-            KeyCode::Char(c) if c.is_ascii_digit() => {
-                let digit = c.to_digit(10).unwrap() as usize;
-                self.motion_count = Some(
-                    self.motion_count
-                        .unwrap_or(0)
-                        .saturating_mul(10)
-                        .saturating_add(digit),
-                );
+        if let Some(KeyCode::Char('"')) = self.last_key {
+            self.last_key = None;
+            match key {
+                KeyCode::Char(name) if Registers::is_valid(name) => self.register = Some(name),
+                _ => self.cancel_pending(),
             }
+            return false;
+        }
 
-            KeyCode::Char('j') => {
-                let count = self.motion_count.take().unwrap_or(1);
-                for _ in 0..count {
-                    self.move_cursor_down();
+        if let Some(prefix @ KeyCode::Char('q' | '@')) = self.last_key {
+            self.last_key = None;
+            let count = self.motion_count.take().unwrap_or(1);
+            match (prefix, key) {
+                (KeyCode::Char('q'), KeyCode::Char(name)) if Registers::is_valid(name) => {
+                    self.recording = Some((name, Vec::new()));
+                }
+                (KeyCode::Char('@'), KeyCode::Char('@')) => match self.last_macro {
+                    Some(name) => self.play_macro(name, count),
+                    None => self.error("No previous macro".to_string()),
+                },
+                (KeyCode::Char('@'), KeyCode::Char(name)) if Registers::is_valid(name) => {
+                    self.play_macro(name, count);
                 }
+                _ => self.cancel_pending(),
             }
-            KeyCode::Char('k') => {
-                let count = self.motion_count.take().unwrap_or(1);
-                for _ in 0..count {
-                    self.move_cursor_up();
+            return false;
+        }
+
+        if let Some(prefix @ KeyCode::Char('m' | '\'' | '`')) = self.last_key {
+            self.last_key = None;
+            match (prefix, key) {
+                (KeyCode::Char('m'), KeyCode::Char(name))
+                    if name.is_ascii_alphabetic() || name == '\'' || name == '`' =>
+                {
+                    self.set_mark(name);
+                    self.motion_count = None;
+                }
+                (KeyCode::Char(prefix), KeyCode::Char(name)) if prefix != 'm' => {
+                    let linewise = prefix == '\'';
+                    self.apply_motion(Motion::Mark { name, linewise });
                 }
+                _ => self.cancel_pending(),
             }
-            KeyCode::Char('h') => {
-                let count = self.motion_count.take().unwrap_or(1);
-                for _ in 0..count {
-                    self.move_cursor_left();
+            return false;
+        }
+
+        if let Some(KeyCode::Char('r')) = self.last_key {
+            self.last_key = None;
+            let count = self.motion_count.take();
+            let ch = match key {
+                KeyCode::Char(ch) => Some(ch),
+                KeyCode::Tab => Some('\t'),
+                KeyCode::Enter => Some('\n'),
+                _ => None,
+            };
+            if let Some(ch) = ch {
+                self.run_change(Action::ReplaceChar(ch), count);
+            }
+            return false;
+        }
+
+        if let Some(prefix @ KeyCode::Char('f' | 'F' | 't' | 'T')) = self.last_key {
+            self.last_key = None;
+            let ch = match key {
+                KeyCode::Char(ch) => Some(ch),
+                KeyCode::Tab => Some('\t'),
+                _ => None,
+            };
+            match ch.and_then(|ch| CharSearch::from_key(prefix, ch)) {
+                Some(search) => {
+                    self.last_find = Some(search);
+                    self.apply_motion(Motion::FindChar {
+                        search,
+                        repeat: false,
+                    });
+                }
+                // Esc and the like cancel the search
+                None => {
+                    self.motion_count = None;
+                    self.pending_operator = None;
                 }
             }
-            KeyCode::Char('l') => {
-                let count = self.motion_count.take().unwrap_or(1);
-                for _ in 0..count {
-                    self.move_cursor_right();
+            return false;
+        }
+
+        // `0` is a motion unless it continues a count
+        if let KeyCode::Char(c) = key
+            && c.is_ascii_digit()
+            && (c != '0' || self.motion_count.is_some())
+        {
+            let digit = c.to_digit(10).unwrap() as usize;
+            self.motion_count = Some(
+                self.motion_count
+                    .unwrap_or(0)
+                    .saturating_mul(10)
+                    .saturating_add(digit),
+            );
+            return false;
+        }
+
+        if let Some(motion) = Motion::from_key(key) {
+            self.apply_motion(motion);
+            return false;
+        }
+
+        // Keep the count and operator, they apply once the char to find or the mark is typed
+        if let KeyCode::Char('f' | 'F' | 't' | 'T' | '\'' | '`') = key {
+            self.last_key = Some(key);
+            return false;
+        }
+
+        if let Some(operator) = Operator::from_key(key) {
+            match self.pending_operator.take() {
+                // Doubling an operator, like `dd`, works on whole lines
+                Some(pending) if pending.operator == operator => {
+                    let count = combine_counts(pending.count, self.motion_count.take());
+                    self.operate(operator, Target::Lines, count);
+                }
+                Some(_) => self.motion_count = None,
+                None => {
+                    self.pending_operator = Some(PendingOperator {
+                        operator,
+                        count: self.motion_count.take(),
+                    });
                 }
             }
-            KeyCode::Char('w') => {
-                let count = self.motion_count.take().unwrap_or(1);
-                for _ in 0..count {
-                    self.jump_cursor_word();
+            return false;
+        }
+
+        if self.pending_operator.is_some() {
+            match key {
+                KeyCode::Char('i' | 'a' | 'g') => self.last_key = Some(key),
+                // Anything else cancels the operator
+                _ => {
+                    self.pending_operator = None;
+                    self.motion_count = None;
+                }
+            }
+            return false;
+        }
+
+        match key {
+            // `x` and `X` are short for `dl` and `dh`
+            KeyCode::Char('x' | 'X') => {
+                let count = self.motion_count.take();
+                let motion = if key == KeyCode::Char('x') {
+                    Motion::Right
+                } else {
+                    Motion::Left
+                };
+                self.operate(Operator::Delete, Target::Motion(motion), count);
+            }
+
+            KeyCode::Char('"') => self.last_key = Some(key),
+
+            KeyCode::Char('q') => match self.recording.take() {
+                Some((name, mut keys)) => {
+                    // The `q` that stopped the recording isn't part of it
+                    keys.pop();
+                    let text: String = keys.into_iter().map(keys::to_notation).collect();
+                    self.registers.record(name, text);
+                    self.motion_count = None;
+                }
+                None => self.last_key = Some(key),
+            },
+
+            // Keep the count, it says how many times to run the macro
+            KeyCode::Char('@') => self.last_key = Some(key),
+
+            KeyCode::Char('m') => self.last_key = Some(key),
+
+            KeyCode::Enter if self.buffer().listing => {
+                self.motion_count = None;
+                if let Err(e) = self.open_listing_entry() {
+                    self.error(e.to_string());
                 }
             }
 
-            KeyCode::Char('b') => {
+            // Terminals send `Ctrl-i` as Tab
+            KeyCode::Tab => {
                 let count = self.motion_count.take().unwrap_or(1);
-                for _ in 0..count {
-                    self.jump_cursor_word_reverse();
+                self.jump_newer(count);
+            }
+
+            KeyCode::Char('i' | 'a' | 'I' | 'A' | 'o' | 'O' | 'R') => {
+                let count = self.motion_count.take();
+                if let Some(entry) = InsertEntry::from_key(key) {
+                    self.run_change(Action::Insert(entry), count);
                 }
             }
 
-            KeyCode::Char('d') => {
-                self.last_key = Some(KeyCode::Char('d'));
-                self.motion_count = None;
+            // `s`, `S` and `C` are short for `cl`, `cc` and `c$`
+            KeyCode::Char('s' | 'S' | 'C') => {
+                let count = self.motion_count.take();
+                let target = match key {
+                    KeyCode::Char('s') => Target::Motion(Motion::Right),
+                    KeyCode::Char('S') => Target::Lines,
+                    _ => Target::Motion(Motion::LineEnd),
+                };
+                let operator = Operator::Change;
+                self.run_change(Action::Operator { operator, target }, count);
+            }
+
+            KeyCode::Char('J') => {
+                let count = self.motion_count.take();
+                self.run_change(Action::Join { spaces: true }, count);
             }
 
-            KeyCode::Char('x') => {
-                self.delete_char_at_cursor();
+            KeyCode::Char('~') => {
+                let count = self.motion_count.take();
+                self.run_change(Action::ToggleCase, count);
+            }
+
+            KeyCode::Char('.') => {
+                let count = self.motion_count.take();
+                self.repeat_change(count);
+            }
+
+            KeyCode::Char('r') => {
+                // Keep the count, it applies once the replacement char is typed
+                self.last_key = Some(key);
+            }
+
+            KeyCode::Char('v') => {
+                self.toggle_visual(Mode::Visual);
                 self.motion_count = None;
             }
 
-            KeyCode::Char('i') => {
-                self.mode = Mode::Insert;
+            KeyCode::Char('V') => {
+                self.toggle_visual(Mode::VisualLine);
                 self.motion_count = None;
             }
 
+            KeyCode::Char('p' | 'P') => {
+                let count = self.motion_count.take();
+                let before = key == KeyCode::Char('P');
+                self.run_change(Action::Put { before }, count);
+            }
+
             KeyCode::Char(':') => {
                 self.mode = Mode::Command;
                 self.command_prompt.activate();
                 self.motion_count = None;
             }
 
-            KeyCode::Char('0') => {
-                if self.motion_count.is_some() {
-                    self.motion_count = Some(self.motion_count.unwrap().saturating_mul(10));
-                } else {
-                    self.cursor.x = 0;
-                }
+            KeyCode::Char('g') => {
+                // Keep the count, it applies to the key after `g`
+                self.last_key = Some(KeyCode::Char('g'));
             }
 
-            KeyCode::Char('$') => {
-                if let Some(line) = self.buffer.get_line(self.cursor.y) {
-                    self.cursor.x = line.len().saturating_sub(1);
-                }
-                self.motion_count = None;
-            }
+            _ => self.cancel_pending(), // unknown key resets count
+        }
 
-            KeyCode::Char('g') => {
-                self.cursor.y = 0;
-                self.cursor.x = 0;
-                self.motion_count = None;
+        false
+    }
+
+    /// `@`: types the keys in register `name` `count` times. They are edited
+    /// like any other register text, with special keys written as `<Esc>`.
+    fn play_macro(&mut self, name: char, count: usize) {
+        let Some(register) = self.registers.get(name) else {
+            self.error(format!("Register {} is empty", name));
+            return;
+        };
+
+        let keys = keys::parse(&register.text);
+        self.last_macro = Some(name);
+        // Ahead of what's left of a macro running this one
+        for _ in 0..count {
+            for key in keys.iter().rev() {
+                self.queued_keys.push_front(*key);
             }
+        }
+    }
 
-            KeyCode::Char('G') => {
-                self.cursor.y = self.buffer.line_count().saturating_sub(1);
-                self.cursor.x = 0;
-                self.motion_count = None;
+    /// `m`: puts mark `name` on the cursor. Uppercase marks are for the
+    /// whole editor, setting one takes it out of whichever buffer had it.
+    fn set_mark(&mut self, name: char) {
+        let name = if name == '`' { '\'' } else { name };
+        let Cursor { x, y } = self.window.cursor;
+        let pos = self
+            .buffer()
+            .char_to_byte_position(y, x)
+            .unwrap_or(self.buffer().text.len());
+
+        if name.is_ascii_uppercase() {
+            for buffer in &mut self.buffers {
+                buffer.marks.remove(&Mark::Named(name));
             }
+        }
+        self.buffer_mut().marks.insert(Mark::Named(name), pos);
+    }
 
-            _ => {
-                self.motion_count = None; // unknown key resets count
+    /// The buffer mark `name` is in and where.
+    fn find_mark(&self, name: char) -> Option<(usize, Cursor)> {
+        let mark = Mark::Named(if name == '`' { '\'' } else { name });
+        let index = if name.is_ascii_uppercase() {
+            self.buffers
+                .iter()
+                .position(|buffer| buffer.marks.contains_key(&mark))?
+        } else {
+            self.window.buffer
+        };
+        Some((index, self.buffers[index].mark(mark)?))
+    }
+
+    /// Adds a place jumped away from to the jump list, it also becomes the `''` mark.
+    fn push_jump(&mut self, buffer: usize, cursor: Cursor) {
+        let pos = self.buffers[buffer]
+            .char_to_byte_position(cursor.y, cursor.x)
+            .unwrap_or(self.buffers[buffer].text.len());
+        self.buffers[buffer].marks.insert(Mark::Named('\''), pos);
+
+        // A line is only in the list once, where it was last jumped from
+        let buffers = &mut self.buffers;
+        self.window.jumps.retain(|jump| {
+            let mark = Mark::Jump(jump.id);
+            let same_line = jump.buffer == buffer
+                && buffers[buffer]
+                    .mark(mark)
+                    .is_some_and(|position| position.y == cursor.y);
+            if same_line {
+                buffers[buffer].marks.remove(&mark);
             }
+            !same_line
+        });
+        if self.window.jumps.len() >= MAX_JUMPS {
+            let oldest = self.window.jumps.remove(0);
+            self.buffers[oldest.buffer]
+                .marks
+                .remove(&Mark::Jump(oldest.id));
+        }
+
+        let id = self.next_jump_id;
+        self.next_jump_id += 1;
+        self.buffers[buffer].marks.insert(Mark::Jump(id), pos);
+        self.window.jumps.push(Jump { buffer, id });
+        self.window.jump_index = self.window.jumps.len();
+    }
+
+    /// `Ctrl-o`: goes `count` entries back in the jump list.
+    fn jump_older(&mut self, count: usize) {
+        if self.window.jump_index == self.window.jumps.len() {
+            // Remember where this is so `Ctrl-i` can come back to it
+            let (buffer, cursor) = (self.window.buffer, self.window.cursor);
+            self.push_jump(buffer, cursor);
+            self.window.jump_index = self.window.jumps.len() - 1;
+        }
+
+        match self.window.jump_index.checked_sub(count) {
+            Some(index) => self.go_to_jump(index),
+            None => self.fail(),
+        }
+    }
+
+    /// `Ctrl-i`: goes `count` entries forward in the jump list.
+    fn jump_newer(&mut self, count: usize) {
+        let index = self.window.jump_index + count;
+        if index < self.window.jumps.len() {
+            self.go_to_jump(index);
+        } else {
+            self.fail();
         }
+    }
+
+    fn go_to_jump(&mut self, index: usize) {
+        let jump = self.window.jumps[index];
+        let Some(cursor) = self.buffers[jump.buffer].mark(Mark::Jump(jump.id)) else {
+            self.fail();
+            return;
+        };
+
+        self.switch_buffer(jump.buffer);
+        self.window.cursor = cursor;
+        self.window.jump_index = index;
+    }
 
+    /// Whether the buffer can be changed, with an error if it can't.
+    fn check_modifiable(&mut self) -> bool {
+        let Err(e) = self.modifiable() else {
+            return true;
+        };
+        self.cancel_pending();
+        self.error(e.to_string());
         false
     }
 
-    fn handle_insert_mode(&mut self, key: KeyCode) -> bool {
-        match key {
-            KeyCode::Esc => self.mode = Mode::Normal,
-            KeyCode::Char(c) => self.insert_char(c),
-            KeyCode::Backspace => self.delete_char_before_cursor(),
-            KeyCode::Delete => self.delete_char_at_cursor(),
-            KeyCode::Enter => self.insert_newline(),
-            KeyCode::Left => self.move_cursor_left(),
-            KeyCode::Right => self.move_cursor_right(),
-            KeyCode::Up => self.move_cursor_up(),
-            KeyCode::Down => self.move_cursor_down(),
-            _ => {}
+    /// Why the buffer can't be changed, if it can't.
+    fn modifiable(&self) -> anyhow::Result<()> {
+        let buffer = self.buffer();
+        if buffer.loading.is_some() {
+            anyhow::bail!("{} is still being read", buffer.buffer_name);
+        }
+        if !buffer.options.modifiable {
+            anyhow::bail!("Cannot make changes, 'modifiable' is off");
+        }
+        Ok(())
+    }
+
+    /// Forgets a half typed command: its count, operator and register.
+    fn cancel_pending(&mut self) {
+        self.motion_count = None;
+        self.pending_operator = None;
+        self.register = None;
+    }
+
+    /// Moves the cursor by `motion`, or has the pending operator act on it.
+    fn apply_motion(&mut self, motion: Motion) {
+        match self.pending_operator.take() {
+            Some(pending) => {
+                let count = combine_counts(pending.count, self.motion_count.take());
+                self.operate(pending.operator, Target::Motion(motion), count);
+            }
+            None => self.run_motion(motion),
+        }
+    }
+
+    /// Applies `operator` to `target`, as a change unless it only yanks.
+    fn operate(&mut self, operator: Operator, target: Target, count: Option<usize>) {
+        if operator == Operator::Yank {
+            self.run_operator(operator, target, count);
+            self.register = None;
+        } else {
+            self.run_change(Action::Operator { operator, target }, count);
+        }
+    }
+
+    fn run_operator(&mut self, operator: Operator, target: Target, count: Option<usize>) {
+        self.motion_count = None;
+        match target {
+            Target::Motion(motion) => {
+                self.pending_operator = Some(PendingOperator { operator, count });
+                self.run_motion(motion);
+            }
+            Target::TextObject { key, around } => {
+                if let Some(object) = self.text_object(key, around, count.unwrap_or(1)) {
+                    self.apply_operator(operator, object.range, object.linewise);
+                }
+            }
+            Target::Lines => self.operate_on_lines(operator, count.unwrap_or(1)),
+        }
+    }
+
+    /// Takes the count typed so far, multiplied with the one typed before the
+    /// operator, like `2d3w` deleting six words.
+    fn take_count(&mut self) -> usize {
+        let operator_count = self.pending_operator.and_then(|pending| pending.count);
+        let count = self.motion_count.take();
+        operator_count.unwrap_or(1) * count.unwrap_or(1)
+    }
+
+    /// Moves the cursor by `motion`, or applies the pending operator up to where it goes.
+    fn run_motion(&mut self, motion: Motion) {
+        // `;` and `,` stand for the last char search, `,` going the other way
+        let motion = match motion {
+            Motion::RepeatFind { reverse } => {
+                let Some(search) = self.last_find else {
+                    self.cancel_pending();
+                    self.fail();
+                    return;
+                };
+                let search = if reverse { search.reversed() } else { search };
+                Motion::FindChar {
+                    search,
+                    repeat: true,
+                }
+            }
+            _ => motion,
+        };
+
+        // `cw` on a word changes up to its end like `ce`, leaving the white space after it
+        let (motion, change_word) = match (motion, self.pending_operator) {
+            (Motion::WordForward { bigword }, Some(pending))
+                if pending.operator == Operator::Change
+                    && self
+                        .buffer()
+                        .get_line(self.window.cursor.y)
+                        .and_then(|line| line.chars().nth(self.window.cursor.x))
+                        .is_some_and(|ch| !ch.is_whitespace()) =>
+            {
+                (Motion::WordEnd { bigword }, true)
+            }
+            _ => (motion, false),
+        };
+
+        let explicit_count = self.motion_count.is_some()
+            || self
+                .pending_operator
+                .is_some_and(|pending| pending.count.is_some());
+        let count = self.take_count();
+        let start = self.window.cursor;
+        let start_buffer = self.window.buffer;
+
+        match motion {
+            Motion::Left if self.pending_operator.is_some() => {
+                self.window.cursor.x = start.x.saturating_sub(count);
+            }
+            Motion::Left => (0..count).for_each(|_| self.move_cursor_left()),
+            Motion::Down => (0..count).for_each(|_| self.move_cursor_down()),
+            Motion::Up => (0..count).for_each(|_| self.move_cursor_up()),
+            // With an operator `h` and `l` stay on the line, so `3x` doesn't join lines
+            Motion::Right if self.pending_operator.is_some() => {
+                let line_len = self.buffer().line_len(start.y);
+                self.window.cursor.x = (start.x + count).min(line_len);
+            }
+            Motion::Right => (0..count).for_each(|_| self.move_cursor_right()),
+            Motion::DisplayDown => (0..count).for_each(|_| self.move_cursor_display_down()),
+            Motion::DisplayUp => (0..count).for_each(|_| self.move_cursor_display_up()),
+            Motion::WordForward { bigword } => {
+                for _ in 0..count {
+                    self.window.cursor = word_forward(self.buffer(), self.window.cursor, bigword);
+                }
+            }
+            Motion::WordBackward { bigword } => {
+                for _ in 0..count {
+                    self.window.cursor = word_backward(self.buffer(), self.window.cursor, bigword);
+                }
+            }
+            Motion::WordEnd { bigword } => {
+                for i in 0..count {
+                    let cursor = self.window.cursor;
+                    self.window.cursor = if i == 0 && change_word {
+                        end_of_word(self.buffer(), cursor, bigword)
+                    } else {
+                        word_end(self.buffer(), cursor, bigword)
+                    };
+                }
+            }
+            Motion::WordEndBackward { bigword } => {
+                for _ in 0..count {
+                    self.window.cursor =
+                        word_end_backward(self.buffer(), self.window.cursor, bigword);
+                }
+            }
+            Motion::FindChar { search, repeat } => {
+                let line = self.buffer().get_line(start.y).unwrap_or("");
+                match find_char(line, start.x, search, count, repeat) {
+                    Some(x) => self.window.cursor.x = x,
+                    // Not that many matches on the line, the motion and any operator fail
+                    None => {
+                        self.cancel_pending();
+                        self.fail();
+                        return;
+                    }
+                }
+            }
+            Motion::Mark { name, linewise } => {
+                let Some((buffer, target)) = self.find_mark(name) else {
+                    self.cancel_pending();
+                    self.error(format!("Mark not set: {}", name));
+                    return;
+                };
+                if buffer != self.window.buffer {
+                    // An operator can't reach into another buffer
+                    if self.pending_operator.is_some() {
+                        self.cancel_pending();
+                        self.fail();
+                        return;
+                    }
+                    self.switch_buffer(buffer);
+                }
+
+                self.window.cursor = target;
+                if linewise {
+                    self.window.cursor.x = self.first_non_blank(target.y);
+                }
+            }
+            Motion::MatchBracket => {
+                self.update_brackets(self.window.buffer);
+                let buffer = &self.buffers[self.window.buffer];
+                match match_bracket(buffer, buffer.brackets.brackets(), start) {
+                    Some(target) => self.window.cursor = target,
+                    None => {
+                        self.cancel_pending();
+                        self.fail();
+                        return;
+                    }
+                }
+            }
+            // Turned into a `FindChar` above
+            Motion::RepeatFind { .. } => {}
+            Motion::LineStart => self.window.cursor.x = 0,
+            Motion::LineEnd => {
+                let y = (start.y + count - 1).min(self.buffer().line_count() - 1);
+                self.window.cursor = Cursor {
+                    x: self.buffer().line_len(y).saturating_sub(1),
+                    y,
+                };
+            }
+            Motion::FirstLine | Motion::LastLine => {
+                let last = self.buffer().line_count() - 1;
+                let y = match (explicit_count, motion) {
+                    (true, _) => (count - 1).min(last),
+                    (false, Motion::FirstLine) => 0,
+                    (false, _) => last,
+                };
+                self.window.cursor = Cursor { x: 0, y };
+            }
+        }
+
+        let Some(pending) = self.pending_operator.take() else {
+            self.register = None;
+            // `w` off the end of the buffer stops on the last char
+            let line_len = self.buffer().line_len(self.window.cursor.y);
+            if self.window.cursor.x >= line_len && matches!(motion, Motion::WordForward { .. }) {
+                self.window.cursor.x = line_len.saturating_sub(1);
+            }
+            // Like `l` at the end of the line, going nowhere counts as failing
+            if self.window.cursor == start && motion.moves_relative() {
+                self.fail();
+            }
+            if motion.is_jump() {
+                self.push_jump(start_buffer, start);
+            }
+            return;
+        };
+        let target = self.window.cursor;
+        self.window.cursor = start;
+
+        // Moving up or down past the buffer edge fails, so the operator does nothing
+        if target == start && matches!(motion, Motion::Up | Motion::Down) {
+            self.fail();
+            return;
+        }
+
+        let (from, to) = (start.min(target), start.max(target));
+        // An inclusive motion that ends past the line, like `$` on an empty one, can't take the newline
+        let kind = match motion.kind() {
+            MotionKind::Inclusive if to.x >= self.buffer().line_len(to.y) => MotionKind::Exclusive,
+            kind => kind,
+        };
+        let (range, linewise) = match kind {
+            MotionKind::Linewise => (self.region_bytes(from, to, true), true),
+            MotionKind::Inclusive => (self.region_bytes(from, to, false), false),
+            MotionKind::Exclusive => {
+                let buffer = self.buffer();
+                let start_byte = buffer.char_to_byte_position(from.y, from.x).unwrap_or(0);
+                // An exclusive motion to the start of a later line stops at the end of the one before
+                let end_byte = if to.x == 0 && to.y > from.y {
+                    buffer.line_offsets[to.y] - 1
+                } else {
+                    buffer
+                        .char_to_byte_position(to.y, to.x)
+                        .unwrap_or(buffer.text.len())
+                };
+                (start_byte..end_byte, false)
+            }
+        };
+
+        self.apply_operator(pending.operator, range, linewise);
+    }
+
+    /// The text object for the key after `i` or `a`.
+    fn text_object(&mut self, key: char, around: bool, count: usize) -> Option<TextObject> {
+        let cursor = self.window.cursor;
+        let buffer = &self.buffers[self.window.buffer];
+        let pos = buffer
+            .char_to_byte_position(cursor.y, cursor.x)
+            .unwrap_or(buffer.text.len());
+
+        match key {
+            'w' => textobject::word(buffer, pos, around, false, count),
+            'W' => textobject::word(buffer, pos, around, true, count),
+            's' => textobject::sentence(buffer, pos, around),
+            'p' => textobject::paragraph(buffer, cursor.y, around, count),
+            '"' | '\'' | '`' => textobject::quote(buffer, pos, key, around),
+            '(' | ')' | 'b' => textobject::bracket(buffer, pos, '(', ')', around, count),
+            '{' | '}' | 'B' => textobject::bracket(buffer, pos, '{', '}', around, count),
+            '[' | ']' => textobject::bracket(buffer, pos, '[', ']', around, count),
+            '<' | '>' => textobject::bracket(buffer, pos, '<', '>', around, count),
+            't' => textobject::tag(buffer, pos, around, count),
+            'f' | 'c' | 'a' | '/' => {
+                let name = match key {
+                    'f' => "function",
+                    'c' => "class",
+                    'a' => "parameter",
+                    _ => "comment",
+                };
+                let range = self
+                    .highlighter
+                    .text_object(&buffer.text, name, !around, pos)?;
+
+                // Inside a body means inside its braces
+                let text = &buffer.text[range.clone()];
+                let range = if !around && text.starts_with('{') && text.ends_with('}') {
+                    textobject::block_inner(&buffer.text, range.start, range.end - 1)
+                } else {
+                    range
+                };
+                Some(TextObject {
+                    range,
+                    linewise: false,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    fn handle_visual_mode(&mut self, event: KeyEvent) -> bool {
+        // The key after `g`, `"`, `m` and the like, or the char after `f`, is for normal mode
+        if let Some(KeyCode::Char(
+            'g' | '"' | 'f' | 'F' | 't' | 'T' | 'm' | '\'' | '`' | 'q' | '@',
+        )) = self.last_key
+        {
+            return self.handle_normal_mode(event);
+        }
+
+        if let Some(KeyCode::Char(prefix @ ('i' | 'a'))) = self.last_key {
+            self.last_key = None;
+            let count = self.motion_count.take().unwrap_or(1);
+            if let KeyCode::Char(object) = event.code {
+                self.select_text_object(object, prefix == 'a', count);
+            }
+            return false;
+        }
+
+        match event.code {
+            KeyCode::Esc => self.mode = Mode::Normal,
+            KeyCode::Char('v') => self.toggle_visual(Mode::Visual),
+            KeyCode::Char('V') => self.toggle_visual(Mode::VisualLine),
+            KeyCode::Char('d' | 'x') => self.operate_on_selection(Operator::Delete),
+            KeyCode::Char('c') => self.operate_on_selection(Operator::Change),
+            KeyCode::Char('y') => self.operate_on_selection(Operator::Yank),
+            KeyCode::Char('>') => self.operate_on_selection(Operator::Indent),
+            KeyCode::Char('<') => self.operate_on_selection(Operator::Outdent),
+            KeyCode::Char('i' | 'a') => self.last_key = Some(event.code),
+            // Not supported on a selection yet, don't let them fall through to normal mode
+            KeyCode::Char(
+                'p' | 'P' | ':' | 'r' | 'R' | 's' | 'S' | 'C' | 'o' | 'O' | 'I' | 'A' | 'J' | '~'
+                | '.',
+            ) => {}
+            // Everything else moves the cursor, which extends the selection
+            _ => return self.handle_normal_mode(event),
+        }
+
+        false
+    }
+
+    /// `viw` and the like: selects a text object.
+    fn select_text_object(&mut self, key: char, around: bool, count: usize) {
+        let Some(object) = self.text_object(key, around, count) else {
+            return;
+        };
+        if object.range.is_empty() {
+            return;
+        }
+
+        let buffer = &self.buffers[self.window.buffer];
+        let last_char = buffer.text[..object.range.end]
+            .char_indices()
+            .next_back()
+            .map_or(0, |(i, _)| i);
+        self.visual_anchor = buffer.byte_to_cursor(object.range.start);
+        self.window.cursor = buffer.byte_to_cursor(last_char);
+        if object.linewise {
+            self.mode = Mode::VisualLine;
+        }
+    }
+
+    /// Starts visual `mode`, switches between charwise and linewise, or leaves it.
+    fn toggle_visual(&mut self, mode: Mode) {
+        if self.mode == mode {
+            self.mode = Mode::Normal;
+            return;
+        }
+
+        if !matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            self.visual_anchor = self.window.cursor;
+        }
+        self.mode = mode;
+    }
+
+    /// Start and end of the visual selection in buffer order, both inclusive.
+    fn selection(&self) -> Option<(Cursor, Cursor)> {
+        if !matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            return None;
+        }
+
+        let (anchor, cursor) = (self.visual_anchor, self.window.cursor);
+        Some((anchor.min(cursor), anchor.max(cursor)))
+    }
+
+    /// Byte range covered by `start..=end`, or by their whole lines when `linewise`.
+    fn region_bytes(&self, start: Cursor, end: Cursor, linewise: bool) -> Range<usize> {
+        let buffer = self.buffer();
+
+        if linewise {
+            let from = buffer.line_offsets[start.y];
+            let to = buffer
+                .line_offsets
+                .get(end.y + 1)
+                .copied()
+                .unwrap_or(buffer.text.len());
+            return from..to;
+        }
+
+        let from = buffer.char_to_byte_position(start.y, start.x).unwrap_or(0);
+        let to = buffer
+            .char_to_byte_position(end.y, end.x)
+            .unwrap_or(buffer.text.len());
+        // The end is inclusive, past the end of a line that takes the newline
+        let to = to + buffer.text[to..].chars().next().map_or(0, char::len_utf8);
+
+        from..to
+    }
+
+    /// Applies `operator` to `range` of the buffer text and keeps what it
+    /// covered in the unnamed register.
+    fn apply_operator(&mut self, operator: Operator, range: Range<usize>, linewise: bool) {
+        if let Operator::Indent | Operator::Outdent = operator {
+            // Shifting always works on whole lines
+            let first = self.buffer().byte_to_cursor(range.start).y;
+            let last = self
+                .buffer()
+                .byte_to_cursor(range.end.saturating_sub(1).max(range.start))
+                .y;
+            self.shift_lines(first..=last, operator == Operator::Outdent);
+            return;
+        }
+
+        let mut text = self.buffer().text[range.clone()].to_string();
+        if linewise && !text.ends_with('\n') {
+            text.push('\n');
+        }
+        let name = self.register.take().unwrap_or(UNNAMED);
+        self.registers.set(name, Register { text, linewise });
+        let start = self.buffer().byte_to_cursor(range.start);
+
+        match operator {
+            Operator::Yank => self.window.cursor = start,
+            Operator::Delete => {
+                let mut range = range;
+                // Deleting the last lines takes the newline before them instead
+                if linewise && range.end == self.buffer().text.len() && range.start > 0 {
+                    range.start -= 1;
+                }
+                self.buffer_mut().delete(range);
+
+                let y = start.y.min(self.buffer().line_count() - 1);
+                self.window.cursor = if linewise { Cursor { x: 0, y } } else { start };
+            }
+            Operator::Change => {
+                let mut range = range;
+                // Changing lines leaves an empty line to type into
+                if linewise && self.buffer().text[range.clone()].ends_with('\n') {
+                    range.end -= 1;
+                }
+                self.buffer_mut().delete(range);
+                self.window.cursor = start;
+                self.start_insert(InsertEntry::Before, 1);
+            }
+            Operator::Indent | Operator::Outdent => {}
+        }
+    }
+
+    /// `dd`, `cc`, `>>` and the like: applies `operator` to `count` lines from the cursor's.
+    fn operate_on_lines(&mut self, operator: Operator, count: usize) {
+        let y = self.window.cursor.y;
+        let last = (y + count - 1).min(self.buffer().line_count() - 1);
+        let range = self.region_bytes(Cursor { x: 0, y }, Cursor { x: 0, y: last }, true);
+        self.apply_operator(operator, range, true);
+    }
+
+    /// Makes a change and remembers it for `.`. A change that goes on in insert
+    /// mode is only remembered once that ends, with the typed text.
+    fn run_change(&mut self, action: Action, count: Option<usize>) {
+        if !self.check_modifiable() {
+            return;
+        }
+        let n = count.unwrap_or(1);
+        let register = self.register;
+        match action {
+            Action::Insert(entry) => self.start_insert(entry, n),
+            Action::Operator { operator, target } => self.run_operator(operator, target, count),
+            Action::Put { before } => self.put(before, n),
+            Action::ReplaceChar(ch) => {
+                if !self.replace_chars(ch, n) {
+                    self.fail();
+                    return;
+                }
+            }
+            Action::Join { spaces } => self.join_lines(n, spaces),
+            Action::ToggleCase => self.toggle_case(n),
+        }
+
+        self.register = None;
+
+        let change = Change {
+            action,
+            count,
+            register,
+            inserted: Vec::new(),
+        };
+        match &mut self.insert {
+            Some(session) if matches!(self.mode, Mode::Insert | Mode::Replace) => {
+                session.change = Some(change);
+            }
+            _ => self.last_change = Some(change),
+        }
+    }
+
+    /// `.`: makes the last change again, a count replaces the one it had.
+    fn repeat_change(&mut self, count: Option<usize>) {
+        let Some(change) = self.last_change.clone() else {
+            return;
+        };
+
+        self.register = change.register;
+        self.run_change(change.action, count.or(change.count));
+        if matches!(self.mode, Mode::Insert | Mode::Replace) {
+            for key in change.inserted {
+                self.handle_insert_mode(key);
+            }
+            self.finish_insert();
+        }
+    }
+
+    /// Enters insert mode, or replace mode for `R`, the way `entry` does.
+    fn start_insert(&mut self, entry: InsertEntry, count: usize) {
+        let y = self.window.cursor.y;
+        let line_len = self.buffer().line_len(y);
+        match entry {
+            InsertEntry::Before | InsertEntry::Replace => {}
+            InsertEntry::After => self.window.cursor.x = (self.window.cursor.x + 1).min(line_len),
+            InsertEntry::LineStart => self.window.cursor.x = self.first_non_blank(y),
+            InsertEntry::LineEnd => self.window.cursor.x = line_len,
+            InsertEntry::OpenBelow | InsertEntry::OpenAbove => {
+                self.open_line(entry == InsertEntry::OpenAbove)
+            }
+        }
+
+        self.mode = if entry == InsertEntry::Replace {
+            Mode::Replace
+        } else {
+            Mode::Insert
+        };
+        self.insert = Some(InsertSession {
+            entry,
+            count,
+            keys: Vec::new(),
+            replaced: Vec::new(),
+            change: None,
+        });
+    }
+
+    /// Leaves insert mode, typing the text again for a count.
+    fn finish_insert(&mut self) {
+        let Some(session) = self.insert.take() else {
+            self.mode = Mode::Normal;
+            return;
+        };
+
+        for _ in 1..session.count {
+            if let InsertEntry::OpenBelow | InsertEntry::OpenAbove = session.entry {
+                self.open_line(session.entry == InsertEntry::OpenAbove);
+            }
+            for &key in &session.keys {
+                self.handle_insert_mode(key);
+            }
+        }
+
+        let Cursor { x, y } = self.window.cursor;
+        if let Some(pos) = self.buffer().char_to_byte_position(y, x) {
+            self.buffer_mut().marks.insert(Mark::Named('^'), pos);
+        }
+
+        self.mode = Mode::Normal;
+        if let Some(mut change) = session.change {
+            change.inserted = session.keys;
+            self.last_change = Some(change);
+        }
+    }
+
+    /// Opens an empty line below or above the cursor's and moves onto it.
+    fn open_line(&mut self, above: bool) {
+        let y = self.window.cursor.y;
+        if above {
+            let offset = self.buffer().line_offsets[y];
+            self.buffer_mut().insert(offset, "\n");
+            self.window.cursor = Cursor { x: 0, y };
+        } else {
+            let end = self.buffer().line_offsets[y] + self.buffer().get_line(y).unwrap_or("").len();
+            self.buffer_mut().insert(end, "\n");
+            self.window.cursor = Cursor { x: 0, y: y + 1 };
+        }
+    }
+
+    /// Column of the first char on `line` that isn't white space.
+    fn first_non_blank(&self, line: usize) -> usize {
+        let text = self.buffer().get_line(line).unwrap_or("");
+        text.chars()
+            .position(|ch| !ch.is_whitespace())
+            .unwrap_or(text.chars().count())
+    }
+
+    /// `r`: replaces `count` chars with `ch`, fails if the line is too short.
+    /// A line break replaces them all with a single one.
+    fn replace_chars(&mut self, ch: char, count: usize) -> bool {
+        let Cursor { x, y } = self.window.cursor;
+        if x + count > self.buffer().line_len(y) {
+            return false;
+        }
+
+        if ch == '\n' {
+            self.replace_cols(y, x, x + count, "\n");
+            self.window.cursor = Cursor { x: 0, y: y + 1 };
+        } else {
+            self.replace_cols(y, x, x + count, &ch.to_string().repeat(count));
+            self.window.cursor.x = x + count - 1;
+        }
+        true
+    }
+
+    /// `J` and `gJ`: joins `count` lines, at least two. `J` drops the next
+    /// line's indent and puts a space between the lines.
+    fn join_lines(&mut self, count: usize, spaces: bool) {
+        let y = self.window.cursor.y;
+        for _ in 0..count.max(2) - 1 {
+            if y + 1 >= self.buffer().line_count() {
+                self.fail();
+                break;
+            }
+
+            let line = self.buffer().get_line(y).unwrap_or("");
+            let x = line.chars().count();
+            let newline = self.buffer().line_offsets[y] + line.len();
+            let next = self.buffer().get_line(y + 1).unwrap_or("");
+            let (end, separator) = if spaces {
+                let trimmed = next.trim_start();
+                let separator = if line.is_empty()
+                    || line.ends_with(char::is_whitespace)
+                    || trimmed.is_empty()
+                    || trimmed.starts_with(')')
+                {
+                    ""
+                } else {
+                    " "
+                };
+                (newline + 1 + next.len() - trimmed.len(), separator)
+            } else {
+                (newline + 1, "")
+            };
+
+            self.buffer_mut().delete(newline..end);
+            self.buffer_mut().insert(newline, separator);
+            self.window.cursor.x = x;
+        }
+    }
+
+    /// `~`: switches the case of `count` chars and moves past them.
+    fn toggle_case(&mut self, count: usize) {
+        let Cursor { x, y } = self.window.cursor;
+        let line_len = self.buffer().line_len(y);
+        let end = (x + count).min(line_len);
+        let toggled: String = self
+            .buffer()
+            .get_line(y)
+            .unwrap_or("")
+            .chars()
+            .skip(x)
+            .take(end - x)
+            .map(|ch| {
+                if ch.is_lowercase() {
+                    ch.to_uppercase().collect::<String>()
+                } else {
+                    ch.to_lowercase().collect()
+                }
+            })
+            .collect();
+
+        self.replace_cols(y, x, end, &toggled);
+        self.window.cursor.x = end.min(line_len.saturating_sub(1));
+    }
+
+    /// `>` and `<`: shifts `lines` by `shiftwidth`, empty lines stay empty.
+    fn shift_lines(&mut self, lines: std::ops::RangeInclusive<usize>, outdent: bool) {
+        let first = *lines.start();
+        let options = &self.buffer().options;
+        let (shift, tabstop, expandtab) =
+            (options.shift_width(), options.tabstop, options.expandtab);
+
+        for y in lines {
+            let indent = self.first_non_blank(y);
+            if !outdent && indent == self.buffer().line_len(y) {
+                continue;
+            }
+
+            let width = self.buffer().visual_col(y, indent);
+            let width = if outdent {
+                width.saturating_sub(shift)
+            } else {
+                width + shift
+            };
+            let fill = if expandtab {
+                " ".repeat(width)
+            } else {
+                whitespace_fill(0, width, tabstop)
+            };
+            self.replace_cols(y, 0, indent, &fill);
+        }
+
+        self.window.cursor = Cursor {
+            x: self.first_non_blank(first),
+            y: first,
+        };
+    }
+
+    fn operate_on_selection(&mut self, operator: Operator) {
+        let selection = self.selection();
+        let linewise = self.mode == Mode::VisualLine;
+        self.mode = Mode::Normal;
+        if operator != Operator::Yank && !self.check_modifiable() {
+            return;
+        }
+
+        if let Some((start, end)) = selection {
+            let range = self.region_bytes(start, end, linewise);
+            self.apply_operator(operator, range, linewise);
+        }
+    }
+
+    /// `p` and `P`: puts a register, the unnamed one unless another was
+    /// named, after or before the cursor.
+    fn put(&mut self, before: bool, count: usize) {
+        let name = self.register.take().unwrap_or(UNNAMED);
+        let Some(register) = self.registers.get(name).cloned() else {
+            self.error(format!("Register {} is empty", name));
+            return;
+        };
+        let text = register.text.repeat(count);
+
+        if register.linewise {
+            let y = if before {
+                self.window.cursor.y
+            } else {
+                self.window.cursor.y + 1
+            };
+            if let Some(&offset) = self.buffer().line_offsets.get(y) {
+                self.buffer_mut().insert(offset, &text);
+            } else {
+                // Below the last line there is no newline to put the lines after
+                let end = self.buffer().text.len();
+                let text = format!("\n{}", text.strip_suffix('\n').unwrap_or(&text));
+                self.buffer_mut().insert(end, &text);
+            }
+            self.window.cursor = Cursor { x: 0, y };
+            return;
+        }
+
+        let x = if before {
+            self.window.cursor.x
+        } else {
+            (self.window.cursor.x + 1).min(self.buffer().line_len(self.window.cursor.y))
+        };
+        if let Some(byte_pos) = self.buffer().char_to_byte_position(self.window.cursor.y, x) {
+            self.buffer_mut().insert(byte_pos, &text);
+
+            // The cursor ends up on the last char that was put
+            let lines = text.split('\n').count() - 1;
+            let last_line_len = text.rsplit('\n').next().unwrap_or("").chars().count();
+            self.window.cursor = if lines == 0 {
+                Cursor {
+                    x: x + last_line_len.saturating_sub(1),
+                    y: self.window.cursor.y,
+                }
+            } else {
+                Cursor {
+                    x: last_line_len.saturating_sub(1),
+                    y: self.window.cursor.y + lines,
+                }
+            };
+        }
+    }
+
+    fn handle_insert_mode(&mut self, key: KeyCode) -> bool {
+        if key == KeyCode::Esc {
+            self.finish_insert();
+            return false;
+        }
+        if let Some(session) = &mut self.insert {
+            session.keys.push(key);
+        }
+
+        if self.mode == Mode::Replace {
+            match key {
+                KeyCode::Char(c) => return self.replace_typed(c),
+                KeyCode::Tab => return self.replace_typed('\t'),
+                KeyCode::Backspace => return self.undo_replace(),
+                _ => {}
+            }
+        }
+
+        match key {
+            KeyCode::Char(c) => self.insert_char(c),
+            KeyCode::Tab => self.insert_tab(),
+            KeyCode::Backspace => self.delete_char_before_cursor(),
+            KeyCode::Delete => self.delete_char_at_cursor(),
+            KeyCode::Enter => self.insert_newline(),
+            KeyCode::Left => self.move_cursor_left(),
+            KeyCode::Right => self.move_cursor_right(),
+            KeyCode::Up => self.move_cursor_up(),
+            KeyCode::Down => self.move_cursor_down(),
+            _ => {}
+        }
+        false
+    }
+
+    /// A char typed in replace mode takes the place of the one under the cursor.
+    fn replace_typed(&mut self, ch: char) -> bool {
+        let Cursor { x, y } = self.window.cursor;
+        let original = self
+            .buffer()
+            .get_line(y)
+            .and_then(|line| line.chars().nth(x));
+        match original {
+            Some(_) => {
+                self.replace_cols(y, x, x + 1, &ch.to_string());
+                self.window.cursor.x += 1;
+            }
+            None => self.insert_char(ch),
+        }
+        if let Some(session) = &mut self.insert {
+            session.replaced.push(original);
+        }
+        false
+    }
+
+    /// Backspace in replace mode puts back what the last typed char replaced.
+    fn undo_replace(&mut self) -> bool {
+        match self
+            .insert
+            .as_mut()
+            .and_then(|session| session.replaced.pop())
+        {
+            Some(Some(original)) => {
+                self.window.cursor.x -= 1;
+                let Cursor { x, y } = self.window.cursor;
+                self.replace_cols(y, x, x + 1, &original.to_string());
+            }
+            Some(None) => self.delete_char_before_cursor(),
+            // Before where replacing started there is nothing to put back
+            None => self.move_cursor_left(),
+        }
+        false
+    }
+
+    fn execute_command(&mut self, command: &str) -> anyhow::Result<()> {
+        let command = command.trim();
+
+        let (range, rest) = split_range(command);
+        if let Some(args) = rest.strip_prefix('s')
+            && args.starts_with(|ch: char| !ch.is_alphanumeric())
+        {
+            return self.substitute(range, args);
+        }
+
+        match command {
+            "w" | "write" => self.save_buffer(None, false)?,
+            "w!" | "write!" => self.save_buffer(None, true)?,
+            // save da buffer
+            cmd if cmd.starts_with("w ") || cmd.starts_with("w! ") => {
+                let (force, filename) = cmd.split_once(' ').unwrap_or_default();
+                let filename = filename.trim();
+                self.save_buffer((!filename.is_empty()).then_some(filename), force == "w!")?;
+            }
+            "e!" | "edit!" => self.reload_buffer()?,
+            cmd if cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|c| c == "vie" || c == "view") =>
+            {
+                let (_, filename) = cmd.split_once(' ').unwrap_or_default();
+                if !filename.trim().is_empty() {
+                    self.edit_file(filename.trim())?;
+                }
+                self.buffer_mut().options.readonly = true;
+            }
+            "checkt" | "checktime" => self.checktime(),
+            cmd if cmd.starts_with("e ") => {
+                let filename = cmd[2..].trim();
+                if !filename.is_empty() {
+                    self.edit_file(filename)?;
+                }
+            }
+            "bn" | "bnext" => {
+                self.switch_buffer((self.window.buffer + 1) % self.buffers.len());
+            }
+            "bp" | "bprevious" => {
+                let count = self.buffers.len();
+                self.switch_buffer((self.window.buffer + count - 1) % count);
+            }
+            cmd if cmd.starts_with("b ") || cmd.starts_with("buffer ") => {
+                let (_, number) = cmd.split_once(' ').unwrap_or_default();
+                let index = number
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .filter(|&n| n >= 1 && n <= self.buffers.len())
+                    .ok_or_else(|| anyhow::anyhow!("No such buffer: {}", number.trim()))?;
+                self.switch_buffer(index - 1);
+            }
+            "q" | "quit" | "q!" | "quit!" if self.windows.is_empty() => self.close_tab()?,
+            "q" | "quit" | "q!" | "quit!" | "clo" | "close" => self.close_window()?,
+            "on" | "only" => self.only_window(),
+            cmd if cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|c| c == "sp" || c == "split") =>
+            {
+                let (_, filename) = cmd.split_once(' ').unwrap_or_default();
+                self.split_window(SplitDirection::Horizontal, filename.trim())?;
+            }
+            cmd if cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|c| c == "vs" || c == "vsplit") =>
+            {
+                let (_, filename) = cmd.split_once(' ').unwrap_or_default();
+                self.split_window(SplitDirection::Vertical, filename.trim())?;
+            }
+            cmd if cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|c| c == "tabnew" || c == "tabe" || c == "tabedit") =>
+            {
+                let (_, filename) = cmd.split_once(' ').unwrap_or_default();
+                self.new_tab(filename.trim())?;
+            }
+            cmd if cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|c| c == "tabn" || c == "tabnext") =>
+            {
+                let (_, number) = cmd.split_once(' ').unwrap_or_default();
+                let index = match number.trim() {
+                    "" => (self.current_tab + 1) % self.tab_count(),
+                    number => {
+                        number
+                            .parse::<usize>()
+                            .ok()
+                            .filter(|&n| n >= 1 && n <= self.tab_count())
+                            .ok_or_else(|| anyhow::anyhow!("No such tab page: {}", number))?
+                            - 1
+                    }
+                };
+                self.switch_tab(index);
+            }
+            "tabp" | "tabprevious" | "tabN" | "tabNext" => {
+                let count = self.tab_count();
+                self.switch_tab((self.current_tab + count - 1) % count);
+            }
+            "tabc" | "tabclose" => self.close_tab()?,
+            cmd if cmd.starts_with("set ") || cmd.starts_with("se ") => {
+                let (_, args) = cmd.split_once(' ').unwrap_or_default();
+                for arg in args.split_whitespace() {
+                    self.set_option(arg)?;
+                }
+            }
+            cmd if cmd
+                .split_whitespace()
+                .next()
+                .is_some_and(|c| c == "so" || c == "source") =>
+            {
+                let (_, path) = cmd.split_once(' ').unwrap_or_default();
+                if path.trim().is_empty() {
+                    anyhow::bail!("Argument required");
+                }
+                self.source(Path::new(path.trim()))?;
+            }
+            "$" => self.go_to_line(self.buffer().line_count()),
+            cmd if cmd.parse::<usize>().is_ok() => self.go_to_line(cmd.parse()?),
+            cmd if cmd.starts_with('/') => self.search_line(&cmd[1..])?,
+            _ => anyhow::bail!("Not an editor command: {}", command),
+        }
+
+        Ok(())
+    }
+
+    /// `:[range]s/pattern/replacement/[flags]`: replaces matches of `pattern`
+    /// on the lines in `range`, the cursor line without one. The cursor ends
+    /// up on the last line that changed.
+    fn substitute(&mut self, range: &str, args: &str) -> anyhow::Result<()> {
+        self.modifiable()?;
+        let substitute = Substitute::parse(args)?;
+        let (first, last) = self.line_range(range)?;
+
+        let mut substitutions = 0;
+        let mut changed_lines = Vec::new();
+        let buffer = &mut self.buffers[self.window.buffer];
+        for y in first..=last {
+            let line = buffer.get_line(y).unwrap_or("");
+            let Some((replaced, count)) = substitute.apply(line) else {
+                continue;
+            };
+            let start = buffer.line_offsets[y];
+            let end = start + line.len();
+            buffer.delete(start..end);
+            buffer.insert(start, &replaced);
+            substitutions += count;
+            changed_lines.push(y);
+        }
+
+        let Some(&y) = changed_lines.last() else {
+            anyhow::bail!("Pattern not found: {}", substitute.regex.as_str());
+        };
+        self.window.cursor = Cursor {
+            x: self.first_non_blank(y),
+            y,
+        };
+        if substitutions > 1 {
+            self.message = Some(Message::Info(format!(
+                "{} substitutions on {} lines",
+                substitutions,
+                changed_lines.len()
+            )));
+        }
+        Ok(())
+    }
+
+    /// The first and last line, counting from zero, of an ex range like `%`,
+    /// `.`, `$`, `3` or `3,$`. An empty range is the cursor line.
+    fn line_range(&self, range: &str) -> anyhow::Result<(usize, usize)> {
+        // After a final newline there is no line of the file, only the empty end of the text
+        let buffer = self.buffer();
+        let last = if buffer.text.ends_with('\n') {
+            buffer.line_count() - 2
+        } else {
+            buffer.line_count() - 1
+        };
+        let line = |spec: &str| match spec {
+            "" | "." => Ok(self.window.cursor.y),
+            "$" => Ok(last),
+            number => number
+                .parse::<usize>()
+                .ok()
+                .filter(|&number| number >= 1)
+                .map(|number| (number - 1).min(last))
+                .ok_or_else(|| anyhow::anyhow!("Invalid range: {}", range)),
+        };
+
+        if range == "%" {
+            return Ok((0, last));
+        }
+        let (first, last) = match range.split_once(',') {
+            Some((first, last)) => (line(first)?, line(last)?),
+            None => (line(range)?, line(range)?),
+        };
+        if first > last {
+            anyhow::bail!("Backwards range: {}", range);
+        }
+        Ok((first, last))
+    }
+
+    /// `:N` and `:$`: goes to the first non-blank of line `line`, counting from one.
+    fn go_to_line(&mut self, line: usize) {
+        let start = self.window.cursor;
+        let y = line.saturating_sub(1).min(self.buffer().line_count() - 1);
+        self.window.cursor = Cursor {
+            x: self.first_non_blank(y),
+            y,
+        };
+        self.push_jump(self.window.buffer, start);
+    }
+
+    /// `:/pattern`: goes to the next line matching `pattern`, wrapping around
+    /// the end of the buffer. A trailing `/` is left out of the pattern.
+    fn search_line(&mut self, pattern: &str) -> anyhow::Result<()> {
+        let pattern = pattern.strip_suffix('/').unwrap_or(pattern);
+        let regex =
+            Regex::new(pattern).map_err(|_| anyhow::anyhow!("Invalid pattern: {}", pattern))?;
+
+        let start = self.window.cursor;
+        let buffer = self.buffer();
+        let count = buffer.line_count();
+        let found = (1..=count)
+            .map(|offset| (start.y + offset) % count)
+            .find_map(|y| {
+                let line = buffer.get_line(y).unwrap_or("");
+                let found = regex.find(line)?;
+                Some(Cursor {
+                    x: line[..found.start()].chars().count(),
+                    y,
+                })
+            })
+            .ok_or_else(|| anyhow::anyhow!("Pattern not found: {}", pattern))?;
+
+        self.window.cursor = found;
+        self.push_jump(self.window.buffer, start);
+        Ok(())
+    }
+
+    /// `:source`: runs the ex commands in the file at `path`, one per line.
+    /// Empty lines and ones starting with `"` are skipped. A failing command
+    /// doesn't stop the rest, the first error is returned at the end.
+    fn source(&mut self, path: &Path) -> anyhow::Result<()> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Can't read {}: {}", path.display(), e))?;
+
+        let mut first_error = None;
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            let command = line.strip_prefix(':').unwrap_or(line);
+            if command.is_empty() || command.starts_with('"') {
+                continue;
+            }
+            if let Err(e) = self.execute_command(command)
+                && first_error.is_none()
+            {
+                first_error = Some(anyhow::anyhow!(
+                    "{} line {}: {}",
+                    path.display(),
+                    number + 1,
+                    e
+                ));
+            }
+        }
+
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Opens `filename` in a new buffer, or switches to it if it is already open.
+    fn edit_file(&mut self, filename: &str) -> anyhow::Result<()> {
+        let index = self.open_buffer(filename)?;
+        self.switch_buffer(index);
+
+        Ok(())
+    }
+
+    /// Loads `filename` into a new buffer unless it is already open, returns its index.
+    fn open_buffer(&mut self, filename: &str) -> anyhow::Result<usize> {
+        // However the name is written, one file gets one buffer
+        let file = canonical_path(Path::new(filename));
+        if let Some(index) = self.buffers.iter().position(|b| {
+            b.path
+                .as_deref()
+                .is_some_and(|path| canonical_path(path) == file)
+        }) {
+            return Ok(index);
+        }
+
+        let buffer = match self.load_file(filename) {
+            Ok(buffer) => buffer,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => self.new_file_buffer(filename),
+            Err(e) => anyhow::bail!("Can't open {}: {}", filename, e),
+        };
+        self.buffers.push(buffer);
+        self.attach_swap(self.buffers.len() - 1);
+        self.watch_file(self.buffers.len() - 1);
+        Ok(self.buffers.len() - 1)
+    }
+
+    /// Where swap files go: the `directory` option, or the default dir.
+    pub fn swap_dir(&self) -> PathBuf {
+        if self.options.directory.is_empty() {
+            swap::default_dir()
+        } else {
+            PathBuf::from(&self.options.directory)
+        }
+    }
+
+    /// Picks the swap file for buffer `index`, or asks what to do about the
+    /// one that is already there.
+    fn attach_swap(&mut self, index: usize) {
+        if !self.options.swapfile || self.buffers[index].listing || self.buffers[index].large {
+            return;
+        }
+        let Some(path) = &self.buffers[index].path else {
+            return;
+        };
+
+        let swap_file = swap::swap_path(&self.swap_dir(), path);
+        match Swap::read(&swap_file) {
+            // Names of different files can make the same swap file name
+            Ok(swap) if canonical_path(&swap.path) != canonical_path(path) => {
+                let message = format!(
+                    "{} belongs to {}, no swap file for {}",
+                    swap_file.display(),
+                    swap.path.display(),
+                    self.buffers[index].buffer_name
+                );
+                self.error(message);
+            }
+            Ok(swap) => self.ask(Prompt::Swap {
+                buffer: index,
+                swap_file,
+                swap,
+            }),
+            Err(_) => self.buffers[index].swap_file = Some(swap_file),
+        }
+    }
+
+    /// Asks `prompt` now, or once the prompts before it are answered.
+    fn ask(&mut self, prompt: Prompt) {
+        if self.prompt.is_some() {
+            self.queued_prompts.push_back(prompt);
+        } else {
+            self.prompt = Some(prompt);
+            self.show_prompt();
+        }
+    }
+
+    fn show_prompt(&mut self) {
+        let text = match &self.prompt {
+            None => return,
+            Some(Prompt::Swap { buffer, swap, .. }) => {
+                let running = if swap.is_running() {
+                    ", still running"
+                } else {
+                    ""
+                };
+                format!(
+                    "Swap file found for {} (pid {}{}): [r]ecover, [o]pen read-only, [d]elete it, [e]dit anyway",
+                    self.buffers[*buffer].buffer_name, swap.pid, running
+                )
+            }
+            Some(Prompt::Reload { buffer }) => format!(
+                "{} changed on disk and in the buffer: [l]oad it from disk, [k]eep the buffer",
+                self.buffers[*buffer].buffer_name
+            ),
+        };
+        self.message = Some(Message::Error(text));
+    }
+
+    fn answer_prompt(&mut self, key: KeyCode) {
+        match self.prompt.take() {
+            Some(Prompt::Swap {
+                buffer,
+                swap_file,
+                swap,
+            }) => self.answer_swap_prompt(key, buffer, swap_file, swap),
+            Some(Prompt::Reload { buffer }) => self.answer_reload_prompt(key, buffer),
+            None => {}
+        }
+        // Unless that wasn't an answer and it is being asked again
+        if self.prompt.is_none()
+            && let Some(next) = self.queued_prompts.pop_front()
+        {
+            self.prompt = Some(next);
+            self.show_prompt();
+        }
+    }
+
+    fn answer_swap_prompt(&mut self, key: KeyCode, index: usize, swap_file: PathBuf, swap: Swap) {
+        let buffer = &mut self.buffers[index];
+        self.message = match key {
+            KeyCode::Char('r') => {
+                buffer.update_text(swap.text);
+                buffer.modified = true;
+                buffer.swap_file = Some(swap_file);
+                self.clamp_window_cursor();
+                Some(Message::Info(
+                    "Recovered from the swap file, :w to keep it".to_string(),
+                ))
+            }
+            KeyCode::Char('o') => {
+                buffer.options.readonly = true;
+                None
+            }
+            KeyCode::Char('d') => match std::fs::remove_file(&swap_file) {
+                Ok(()) => {
+                    buffer.swap_file = Some(swap_file);
+                    None
+                }
+                Err(e) => Some(Message::Error(format!("Can't delete swap file: {}", e))),
+            },
+            // The other swap file stays as it is, this buffer goes without one
+            KeyCode::Char('e') | KeyCode::Esc => Some(Message::Info(format!(
+                "No swap file for {}, {} is left alone",
+                buffer.buffer_name,
+                swap_file.display()
+            ))),
+            _ => {
+                self.prompt = Some(Prompt::Swap {
+                    buffer: index,
+                    swap_file,
+                    swap,
+                });
+                self.show_prompt();
+                return;
+            }
+        };
+    }
+
+    fn answer_reload_prompt(&mut self, key: KeyCode, index: usize) {
+        let Some(path) = self.buffers[index].path.clone() else {
+            return;
+        };
+        let large = self.buffers[index].large;
+        let result = match key {
+            KeyCode::Char('l') if large => self.reload_large_buffer(index, &path),
+            KeyCode::Char('l') => DiskState::read(&path)
+                .map(|(state, contents)| self.load_from_disk(index, contents, state)),
+            // Stop asking about this version, a later change asks again
+            KeyCode::Char('k') | KeyCode::Esc => {
+                let buffer = &mut self.buffers[index];
+                if large {
+                    if let (Some(disk), Ok(metadata)) = (buffer.disk, std::fs::metadata(&path)) {
+                        buffer.disk = Some(disk.with_metadata(&metadata));
+                    }
+                } else if let Ok((state, _)) = DiskState::read(&path) {
+                    buffer.disk = Some(state);
+                }
+                Ok(())
+            }
+            _ => {
+                self.prompt = Some(Prompt::Reload { buffer: index });
+                self.show_prompt();
+                return;
+            }
+        };
+        if let Err(e) = result {
+            self.error(format!("Can't read {}: {}", path.display(), e));
+        }
+    }
+
+    /// Looks whether the file of buffer `index` changed on disk. An unmodified
+    /// buffer is reloaded, for a modified one the user is asked. A large file
+    /// counts as changed when its time or size did, it isn't read to compare.
+    fn check_buffer(&mut self, index: usize) {
+        let buffer = &self.buffers[index];
+        let (Some(path), Some(disk)) = (buffer.path.clone(), buffer.disk) else {
+            return;
+        };
+        if buffer.loading.is_some() {
+            return;
+        }
+
+        match std::fs::metadata(&path) {
+            Ok(metadata) if !disk.looks_changed(&metadata) => return,
+            Ok(_) if buffer.large => {
+                if buffer.modified {
+                    self.ask_reload(index);
+                } else {
+                    let message = format!("{} changed on disk, reloading", buffer.buffer_name);
+                    match self.reload_large_buffer(index, &path) {
+                        Ok(()) => self.message = Some(Message::Info(message)),
+                        Err(e) => self.error(format!("Can't read {}: {}", path.display(), e)),
+                    }
+                }
+                return;
+            }
+            Ok(_) => {}
+            Err(_) => {
+                // Only say so once, the buffer is all that's left of the file
+                let name = buffer.buffer_name.clone();
+                self.buffers[index].disk = None;
+                self.error(format!("{} is no longer on disk", name));
+                return;
+            }
+        }
+        let Ok((state, contents)) = DiskState::read(&path) else {
+            return;
+        };
+        // Touched but still the same text
+        if state.hash == disk.hash {
+            self.buffers[index].disk = Some(state);
+            return;
+        }
+
+        if self.buffers[index].modified {
+            self.ask_reload(index);
+        } else {
+            let name = &self.buffers[index].buffer_name;
+            self.message = Some(Message::Info(format!("{} changed on disk, reloaded", name)));
+            // Which a warning about the new contents takes the place of
+            self.load_from_disk(index, contents, state);
+        }
+    }
+
+    /// Asks whether to load the changed file of buffer `index`. Not while
+    /// something else is asked, a later check asks again.
+    fn ask_reload(&mut self, index: usize) {
+        if self.prompt.is_none() {
+            self.prompt = Some(Prompt::Reload { buffer: index });
+            self.show_prompt();
+        }
+    }
+
+    /// Reads the file of large buffer `index` again in the background, its
+    /// text is replaced and the changes to it dropped once that's done.
+    fn reload_large_buffer(&mut self, index: usize, path: &Path) -> io::Result<()> {
+        let size = std::fs::metadata(path)?.len();
+        let loading = Loading::start(path.to_path_buf(), size);
+        if !self.headless {
+            self.buffers[index].loading = Some(loading);
+            return Ok(());
+        }
+
+        let loaded = loading.wait()?;
+        let buffer = &mut self.buffers[index];
+        if let Some(warning) = Self::fill_large_buffer(buffer, loaded) {
+            self.message = Some(warning);
+        }
+        Self::apply_editorconfig(&self.options, buffer, false);
+        if index == self.window.buffer {
+            self.clamp_window_cursor();
+        }
+        Ok(())
+    }
+
+    /// Replaces the text of buffer `index` with `contents` as read from its file.
+    fn load_from_disk(&mut self, index: usize, contents: Vec<u8>, state: DiskState) {
+        let decoded = format::decode(&contents);
+        let buffer = &mut self.buffers[index];
+        buffer.update_text(decoded.text);
+        buffer.options.fileformat = decoded.format;
+        buffer.options.fileencoding = decoded.encoding;
+        Self::apply_editorconfig(&self.options, buffer, false);
+        if let Some(warning) = Self::decode_warning(buffer, false, decoded.lossy) {
+            self.message = Some(warning);
+        }
+        buffer.modified = false;
+        buffer.swap_stale = false;
+        buffer.disk = Some(state);
+        if let Some(swap_file) = &buffer.swap_file {
+            let _ = std::fs::remove_file(swap_file);
+        }
+        if index == self.window.buffer {
+            self.clamp_window_cursor();
+        }
+    }
+
+    /// `:checktime`, also done when the terminal gets focus back: looks for
+    /// files changed on disk by other programs.
+    pub fn checktime(&mut self) {
+        for index in 0..self.buffers.len() {
+            self.check_buffer(index);
+        }
+    }
+
+    /// `:e!`: throws away the changes to the buffer and loads its file again.
+    fn reload_buffer(&mut self) -> anyhow::Result<()> {
+        let path = self
+            .buffer()
+            .path
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("No file name"))?;
+        if self.buffer().listing {
+            let text = Self::list_directory(&path)
+                .map_err(|e| anyhow::anyhow!("Can't read {}: {}", path.display(), e))?;
+            self.buffers[self.window.buffer].update_text(text);
+            self.buffer_mut().modified = false;
+            self.clamp_window_cursor();
+            return Ok(());
+        }
+        if self.buffer().large {
+            return self
+                .reload_large_buffer(self.window.buffer, &path)
+                .map_err(|e| anyhow::anyhow!("Can't read {}: {}", path.display(), e));
+        }
+        let (state, contents) = DiskState::read(&path)
+            .map_err(|e| anyhow::anyhow!("Can't read {}: {}", path.display(), e))?;
+        self.load_from_disk(self.window.buffer, contents, state);
+
+        Ok(())
+    }
+
+    /// Works out the brackets of buffer `index` again if its text changed.
+    /// Only Rust files are parsed, and large files are left without.
+    fn update_brackets(&mut self, index: usize) {
+        let buffer = &mut self.buffers[index];
+        // Going through a large file on every frame would take too long
+        if buffer.large {
+            return;
+        }
+        let rust = buffer
+            .path
+            .as_ref()
+            .is_some_and(|path| path.extension().is_some_and(|ext| ext == "rs"));
+        self.highlighter
+            .update_brackets(&mut buffer.brackets, &buffer.text, buffer.changes, rust);
+    }
+
+    fn watch_file(&mut self, index: usize) {
+        if let (Some(watcher), Some(path)) = (&mut self.watcher, &self.buffers[index].path) {
+            // Without a watcher changes are still found on focus and `:checktime`
+            let _ = watcher.watch(path);
+        }
+    }
+
+    /// Runs an ex command, typed or given on the command line, returns
+    /// whether it quits the editor.
+    pub fn run_command(&mut self, command: &str) -> anyhow::Result<bool> {
+        let command = command.trim();
+        let command = command.strip_prefix(':').unwrap_or(command);
+
+        // Quitting the last window quits the editor, otherwise it only closes the window
+        let last_window = self.windows.is_empty() && self.tabs.is_empty();
+        match command {
+            "q" | "quit" if last_window => {
+                if let Some(buffer) = self.buffers.iter().find(|buffer| buffer.modified) {
+                    anyhow::bail!(
+                        "No write since last change for buffer \"{}\" (add ! to override)",
+                        buffer.buffer_name
+                    );
+                }
+                return Ok(true);
+            }
+            "q!" | "quit!" if last_window => return Ok(true),
+            "wq" | "x" | "xit" | "exit" => {
+                // `:x` only writes when there is something to write
+                if command == "wq" || self.buffer().modified {
+                    self.save_buffer(None, false)?;
+                }
+                if last_window {
+                    return Ok(true);
+                }
+                self.close_window()?;
+            }
+            _ => self.execute_command(command)?,
+        }
+        Ok(false)
+    }
+
+    /// `violet +command`: runs `command` once the files are open. A search
+    /// starts from the end, so a match on the first line counts.
+    pub fn run_start_command(&mut self, command: &str) -> anyhow::Result<bool> {
+        if command.starts_with('/') {
+            let last = self.buffer().line_count() - 1;
+            self.window.cursor = Cursor { x: 0, y: last };
+        }
+        self.run_command(command)
+    }
+
+    /// `violet --clean`: files open with the default options, whatever
+    /// `.editorconfig` files are around.
+    pub fn set_clean(&mut self) {
+        self.options.editorconfig = false;
+    }
+
+    /// `violet --headless`: no terminal to ask questions on, so no swap
+    /// files or file watching, and errors are kept for [`Editor::take_errors`].
+    pub fn set_headless(&mut self) {
+        self.headless = true;
+        self.options.swapfile = false;
+        self.watcher = None;
+    }
+
+    /// The errors shown since the last call, in headless mode.
+    pub fn take_errors(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.errors)
+    }
+
+    /// Whether the editor still has only the empty buffer it starts with,
+    /// which the first file from the command line takes the place of.
+    fn only_startup_buffer(&self) -> bool {
+        let buffer = &self.buffers[0];
+        self.buffers.len() == 1
+            && buffer.path.is_none()
+            && buffer.text.is_empty()
+            && !buffer.modified
+            && buffer.buffer_name == "Untitled"
+    }
+
+    /// Opens a file named on the command line in a buffer of its own, with
+    /// the cursor on `line` and `col` when given, counting from one.
+    pub fn open_arg(&mut self, path: &str, line: Option<usize>, col: Option<usize>) {
+        let index = if self.only_startup_buffer() {
+            self.open_first_file(path);
+            0
+        } else {
+            match self.open_buffer(path) {
+                Ok(index) => index,
+                Err(e) => {
+                    self.error(e.to_string());
+                    return;
+                }
+            }
+        };
+
+        let Some(line) = line else {
+            return;
+        };
+        match &mut self.buffers[index].loading {
+            // There are no lines to go to yet
+            Some(loading) => loading.position = Some((line, col)),
+            None => self.place_cursor(index, line, col),
+        }
+    }
+
+    /// Puts the cursor of buffer `index` on `line` and `col`, counting from
+    /// one, or the first non-blank without a column.
+    fn place_cursor(&mut self, index: usize, line: usize, col: Option<usize>) {
+        let buffer = &mut self.buffers[index];
+        let y = line.saturating_sub(1).min(buffer.line_count() - 1);
+        let x = match col {
+            Some(col) => col.saturating_sub(1).min(buffer.line_len(y)),
+            None => {
+                let text = buffer.get_line(y).unwrap_or("");
+                text.chars().position(|ch| !ch.is_whitespace()).unwrap_or(0)
+            }
+        };
+        buffer.last_cursor = Cursor { x, y };
+        if index == self.window.buffer {
+            self.window.cursor = Cursor { x, y };
+        }
+    }
+
+    /// `violet -`: puts text read from stdin in a buffer named `name`, which
+    /// has no file until it is written with `:w file`.
+    pub fn open_text(&mut self, name: &str, text: String) {
+        let mut buffer = Buffer::new(name.to_string(), text);
+        buffer.options.readonly = self.view_mode;
+        if self.only_startup_buffer() {
+            self.buffers[0] = buffer;
+        } else {
+            self.buffers.push(buffer);
+        }
+    }
+
+    /// `violet -R`: makes the open buffers and any opened later read-only.
+    pub fn set_view_mode(&mut self) {
+        self.view_mode = true;
+        for buffer in &mut self.buffers {
+            buffer.options.readonly = true;
+        }
+    }
+
+    /// `violet -r file`: takes the text from the swap files found when opening.
+    pub fn recover(&mut self) {
+        if let Some(Prompt::Swap { .. }) = self.prompt {
+            while let Some(Prompt::Swap { .. }) = self.prompt {
+                self.answer_prompt(KeyCode::Char('r'));
+            }
+        } else {
+            self.error(format!(
+                "No swap file found for {}",
+                self.buffer().buffer_name
+            ));
+        }
+    }
+
+    /// Called while no keys are coming in. Once it has been quiet for
+    /// `updatetime` the swap files of buffers with new changes are written.
+    pub fn idle(&mut self) {
+        self.poll_loading();
+        if let Some(watcher) = &self.watcher {
+            let changed = watcher.changed();
+            for index in 0..self.buffers.len() {
+                let path = self.buffers[index]
+                    .path
+                    .as_ref()
+                    .and_then(|path| std::path::absolute(path).ok());
+                if path.is_some_and(|path| changed.contains(&path)) {
+                    self.check_buffer(index);
+                }
+            }
+        }
+
+        if self.last_input.elapsed() < Duration::from_millis(self.options.updatetime) {
+            return;
+        }
+
+        let mut failed = None;
+        for buffer in &mut self.buffers {
+            if !buffer.swap_stale || !buffer.modified {
+                continue;
+            }
+            let (Some(swap_file), Some(path)) = (&buffer.swap_file, &buffer.path) else {
+                continue;
+            };
+
+            let swap = Swap {
+                pid: std::process::id(),
+                path: std::path::absolute(path).unwrap_or_else(|_| path.clone()),
+                text: buffer.text.clone(),
+            };
+            match swap.write(swap_file) {
+                Ok(()) => buffer.swap_stale = false,
+                Err(e) => {
+                    // Don't keep failing on every pause in typing
+                    failed = Some(format!("Can't write swap file: {}", e));
+                    buffer.swap_file = None;
+                }
+            }
+        }
+        if let Some(message) = failed {
+            self.error(message);
+        }
+    }
+
+    /// Removes the swap files of all buffers, for quitting.
+    pub fn remove_swaps(&mut self) {
+        for buffer in &mut self.buffers {
+            if let Some(swap_file) = buffer.swap_file.take() {
+                let _ = std::fs::remove_file(swap_file);
+            }
+        }
+    }
+
+    fn switch_buffer(&mut self, index: usize) {
+        if index == self.window.buffer || index >= self.buffers.len() {
+            return;
+        }
+
+        let cursor = self.window.cursor;
+        self.buffer_mut().last_cursor = cursor;
+        self.window.buffer = index;
+        self.window.cursor = self.buffer().last_cursor;
+        self.leave_visual();
+    }
+
+    /// Handles the key after `Ctrl-w`.
+    fn window_command(&mut self, key: KeyCode) -> anyhow::Result<()> {
+        match key {
+            KeyCode::Char('h') | KeyCode::Left => self.focus_neighbour(Direction::Left),
+            KeyCode::Char('j') | KeyCode::Down => self.focus_neighbour(Direction::Down),
+            KeyCode::Char('k') | KeyCode::Up => self.focus_neighbour(Direction::Up),
+            KeyCode::Char('l') | KeyCode::Right => self.focus_neighbour(Direction::Right),
+            KeyCode::Char('w' | 'W') => {
+                let ids = self.window_tree.windows();
+                let index = ids.iter().position(|&id| id == self.window.id).unwrap_or(0);
+                let next = if key == KeyCode::Char('w') {
+                    (index + 1) % ids.len()
+                } else {
+                    (index + ids.len() - 1) % ids.len()
+                };
+                self.focus_window(ids[next]);
+            }
+            KeyCode::Char('s' | 'S') => self.split_window(SplitDirection::Horizontal, "")?,
+            KeyCode::Char('v') => self.split_window(SplitDirection::Vertical, "")?,
+            KeyCode::Char('c') => self.close_window()?,
+            KeyCode::Char('o') => self.only_window(),
+            KeyCode::Char('=') => {
+                self.window_tree.equalize();
+                self.arrange_windows();
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Splits the current window, the new window shows `filename` or the same buffer.
+    fn split_window(&mut self, direction: SplitDirection, filename: &str) -> anyhow::Result<()> {
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+
+        // The new window starts out with its own jump list
+        let window = Window {
+            id,
+            jumps: Vec::new(),
+            jump_index: 0,
+            ..self.window.clone()
+        };
+        self.window_tree.split(self.window.id, id, direction);
+        let previous = std::mem::replace(&mut self.window, window);
+        self.windows.insert(previous.id, previous);
+        self.arrange_windows();
+
+        if !filename.is_empty() {
+            self.edit_file(filename)?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the current window and moves to the one that took its place.
+    fn close_window(&mut self) -> anyhow::Result<()> {
+        let ids = self.window_tree.windows();
+        if !self.window_tree.remove(self.window.id) {
+            anyhow::bail!("Cannot close last window");
+        }
+
+        let index = ids.iter().position(|&id| id == self.window.id).unwrap_or(0);
+        let ids = self.window_tree.windows();
+        let next = ids[index.min(ids.len() - 1)];
+
+        let cursor = self.window.cursor;
+        self.buffer_mut().last_cursor = cursor;
+        self.window = self.windows.remove(&next).expect("window in the tree");
+        self.clamp_window_cursor();
+        self.leave_visual();
+        self.arrange_windows();
+
+        Ok(())
+    }
+
+    /// `:only`: closes every window but the current one.
+    fn only_window(&mut self) {
+        self.windows.clear();
+        self.window_tree = WindowTree::Leaf(self.window.id);
+        self.arrange_windows();
+    }
+
+    fn focus_window(&mut self, id: WindowId) {
+        let Some(window) = self.windows.remove(&id) else {
+            return;
+        };
+
+        let previous = std::mem::replace(&mut self.window, window);
+        self.windows.insert(previous.id, previous);
+        self.clamp_window_cursor();
+        self.leave_visual();
+    }
+
+    /// `Ctrl-w h/j/k/l`: moves to the window next to the current one.
+    fn focus_neighbour(&mut self, direction: Direction) {
+        let rects = self.window_rects();
+        let position = self.cursor_screen_position(&self.window);
+        if let Some(id) = neighbour(&rects, self.window.rect, direction, position) {
+            self.focus_window(id);
+        }
+    }
+
+    /// All windows with where they are on screen.
+    fn window_rects(&self) -> Vec<(WindowId, Rect)> {
+        std::iter::once(&self.window)
+            .chain(self.windows.values())
+            .map(|window| (window.id, window.rect))
+            .collect()
+    }
+
+    /// Lays the windows out over the screen, above the message line, and
+    /// returns the separators between side by side windows.
+    fn arrange_windows(&mut self) -> Vec<Rect> {
+        // The tab line takes the top row once there is more than one tab page
+        let top = usize::from(self.tab_count() > 1);
+        let area = Rect {
+            x: 0,
+            y: top,
+            width: self.render_buffer.width,
+            height: self.render_buffer.height.saturating_sub(1 + top),
+        };
+
+        let (rects, separators) = self.window_tree.arrange(area);
+        for (id, rect) in rects {
+            if id == self.window.id {
+                self.window.rect = rect;
+            } else if let Some(window) = self.windows.get_mut(&id) {
+                window.rect = rect;
+            }
+        }
+
+        separators
+    }
+
+    /// Another window may have changed the buffer, keeps the cursor inside it.
+    fn clamp_window_cursor(&mut self) {
+        let buffer = &self.buffers[self.window.buffer];
+        let y = self.window.cursor.y.min(buffer.line_count() - 1);
+        self.window.cursor = Cursor {
+            x: self.window.cursor.x.min(buffer.line_len(y)),
+            y,
+        };
+    }
+
+    fn leave_visual(&mut self) {
+        if matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            self.mode = Mode::Normal;
+        }
+    }
+
+    /// Number of tab pages, counting the current one.
+    fn tab_count(&self) -> usize {
+        self.tabs.len() + 1
+    }
+
+    /// `:tabnew`: opens a tab page after the current one, showing `filename`
+    /// or a new empty buffer.
+    fn new_tab(&mut self, filename: &str) -> anyhow::Result<()> {
+        let buffer = if filename.is_empty() {
+            self.buffers
+                .push(Buffer::new("Untitled".to_string(), String::new()));
+            self.buffers.len() - 1
+        } else {
+            self.open_buffer(filename)?
+        };
+
+        let cursor = self.window.cursor;
+        self.buffer_mut().last_cursor = cursor;
+        let page = self.take_tab_page();
+        self.tabs.insert(self.current_tab, page);
+        self.current_tab += 1;
+
+        let id = self.next_window_id;
+        self.next_window_id += 1;
+        let mut window = Window::new(id, buffer);
+        window.cursor = self.buffers[buffer].last_cursor;
+        self.load_tab_page(TabPage {
+            window,
+            windows: HashMap::new(),
+            window_tree: WindowTree::Leaf(id),
+        });
+
+        Ok(())
+    }
+
+    /// Goes to the tab page at `index`, counting from the first one.
+    fn switch_tab(&mut self, index: usize) {
+        if index == self.current_tab || index >= self.tab_count() {
+            return;
+        }
+
+        let page = self.take_tab_page();
+        self.tabs.insert(self.current_tab, page);
+        let page = self.tabs.remove(index);
+        self.current_tab = index;
+        self.load_tab_page(page);
+    }
+
+    /// `:tabclose`: closes the current tab page with all its windows.
+    fn close_tab(&mut self) -> anyhow::Result<()> {
+        if self.tabs.is_empty() {
+            anyhow::bail!("Cannot close last tab page");
+        }
+
+        let cursor = self.window.cursor;
+        self.buffer_mut().last_cursor = cursor;
+
+        // The tab page after this one takes its place, or the one before if it was the last
+        let index = self.current_tab.min(self.tabs.len() - 1);
+        let page = self.tabs.remove(index);
+        self.current_tab = index;
+        self.load_tab_page(page);
+
+        Ok(())
+    }
+
+    /// Moves the windows of the current tab page out, to be replaced right after.
+    fn take_tab_page(&mut self) -> TabPage {
+        TabPage {
+            window: std::mem::replace(&mut self.window, Window::new(0, 0)),
+            windows: std::mem::take(&mut self.windows),
+            window_tree: std::mem::replace(&mut self.window_tree, WindowTree::Leaf(0)),
+        }
+    }
+
+    fn load_tab_page(&mut self, page: TabPage) {
+        self.window = page.window;
+        self.windows = page.windows;
+        self.window_tree = page.window_tree;
+        self.clamp_window_cursor();
+        self.leave_visual();
+        self.arrange_windows();
+    }
+
+    /// The buffer shown in the current window of the tab page at `index`.
+    fn tab_buffer(&self, index: usize) -> &Buffer {
+        let window = match index.cmp(&self.current_tab) {
+            std::cmp::Ordering::Less => &self.tabs[index].window,
+            std::cmp::Ordering::Equal => &self.window,
+            std::cmp::Ordering::Greater => &self.tabs[index - 1].window,
+        };
+        &self.buffers[window.buffer]
+    }
+
+    /// The tab line text, and which columns of it belong to which tab page.
+    fn tab_line(&self) -> (String, Vec<(Range<usize>, usize)>) {
+        let mut line = String::new();
+        let mut tab_spans = Vec::new();
+        for index in 0..self.tab_count() {
+            let buffer = self.tab_buffer(index);
+            let start = line.chars().count();
+            line.push_str(&format!(
+                " {} {}{} ",
+                index + 1,
+                buffer.buffer_name,
+                if buffer.modified { " +" } else { "" }
+            ));
+            tab_spans.push((start..line.chars().count(), index));
+        }
+
+        (line, tab_spans)
+    }
+
+    /// Whether the terminal should report mouse events.
+    pub fn mouse_enabled(&self) -> bool {
+        self.options.mouse
+    }
+
+    pub fn handle_mouse(&mut self, event: MouseEvent) {
+        if !self.options.mouse || self.mode == Mode::Command {
+            return;
+        }
+
+        let (x, y) = (event.column as usize, event.row as usize);
+        match event.kind {
+            MouseEventKind::Down(MouseButton::Left) => self.mouse_down(x, y),
+            MouseEventKind::Drag(MouseButton::Left) => self.mouse_drag(x, y),
+            MouseEventKind::Up(MouseButton::Left) => self.drag_start = None,
+            MouseEventKind::ScrollDown => self.scroll(3),
+            MouseEventKind::ScrollUp => self.scroll(-3),
+            _ => {}
+        }
+    }
+
+    fn mouse_down(&mut self, x: usize, y: usize) {
+        self.drag_start = None;
+
+        if y == 0 && self.tab_count() > 1 {
+            let (_, tab_spans) = self.tab_line();
+            if let Some((_, index)) = tab_spans.into_iter().find(|(span, _)| span.contains(&x)) {
+                self.switch_tab(index);
+            }
+            return;
+        }
+
+        // A click on a status line goes to that window, or picks a buffer in the current one
+        if let Some((id, rect)) = self
+            .window_rects()
+            .into_iter()
+            .find(|(_, rect)| rect.contains(x, y) && y + 1 == rect.y + rect.height)
+        {
+            if id != self.window.id {
+                self.focus_window(id);
+                return;
+            }
+
+            let (_, buffer_spans) = self.status_line(&self.window);
+            if let Some((_, index)) = buffer_spans
+                .into_iter()
+                .find(|(span, _)| span.contains(&(x - rect.x)))
+            {
+                self.switch_buffer(index);
+            }
+            return;
+        }
+
+        let Some((id, position, in_gutter)) = self.position_at(x, y) else {
+            return;
+        };
+        self.focus_window(id);
+
+        if in_gutter {
+            // Clicking a line number selects the whole line, dragging extends it
+            self.mode = Mode::VisualLine;
+            self.visual_anchor = position;
+        } else if matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            self.mode = Mode::Normal;
+        }
+
+        self.window.cursor = position;
+        self.drag_start = Some(position);
+    }
+
+    fn mouse_drag(&mut self, x: usize, y: usize) {
+        let (Some(start), Some((id, position, _))) = (self.drag_start, self.position_at(x, y))
+        else {
+            return;
+        };
+        // A selection can't leave the window it started in
+        if id != self.window.id {
+            return;
+        }
+
+        if !matches!(self.mode, Mode::Visual | Mode::VisualLine) {
+            self.mode = Mode::Visual;
+            self.visual_anchor = start;
+        }
+        self.window.cursor = position;
+    }
+
+    /// Scrolls the view by `lines`, dragging the cursor along if it goes off screen.
+    fn scroll(&mut self, lines: isize) {
+        let last_line = self.buffer().line_count() - 1;
+        self.window.viewport_y = self
+            .window
+            .viewport_y
+            .saturating_add_signed(lines)
+            .min(last_line);
+
+        let visible_lines = self.window.text_height().max(1);
+        let y = self
+            .window
+            .cursor
+            .y
+            .clamp(
+                self.window.viewport_y,
+                self.window.viewport_y + visible_lines - 1,
+            )
+            .min(last_line);
+        if y != self.window.cursor.y {
+            let vcol = self
+                .buffer()
+                .visual_col(self.window.cursor.y, self.window.cursor.x);
+            self.window.cursor.y = y;
+            self.window.cursor.x = self.buffer().col_at_visual(y, vcol);
+        }
+    }
+
+    /// Window and buffer position drawn at screen cell (`x`, `y`), and whether
+    /// it is in the gutter.
+    fn position_at(&self, x: usize, y: usize) -> Option<(WindowId, Cursor, bool)> {
+        let window = std::iter::once(&self.window)
+            .chain(self.windows.values())
+            .find(|window| {
+                window.rect.contains(x, y) && y < window.rect.y + window.text_height()
+            })?;
+        let (x, y) = (x - window.rect.x, y - window.rect.y);
+
+        let text_x = self.line_number_width(window) + 1;
+        let layout = self.layout(window, &self.options.showbreak);
+        let buffer = &self.buffers[window.buffer];
+
+        let mut row_y = 0;
+        for line in window.viewport_y..buffer.line_count() {
+            let rows = layout.rows(buffer, line);
+            if y >= row_y + rows.len() {
+                row_y += rows.len();
+                continue;
+            }
+
+            if x < text_x {
+                return Some((window.id, Cursor { x: 0, y: line }, true));
+            }
+
+            let col = if layout.wrap {
+                layout.col_at(buffer, line, &rows, y - row_y, x - text_x)
+            } else {
+                buffer.col_at_visual(line, window.viewport_x + x - text_x)
+            };
+            return Some((window.id, Cursor { x: col, y: line }, false));
+        }
+
+        None
+    }
+
+    fn set_option(&mut self, arg: &str) -> anyhow::Result<()> {
+        if let Some(name) = arg.strip_suffix('?') {
+            let value = self
+                .options
+                .get(name)
+                .or_else(|| self.buffer().options.get(name))
+                .ok_or_else(|| anyhow::anyhow!("Unknown option: {}", name))?;
+            self.message = Some(Message::Info(format!("{}={}", name, value)));
+            return Ok(());
+        }
+
+        let (name, value) = parse_set_arg(arg);
+        if !self.options.set(name, value)? && !self.buffer_mut().options.set(name, value)? {
+            anyhow::bail!("Unknown option: {}", name);
+        }
+        // The text is the same, but the file it makes is not
+        if let "fileformat" | "ff" | "fileencoding" | "fenc" = name {
+            self.buffer_mut().modified = true;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the buffer to `filename`, which becomes its file, or to the
+    /// file it already has.
+    /// Unless `force`d, a file changed on disk since it was read is left alone.
+    fn save_buffer(&mut self, filename: Option<&str>, force: bool) -> anyhow::Result<()> {
+        if self.buffer().loading.is_some() {
+            anyhow::bail!("{} is still being read", self.buffer().buffer_name);
+        }
+        if self.buffer().options.readonly && !force {
+            anyhow::bail!("'readonly' option is set (add ! to override)");
+        }
+        let path = match filename {
+            Some(filename) => PathBuf::from(filename),
+            None => self
+                .buffer()
+                .path
+                .clone()
+                .ok_or_else(|| anyhow::anyhow!("No file name"))?,
+        };
+
+        // Only a file whose time or size changed is read to compare contents,
+        // and a large one not even then
+        let buffer = self.buffer();
+        if !force
+            && buffer.path.as_ref() == Some(&path)
+            && let Some(disk) = buffer.disk
+            && std::fs::metadata(&path).is_ok_and(|metadata| disk.looks_changed(&metadata))
+            && (buffer.large
+                || DiskState::read(&path).is_ok_and(|(state, _)| state.hash != disk.hash))
+        {
+            anyhow::bail!(
+                "{} changed on disk since it was read, add ! to write anyway",
+                buffer.buffer_name
+            );
+        }
+
+        // The fixes only go into the buffer once the file is written with them
+        let fixes = self.write_fixes();
+        let buffer = self.buffer();
+        let mut text = Cow::Borrowed(buffer.text.as_str());
+        for (range, fixed) in &fixes {
+            text.to_mut().replace_range(range.clone(), fixed);
+        }
+        let contents = format::encode(
+            &text,
+            buffer.options.fileformat,
+            buffer.options.fileencoding,
+        )?;
+        write_file(
+            &path,
+            &contents,
+            self.options.backup,
+            self.options.backupcopy,
+        )
+        .map_err(|e| anyhow::anyhow!("Can't write {}: {}", path.display(), e))?;
+
+        if !fixes.is_empty() {
+            let buffer = self.buffer_mut();
+            for (range, fixed) in fixes {
+                buffer.delete(range.clone());
+                buffer.insert(range.start, &fixed);
+            }
+            self.clamp_window_cursor();
+        }
+        let buffer = self.buffer();
+        let message = format!(
+            "\"{}\" {}L, {}B written",
+            path.display(),
+            buffer.line_count(),
+            contents.len()
+        );
+        // Everything is on disk now, the swap file has nothing left to keep
+        let new_swap = self
+            .options
+            .swapfile
+            .then(|| swap::swap_path(&self.swap_dir(), &path));
+        let buffer = self.buffer_mut();
+        if let Some(swap_file) = &buffer.swap_file {
+            let _ = std::fs::remove_file(swap_file);
+        }
+        if buffer.path.as_ref() != Some(&path) {
+            buffer.swap_file = new_swap;
         }
-        false
+        buffer.modified = false;
+        buffer.swap_stale = false;
+        buffer.disk = std::fs::metadata(&path)
+            .ok()
+            .map(|metadata| DiskState::new(&metadata, &contents));
+        buffer.buffer_name = Self::file_name(&path.to_string_lossy());
+        buffer.path = Some(path);
+        self.watch_file(self.window.buffer);
+        self.message = Some(Message::Info(message));
+
+        Ok(())
     }
 
-    fn execute_command(&mut self, command: &str) -> bool {
-        let command = command.trim();
+    /// The edits the `trimwhitespace`, `fixindent` and `fixendofline` hooks
+    /// make to the buffer, from the end of the text to the start so each
+    /// one's offsets still hold after those before it.
+    fn write_fixes(&self) -> Vec<(Range<usize>, String)> {
+        let buffer = self.buffer();
+        let mut fixes = Vec::new();
+        // Large files would take far too long line by line
+        if !buffer.options.modifiable || buffer.large {
+            return fixes;
+        }
+        let (trim, fix_indent) = (buffer.options.trimwhitespace, buffer.options.fixindent);
+        let (expandtab, tabstop) = (buffer.options.expandtab, buffer.options.tabstop);
 
-        match command {
-            "w" | "write" => {
-                if !self.buffer.buffer_name.is_empty() && self.buffer.buffer_name != "Untitled" {
-                    self.save_buffer(&self.buffer.buffer_name.clone());
-                }
-            }
-            // save da buffer
-            cmd if cmd.starts_with("w ") => {
-                let filename = cmd[2..].trim();
-                if !filename.is_empty() {
-                    self.save_buffer(filename);
-                }
+        if buffer.options.fixendofline && !buffer.text.is_empty() && !buffer.text.ends_with('\n') {
+            let end = buffer.text.len();
+            fixes.push((end..end, "\n".to_string()));
+        }
+        for y in (0..buffer.line_count()).rev() {
+            let start = buffer.line_offsets[y];
+            let line = buffer.get_line(y).unwrap_or("");
+            let trimmed = line.trim_end_matches([' ', '\t']).len();
+            if trim && trimmed < line.len() {
+                fixes.push((start + trimmed..start + line.len(), String::new()));
             }
-            cmd if cmd.starts_with("e ") => {
-                let filename = cmd[2..].trim();
-                if !filename.is_empty() {
-                    if let Ok(buffer) = Self::open_file(filename) {
-                        self.buffer = buffer;
-                        self.cursor.x = 0;
-                        self.cursor.y = 0;
-                    }
-                }
+            // A line of only white space has none left to fix once trimmed
+            if !fix_indent || (trim && trimmed == 0) {
+                continue;
             }
-            _ => {} // Unknown command
-        }
-
-        false
-    }
 
-    fn save_buffer(&mut self, filename: &str) {
-        if std::fs::write(filename, &self.buffer.text).is_ok() {
-            self.buffer.buffer_name = Path::new(filename)
-                .file_name()
-                .map(|osstr| osstr.to_string_lossy().to_string())
-                .unwrap_or_else(|| filename.to_string());
+            let indent = line.len() - line.trim_start_matches([' ', '\t']).len();
+            let width = buffer.visual_col(y, indent);
+            let fixed = if expandtab {
+                " ".repeat(width)
+            } else {
+                whitespace_fill(0, width, tabstop)
+            };
+            if line[..indent] != fixed {
+                fixes.push((start..start + indent, fixed));
+            }
         }
+        fixes
     }
 
     fn insert_char(&mut self, ch: char) {
@@ -284,428 +3283,528 @@ impl Editor {
         }
 
         if let Some(byte_pos) = self
-            .buffer
-            .char_to_byte_position(self.cursor.y, self.cursor.x)
+            .buffer()
+            .char_to_byte_position(self.window.cursor.y, self.window.cursor.x)
         {
-            self.buffer.text.insert(byte_pos, ch);
-            self.cursor.x += 1;
-
-            // Update line offsets for all lines after this one
-            for i in self.cursor.y + 1..self.buffer.line_count() {
-                self.buffer.line_offsets[i] += ch.len_utf8();
-            }
+            self.buffer_mut()
+                .insert(byte_pos, ch.encode_utf8(&mut [0; 4]));
+            self.window.cursor.x += 1;
         }
     }
 
-    fn delete_char_before_cursor(&mut self) {
-        if self.cursor.x > 0 {
-            if let Some(byte_pos) = self
-                .buffer
-                .char_to_byte_position(self.cursor.y, self.cursor.x - 1)
-            {
-                let ch = self.buffer.text.remove(byte_pos);
-                self.cursor.x -= 1;
+    fn insert_tab(&mut self) {
+        let options = &self.buffer().options;
+        let (expandtab, tabstop) = (options.expandtab, options.tabstop);
+        let Some(stop) = options.soft_tab_stop().or(expandtab.then_some(tabstop)) else {
+            self.insert_char('\t');
+            return;
+        };
 
-                // Update line offsets for all lines after this one
-                for i in self.cursor.y + 1..self.buffer.line_count() {
-                    self.buffer.line_offsets[i] -= ch.len_utf8();
-                }
-            }
-        } else if self.cursor.y > 0 {
-            // Handle joining with previous line
-            let prev_line_len = self
-                .buffer
-                .get_line(self.cursor.y - 1)
-                .map(|l| l.len())
-                .unwrap_or(0);
+        // Rebuild the whitespace before the cursor so it reaches the next soft tab stop
+        let line: Vec<char> = self
+            .buffer()
+            .get_line(self.window.cursor.y)
+            .unwrap_or("")
+            .chars()
+            .take(self.window.cursor.x)
+            .collect();
+        let start = line
+            .iter()
+            .rposition(|&c| c != ' ' && c != '\t')
+            .map_or(0, |i| i + 1);
 
-            if let Some(byte_pos) = self.buffer.char_to_byte_position(self.cursor.y, 0) {
-                self.buffer.text.remove(byte_pos - 1); // Remove the newline
-                self.buffer.line_offsets.remove(self.cursor.y);
+        let vcol = self
+            .buffer()
+            .visual_col(self.window.cursor.y, self.window.cursor.x);
+        let target = (vcol / stop + 1) * stop;
+        let start_vcol = self.buffer().visual_col(self.window.cursor.y, start);
 
-                // Update line offsets for all lines after this one
-                for i in self.cursor.y..self.buffer.line_count() {
-                    self.buffer.line_offsets[i] -= 1;
-                }
+        let fill = if expandtab {
+            // Existing whitespace is left alone with expandtab, only spaces are added
+            " ".repeat(target - vcol)
+        } else {
+            whitespace_fill(start_vcol, target, tabstop)
+        };
+        let start = if expandtab {
+            self.window.cursor.x
+        } else {
+            start
+        };
 
-                self.cursor.y -= 1;
-                self.cursor.x = prev_line_len;
-            }
-        }
+        self.replace_cols(self.window.cursor.y, start, self.window.cursor.x, &fill);
+        self.window.cursor.x = start + fill.chars().count();
     }
 
-    fn delete_char_at_cursor(&mut self) {
-        if let Some(byte_pos) = self
-            .buffer
-            .char_to_byte_position(self.cursor.y, self.cursor.x)
-        {
-            let ch = self.buffer.text.remove(byte_pos);
-
-            if ch == '\n' {
-                // Remove the line offset for the next line
-                let pos = self
-                    .buffer
-                    .line_offsets
-                    .iter()
-                    .position(|&p| p == byte_pos + 1);
-
-                if let Some(pos) = pos {
-                    self.buffer.line_offsets.remove(pos);
-
-                    // Update line offsets for all lines after this one
-                    for i in pos..self.buffer.line_count() {
-                        self.buffer.line_offsets[i] -= 1;
-                    }
-                }
-            } else {
-                // Update line offsets for all lines after this one
-                for i in self.cursor.y + 1..self.buffer.line_count() {
-                    self.buffer.line_offsets[i] -= ch.len_utf8();
-                }
-            }
+    /// Replaces the chars `start..end` of `line` with `text`.
+    fn replace_cols(&mut self, line: usize, start: usize, end: usize, text: &str) {
+        if let (Some(start), Some(end)) = (
+            self.buffer().char_to_byte_position(line, start),
+            self.buffer().char_to_byte_position(line, end),
+        ) {
+            self.buffer_mut().delete(start..end);
+            self.buffer_mut().insert(start, text);
         }
     }
 
-    fn delete_current_line(&mut self) {
-        if self.buffer.line_count() == 0 {
-            return;
+    /// Backspace over whitespace to the previous soft tab stop, returns whether it applied.
+    fn delete_soft_tab(&mut self) -> bool {
+        let Some(stop) = self.buffer().options.soft_tab_stop() else {
+            return false;
+        };
+
+        let line: Vec<char> = self
+            .buffer()
+            .get_line(self.window.cursor.y)
+            .unwrap_or("")
+            .chars()
+            .take(self.window.cursor.x)
+            .collect();
+        if !matches!(line.last(), Some(' ' | '\t')) {
+            return false;
+        }
+
+        let vcol = self
+            .buffer()
+            .visual_col(self.window.cursor.y, self.window.cursor.x);
+        let target = (vcol - 1) / stop * stop;
+
+        let mut start = self.window.cursor.x;
+        while start > 0
+            && matches!(line[start - 1], ' ' | '\t')
+            && self.buffer().visual_col(self.window.cursor.y, start) > target
+        {
+            start -= 1;
         }
 
-        // Get the byte range for the current line
-        let line_start = self.buffer.line_offsets[self.cursor.y];
-        let line_end = if self.cursor.y + 1 < self.buffer.line_offsets.len() {
-            self.buffer.line_offsets[self.cursor.y + 1] - 1 // -1 to exclude the newline
+        // Removing a tab may overshoot the stop, pad back up to it
+        let start_vcol = self.buffer().visual_col(self.window.cursor.y, start);
+        let fill = if self.buffer().options.expandtab {
+            " ".repeat(target - start_vcol)
         } else {
-            self.buffer.text.len()
+            whitespace_fill(start_vcol, target, self.buffer().options.tabstop)
         };
 
-        // Remove the line
-        self.buffer.text.replace_range(line_start..line_end, "");
-        self.buffer.line_offsets.remove(self.cursor.y);
+        self.replace_cols(self.window.cursor.y, start, self.window.cursor.x, &fill);
+        self.window.cursor.x = start + fill.chars().count();
+        true
+    }
+
+    fn delete_char_before_cursor(&mut self) {
+        if self.window.cursor.x > 0 {
+            if self.delete_soft_tab() {
+                return;
+            }
+
+            if let Some(byte_pos) = self
+                .buffer()
+                .char_to_byte_position(self.window.cursor.y, self.window.cursor.x - 1)
+            {
+                let len = self.buffer().text[byte_pos..]
+                    .chars()
+                    .next()
+                    .map_or(0, char::len_utf8);
+                self.buffer_mut().delete(byte_pos..byte_pos + len);
+                self.window.cursor.x -= 1;
+            }
+        } else if self.window.cursor.y > 0 {
+            // Handle joining with previous line
+            let prev_line_len = self.buffer().line_len(self.window.cursor.y - 1);
+
+            let byte_pos = self.buffer().line_offsets[self.window.cursor.y];
+            self.buffer_mut().delete(byte_pos - 1..byte_pos); // Remove the newline
+
+            self.window.cursor.y -= 1;
+            self.window.cursor.x = prev_line_len;
+        }
+    }
 
-        // Adjust cursor position
-        if self.cursor.y >= self.buffer.line_count() {
-            self.cursor.y = self.buffer.line_count().saturating_sub(1);
+    fn delete_char_at_cursor(&mut self) {
+        if let Some(byte_pos) = self
+            .buffer()
+            .char_to_byte_position(self.window.cursor.y, self.window.cursor.x)
+            && let Some(ch) = self.buffer().text[byte_pos..].chars().next()
+        {
+            self.buffer_mut().delete(byte_pos..byte_pos + ch.len_utf8());
         }
-        self.cursor.x = 0;
     }
 
     fn insert_newline(&mut self) {
         let byte_pos = self
-            .buffer
-            .char_to_byte_position(self.cursor.y, self.cursor.x)
-            .unwrap_or_else(|| self.buffer.text.len());
-
-        self.buffer.text.insert(byte_pos, '\n');
+            .buffer()
+            .char_to_byte_position(self.window.cursor.y, self.window.cursor.x)
+            .unwrap_or(self.buffer().text.len());
 
-        // Update line offsets
-        let new_line_pos = byte_pos + 1;
-        self.buffer.line_offsets.push(new_line_pos);
-        self.buffer.line_offsets.sort();
+        self.buffer_mut().insert(byte_pos, "\n");
 
-        self.cursor.y += 1;
-        self.cursor.x = 0;
+        self.window.cursor.y += 1;
+        self.window.cursor.x = 0;
     }
 
     fn move_cursor_left(&mut self) {
-        if self.cursor.x > 0 {
-            self.cursor.x -= 1;
-        } else if self.cursor.y > 0 {
+        if self.window.cursor.x > 0 {
+            self.window.cursor.x -= 1;
+        } else if self.window.cursor.y > 0 {
             // Move to end of previous line
-            self.cursor.y -= 1;
-            self.cursor.x = self
-                .buffer
-                .get_line(self.cursor.y)
-                .map(|l| l.chars().count())
-                .unwrap_or(0);
+            self.window.cursor.y -= 1;
+            self.window.cursor.x = self.buffer().line_len(self.window.cursor.y);
         }
     }
 
     fn move_cursor_right(&mut self) {
-        if let Some(line) = self.buffer.get_line(self.cursor.y) {
-            let line_len = line.chars().count();
-            if self.cursor.x < line_len {
-                self.cursor.x += 1;
-            } else if self.cursor.y + 1 < self.buffer.line_count() {
+        if self.window.cursor.y < self.buffer().line_count() {
+            let line_len = self.buffer().line_len(self.window.cursor.y);
+            if self.window.cursor.x < line_len {
+                self.window.cursor.x += 1;
+            } else if self.window.cursor.y + 1 < self.buffer().line_count() {
                 // Move to start of next line
-                self.cursor.y += 1;
-                self.cursor.x = 0;
+                self.window.cursor.y += 1;
+                self.window.cursor.x = 0;
             }
         }
     }
 
-    fn jump_cursor_word(&mut self) {
-        if let Some(line) = self.buffer.get_line(self.cursor.y) {
-            let line_len = line.len();
-
-            if self.cursor.x < line_len {
-                let mut new_cursor_x = self.cursor.x;
-                let mut char_iter = line.chars().skip(self.cursor.x);
-
-                // Skip current word
-                while let Some(c) = char_iter.next() {
-                    if c.is_whitespace() {
-                        break;
-                    }
-                    new_cursor_x += 1;
-                }
+    /// `gk`: move up one screen row, which is a whole line unless `wrap` is on.
+    fn move_cursor_display_up(&mut self) {
+        if !self.options.wrap {
+            self.move_cursor_up();
+            return;
+        }
 
-                // Skip whitespace
-                while let Some(c) = char_iter.next() {
-                    if !c.is_whitespace() {
-                        break;
-                    }
-                    new_cursor_x += 1;
-                }
+        let layout = self.layout(&self.window, &self.options.showbreak);
+        let rows = layout.rows(self.buffer(), self.window.cursor.y);
+        let row = Layout::row_of(&rows, self.window.cursor.x);
+        let screen_col = layout.screen_col(
+            self.buffer(),
+            self.window.cursor.y,
+            &rows,
+            self.window.cursor.x,
+        );
 
-                self.cursor.x = new_cursor_x.min(line_len);
-            } else if self.cursor.y + 1 < self.buffer.line_count() {
-                // Move to next line
-                self.cursor.y += 1;
-                self.cursor.x = 0;
-                self.jump_cursor_word();
-            }
+        if row > 0 {
+            self.window.cursor.x = layout.col_at(
+                self.buffer(),
+                self.window.cursor.y,
+                &rows,
+                row - 1,
+                screen_col,
+            );
+        } else if self.window.cursor.y > 0 {
+            let y = self.window.cursor.y - 1;
+            let rows = layout.rows(self.buffer(), y);
+            self.window.cursor.x =
+                layout.col_at(self.buffer(), y, &rows, rows.len() - 1, screen_col);
+            self.window.cursor.y = y;
         }
     }
 
-    fn jump_cursor_word_reverse(&mut self) {
-        if let Some(line) = self.buffer.get_line(self.cursor.y) {
-            let line_len = line.len();
-
-            if self.cursor.x < line_len {
-                let mut new_cursor_x = self.cursor.x;
-                let mut char_iter = line.chars().skip(self.cursor.x);
-
-                // Skip current word
-                while let Some(c) = char_iter.next() {
-                    if c.is_whitespace() {
-                        break;
-                    }
-                    new_cursor_x -= 1;
-                }
+    /// `gj`: move down one screen row, which is a whole line unless `wrap` is on.
+    fn move_cursor_display_down(&mut self) {
+        if !self.options.wrap {
+            self.move_cursor_down();
+            return;
+        }
 
-                // Skip whitespace
-                while let Some(c) = char_iter.next() {
-                    if !c.is_whitespace() {
-                        break;
-                    }
-                    new_cursor_x -= 1;
-                }
+        let layout = self.layout(&self.window, &self.options.showbreak);
+        let rows = layout.rows(self.buffer(), self.window.cursor.y);
+        let row = Layout::row_of(&rows, self.window.cursor.x);
+        let screen_col = layout.screen_col(
+            self.buffer(),
+            self.window.cursor.y,
+            &rows,
+            self.window.cursor.x,
+        );
 
-                self.cursor.x = new_cursor_x.min(line_len);
-            } else if self.cursor.y - 1 < self.buffer.line_count() {
-                // Move to next line
-                self.cursor.y -= 1;
-                self.cursor.x = 0;
-                self.jump_cursor_word();
-            }
+        if row + 1 < rows.len() {
+            self.window.cursor.x = layout.col_at(
+                self.buffer(),
+                self.window.cursor.y,
+                &rows,
+                row + 1,
+                screen_col,
+            );
+        } else if self.window.cursor.y + 1 < self.buffer().line_count() {
+            let y = self.window.cursor.y + 1;
+            let rows = layout.rows(self.buffer(), y);
+            self.window.cursor.x = layout.col_at(self.buffer(), y, &rows, 0, screen_col);
+            self.window.cursor.y = y;
         }
     }
 
     fn move_cursor_up(&mut self) {
-        if self.cursor.y > 0 {
-            self.cursor.y -= 1;
-            self.clamp_cursor_x();
+        if self.window.cursor.y > 0 {
+            let vcol = self
+                .buffer()
+                .visual_col(self.window.cursor.y, self.window.cursor.x);
+            self.window.cursor.y -= 1;
+            self.window.cursor.x = self.buffer().col_at_visual(self.window.cursor.y, vcol);
 
-            if self.cursor.y < self.viewport_y {
-                self.viewport_y = self.cursor.y;
+            if self.window.cursor.y < self.window.viewport_y {
+                self.window.viewport_y = self.window.cursor.y;
             }
         }
     }
 
     fn move_cursor_down(&mut self) {
-        if self.cursor.y + 1 < self.buffer.line_count() {
-            self.cursor.y += 1;
-            self.clamp_cursor_x();
+        if self.window.cursor.y + 1 < self.buffer().line_count() {
+            let vcol = self
+                .buffer()
+                .visual_col(self.window.cursor.y, self.window.cursor.x);
+            self.window.cursor.y += 1;
+            self.window.cursor.x = self.buffer().col_at_visual(self.window.cursor.y, vcol);
 
-            let visible_lines = self.render_buffer.height.saturating_sub(1);
-            if self.cursor.y >= self.viewport_y + visible_lines {
-                self.viewport_y = self.cursor.y - visible_lines + 1;
+            let visible_lines = self.window.text_height();
+            if self.window.cursor.y >= self.window.viewport_y + visible_lines {
+                self.window.viewport_y = self.window.cursor.y - visible_lines + 1;
             }
         }
     }
 
-    fn clamp_cursor_x(&mut self) {
-        if let Some(line) = self.buffer.get_line(self.cursor.y) {
-            let line_len = line.chars().count();
-            if self.cursor.x > line_len {
-                self.cursor.x = line_len;
-            }
-        }
-    }
+    /// The status line text of `window`, and which columns of it show which
+    /// buffer's name. Only the current window lists the mode and all buffers.
+    fn status_line(&self, window: &Window) -> (String, Vec<(Range<usize>, usize)>) {
+        let buffer = &self.buffers[window.buffer];
+        let position = format!(
+            " | Line: {}/{} Col: {}/{} ",
+            window.cursor.y + 1,
+            buffer.line_count(),
+            window.cursor.x + 1,
+            buffer.line_len(window.cursor.y)
+        );
 
-    pub fn prepare_render_buffer(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        let (term_width, term_height) = terminal::size()?;
+        let position = format!("{}{}", Self::file_flags(buffer), position);
 
-        // Resize buffers if terminal size changed
-        self.render_buffer
-            .resize(term_width as usize, term_height as usize);
-        self.prev_render_buffer
-            .resize(term_width as usize, term_height as usize);
+        if window.id != self.window.id {
+            return (format!("{}{}", buffer.buffer_name, position), Vec::new());
+        }
 
-        // Clear the current render buffer
-        for y in 0..self.render_buffer.height {
-            for x in 0..self.render_buffer.width {
-                self.render_buffer.set_cell(x, y, RenderCell::default());
+        let mode_str = match self.mode {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Replace => "REPLACE",
+            Mode::Command => "COMMAND",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "V-LINE",
+        };
+
+        let mut status = match self.recording {
+            Some((name, _)) => format!("{} recording @{} | ", mode_str, name),
+            None => format!("{} | ", mode_str),
+        };
+        let mut buffer_spans = Vec::new();
+        for (index, buffer) in self.buffers.iter().enumerate() {
+            if index > 0 {
+                status.push(' ');
             }
+
+            // Only number the buffers once there is more than one to pick from
+            let name = match (self.buffers.len(), index == window.buffer) {
+                (1, _) => buffer.buffer_name.clone(),
+                (_, true) => format!("[{}:{}]", index + 1, buffer.buffer_name),
+                (_, false) => format!("{}:{}", index + 1, buffer.buffer_name),
+            };
+            let start = status.chars().count();
+            status.push_str(&name);
+            buffer_spans.push((start..status.chars().count(), index));
         }
+        status.push_str(&position);
+
+        (status, buffer_spans)
+    }
 
-        // Prepare content for rendering
-        let total_lines = self.buffer.line_count();
-        let line_number_width = total_lines.to_string().len();
+    /// Marks for the status line about the buffer's file, like ` [RO] [dos]`.
+    /// Empty for a writable unix UTF-8 file.
+    fn file_flags(buffer: &Buffer) -> String {
+        let mut flags = String::new();
+        if buffer.large {
+            flags.push_str(" [large]");
+        }
+        if let Some(loading) = &buffer.loading {
+            flags.push_str(&format!(" [loading {}%]", loading.percent()));
+        } else if !buffer.options.modifiable {
+            flags.push_str(" [-]");
+        } else if buffer.options.readonly {
+            flags.push_str(" [RO]");
+        }
+        if buffer.options.fileformat != FileFormat::Unix {
+            flags.push_str(&format!(" [{}]", buffer.options.fileformat));
+        }
+        if buffer.options.fileencoding != FileEncoding::Utf8 {
+            flags.push_str(&format!(" [{}]", buffer.options.fileencoding));
+        }
+        flags
+    }
 
-        // Calculate visible area (viewport)
-        let visible_lines = self.render_buffer.height.saturating_sub(2); // Leave room for status bar and command prompt
-        let visible_cols = self
-            .render_buffer
-            .width
-            .saturating_sub(line_number_width + 2); // Account for line numbers
+    /// Width of the line number column of `window`, not counting the separator.
+    fn line_number_width(&self, window: &Window) -> usize {
+        self.buffers[window.buffer].line_count().to_string().len()
+    }
 
-        // Adjust viewport to keep cursor visible
-        if self.cursor.y < self.viewport_y {
-            self.viewport_y = self.cursor.y;
-        } else if self.cursor.y >= self.viewport_y + visible_lines {
-            self.viewport_y = self.cursor.y - visible_lines + 1;
+    fn layout<'a>(&self, window: &Window, showbreak: &'a str) -> Layout<'a> {
+        Layout {
+            width: window
+                .rect
+                .width
+                .saturating_sub(self.line_number_width(window) + 2), // Account for line numbers
+            wrap: self.options.wrap,
+            linebreak: self.options.linebreak,
+            showbreak,
         }
+    }
 
-        if self.cursor.x < self.viewport_x {
-            self.viewport_x = self.cursor.x;
-        } else if self.cursor.x >= self.viewport_x + visible_cols {
-            self.viewport_x = self.cursor.x - visible_cols + 1;
+    /// Where the cursor of `window` is drawn on screen.
+    fn cursor_screen_position(&self, window: &Window) -> (usize, usize) {
+        let text_x = window.rect.x + self.line_number_width(window) + 1;
+        let layout = self.layout(window, &self.options.showbreak);
+        let buffer = &self.buffers[window.buffer];
+        let cursor = window.cursor;
+
+        if !layout.wrap {
+            let cursor_vcol = buffer.visual_col(cursor.y, cursor.x);
+            return (
+                text_x + cursor_vcol.saturating_sub(window.viewport_x),
+                window.rect.y + cursor.y.saturating_sub(window.viewport_y),
+            );
         }
 
-        // Render text content (only visible portion)
-        for render_y in 0..visible_lines {
-            let buffer_y = self.viewport_y + render_y;
+        let rows_above: usize = (window.viewport_y..cursor.y)
+            .map(|y| layout.rows(buffer, y).len())
+            .sum();
+        let rows = layout.rows(buffer, cursor.y);
 
-            // Skip if we've gone past the buffer contents
-            if buffer_y >= self.buffer.line_count() {
-                break;
-            }
+        (
+            text_x + layout.screen_col(buffer, cursor.y, &rows, cursor.x),
+            window.rect.y + rows_above + Layout::row_of(&rows, cursor.x),
+        )
+    }
 
-            let line = self.buffer.get_line(buffer_y).unwrap_or("");
-            let is_active = buffer_y == self.cursor.y; // Render line numbers/status column
+    /// Moves the viewport of `window` so its cursor is on screen.
+    fn scroll_to_cursor(&self, window: &mut Window) {
+        let buffer = &self.buffers[window.buffer];
+        let visible_lines = window.text_height();
+        let layout = self.layout(window, &self.options.showbreak);
+        let visible_cols = layout.width;
+        let cursor = window.cursor;
 
-            for x in 0..line_number_width {
-                let mut cell = RenderCell::default();
-                let line_number = (buffer_y + 1).to_string();
-                let padding = line_number_width - line_number.len();
+        if cursor.y < window.viewport_y {
+            window.viewport_y = cursor.y;
+        } else if cursor.y >= window.viewport_y + visible_lines {
+            window.viewport_y = cursor.y + 1 - visible_lines.max(1);
+        }
 
-                if x >= padding {
-                    cell.ch = line_number.chars().nth(x - padding).unwrap_or(' ');
-                } else {
-                    cell.ch = ' ';
+        if layout.wrap {
+            // Wrapped lines take several rows, scroll until the cursor row fits
+            window.viewport_x = 0;
+            let cursor_rows = layout.rows(buffer, cursor.y);
+            let mut rows_needed = Layout::row_of(&cursor_rows, cursor.x) + 1;
+            let mut heights = Vec::new();
+            for y in window.viewport_y..cursor.y {
+                let height = layout.rows(buffer, y).len();
+                heights.push(height);
+                rows_needed += height;
+            }
+            for height in heights {
+                if rows_needed <= visible_lines {
+                    break;
                 }
-
-                cell.fg = if is_active {
-                    Color::Rgb {
-                        r: 207,
-                        g: 159,
-                        b: 255,
-                    }
-                    .into()
-                } else {
-                    //dark violet
-                    Color::Rgb { r: 73, g: 6, b: 72 }.into()
-                };
-                self.render_buffer.set_cell(x, render_y, cell);
+                rows_needed -= height;
+                window.viewport_y += 1;
             }
+        } else {
+            // Horizontal scrolling works on screen columns so tabs are accounted for
+            let cursor_vcol = buffer.visual_col(cursor.y, cursor.x);
+            if cursor_vcol < window.viewport_x {
+                window.viewport_x = cursor_vcol;
+            } else if cursor_vcol >= window.viewport_x + visible_cols {
+                window.viewport_x = cursor_vcol - visible_cols + 1;
+            }
+        }
+    }
 
-            let mut separator_cell = RenderCell::default();
-            separator_cell.ch = '│';
-            separator_cell.fg = Color::Rgb {
-                // dark yellow for now
-                r: 255,
-                b: 0,
-                g: 255,
+    pub fn prepare_render_buffer(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        // Clear the current render buffer
+        for y in 0..self.render_buffer.height {
+            for x in 0..self.render_buffer.width {
+                self.render_buffer.set_cell(x, y, RenderCell::default());
             }
-            .into();
-            self.render_buffer
-                .set_cell(line_number_width, render_y, separator_cell);
+        }
 
-            self.highlighter.highlight(line)?;
+        let separators = self.arrange_windows();
+        for id in self.window_tree.windows() {
+            let mut window = if id == self.window.id {
+                self.window.clone()
+            } else {
+                self.windows[&id].clone()
+            };
 
-            // Render visible portion of the line
-            for (buffer_x, ch) in line
-                .chars()
-                .skip(self.viewport_x)
-                .take(visible_cols)
-                .enumerate()
-            {
-                let render_x = line_number_width + 1 + buffer_x;
-                if render_x >= self.render_buffer.width {
-                    break;
-                }
+            // Edits through another window may have left the cursor past the end
+            let buffer = &self.buffers[window.buffer];
+            window.cursor.y = window.cursor.y.min(buffer.line_count() - 1);
+            window.cursor.x = window.cursor.x.min(buffer.line_len(window.cursor.y));
 
-                let mut cell = RenderCell::default();
-                cell.ch = ch;
+            self.scroll_to_cursor(&mut window);
+            self.render_window(&window)?;
 
-                self.render_buffer.set_cell(render_x, render_y, cell);
+            if id == self.window.id {
+                self.window = window;
+            } else {
+                self.windows.insert(id, window);
             }
+        }
 
-            // Render cursor at end of line if needed
-            if is_active && self.cursor.x == line.len() {
-                let cursor_rel_x = self.cursor.x.saturating_sub(self.viewport_x);
-                let render_x = line_number_width + 1 + cursor_rel_x;
-                if render_x < self.render_buffer.width {
-                    let mut cell = RenderCell::default();
-                    cell.ch = ' ';
-                    match self.mode {
-                        Mode::Normal => {
-                            cell.fg = Color::Rgb { r: 0, b: 0, g: 0 }.into();
-                        }
-                        Mode::Insert => {
-                            cell.fg = Color::Rgb { r: 0, b: 0, g: 0 }.into();
-                        }
-                        Mode::Command => {
-                            cell.fg = Color::Rgb { r: 0, b: 0, g: 0 }.into();
-                        }
-                    }
-                    self.render_buffer.set_cell(render_x, render_y, cell);
-                }
+        for separator in separators {
+            for y in separator.y..separator.y + separator.height {
+                let cell = RenderCell {
+                    ch: '│',
+                    fg: self
+                        .theme
+                        .statusline
+                        .background_color
+                        .unwrap_or_default()
+                        .into(),
+                    ..Default::default()
+                };
+                self.render_buffer.set_cell(separator.x, y, cell);
             }
         }
 
-        // Render status bar
-        let mode_str = match self.mode {
-            Mode::Normal => "NORMAL",
-            Mode::Insert => "INSERT",
-            Mode::Command => "COMMAND",
-        };
-
-        let status = format!(
-            "{} | {} | Line: {}/{} Col: {}/{} ",
-            mode_str,
-            self.buffer.buffer_name,
-            self.cursor.y + 1,
-            self.buffer.line_count(),
-            self.cursor.x + 1,
-            self.buffer
-                .get_line(self.cursor.y)
-                .map(|l| l.len())
-                .unwrap_or(0)
-        );
-
-        let status_y = term_height as usize - 2;
-        for (x, ch) in status.chars().enumerate() {
-            if x >= self.render_buffer.width {
-                break;
+        if self.tab_count() > 1 {
+            let (tab_line, tab_spans) = self.tab_line();
+            let style = &self.theme.statusline;
+            let chars: Vec<char> = tab_line.chars().collect();
+            let width = self.render_buffer.width;
+            for (span, index) in tab_spans {
+                let current = index == self.current_tab;
+                for x in span.filter(|&x| x < width) {
+                    let mut cell = RenderCell {
+                        ch: chars[x],
+                        fg: style.text_color.unwrap_or_default().into(),
+                        bold: current,
+                        ..Default::default()
+                    };
+                    if current {
+                        cell.bg = style.background_color.unwrap_or_default().into();
+                    }
+                    self.render_buffer.set_cell(x, 0, cell);
+                }
             }
+        }
 
-            // STATUS LINE CELL
-            let mut cell = RenderCell::default();
-            cell.ch = ch;
-
-            let cell_color = match self.mode {
-                Mode::Normal => self.theme.statusline.background_color,
-                Mode::Command => self.theme.statusline.background_color,
-                Mode::Insert => self.theme.statusline.background_color,
+        // Render the message line below the windows
+        if let Some(message) = &self.message {
+            let (text, style) = match message {
+                Message::Info(text) => (text, &self.theme.statusline),
+                Message::Error(text) => (text, &self.theme.error),
             };
 
-            cell.bg = cell_color.unwrap_or_default().into();
-            cell.fg = self.theme.statusline.text_color.unwrap_or_default().into();
-
-            self.render_buffer.set_cell(x, status_y, cell);
+            let message_y = self.render_buffer.height.saturating_sub(1);
+            for (x, ch) in text.chars().take(self.render_buffer.width).enumerate() {
+                let cell = RenderCell {
+                    ch,
+                    fg: style.text_color.unwrap_or_default().into(),
+                    ..Default::default()
+                };
+                self.render_buffer.set_cell(x, message_y, cell);
+            }
         }
 
         // Render command prompt if active
@@ -717,28 +3816,25 @@ impl Editor {
 
             // Draw background for prompt
             for x in 0..self.render_buffer.width {
-                let mut cell = RenderCell::default();
-                cell.ch = ' ';
-                // cell.bg = self
-                //     .theme
-                //     .command_prompt
-                //     .background_color
-                //     .unwrap_or_default();
-                self.render_buffer.set_cell(x, prompt_y, cell);
+                // TODO: use self.theme.command_prompt.background_color
+                self.render_buffer
+                    .set_cell(x, prompt_y, RenderCell::default());
             }
 
             // Draw prompt text
             for (i, ch) in prompt_text.chars().enumerate() {
                 let x = prompt_x + i;
                 if x < self.render_buffer.width {
-                    let mut cell = RenderCell::default();
-                    cell.ch = ch;
-                    cell.fg = self
-                        .theme
-                        .command_prompt
-                        .text_color
-                        .unwrap_or_default()
-                        .into();
+                    let cell = RenderCell {
+                        ch,
+                        fg: self
+                            .theme
+                            .command_prompt
+                            .text_color
+                            .unwrap_or_default()
+                            .into(),
+                        ..Default::default()
+                    };
 
                     self.render_buffer.set_cell(x, prompt_y, cell);
                 }
@@ -747,9 +3843,10 @@ impl Editor {
             // Draw cursor in prompt
             let cursor_x = prompt_x + 2 + self.command_prompt.get_cursor_pos();
             if cursor_x < self.render_buffer.width {
-                let mut cell = RenderCell::default();
-                cell.ch = ' ';
-                cell.fg = Color::Rgb { r: 0, g: 0, b: 0 }.into();
+                let cell = RenderCell {
+                    fg: Color::Rgb { r: 0, g: 0, b: 0 }.into(),
+                    ..Default::default()
+                };
                 self.render_buffer.set_cell(cursor_x, prompt_y, cell);
             }
         }
@@ -757,87 +3854,299 @@ impl Editor {
         Ok(())
     }
 
-    pub fn render<W: Write>(&mut self, out: &mut W) -> Result<(), Box<dyn std::error::Error>> {
-        self.prepare_render_buffer()?;
+    /// Draws the text and status line of `window` into its rect.
+    fn render_window(&mut self, window: &Window) -> Result<(), Box<dyn std::error::Error>> {
+        let active = window.id == self.window.id;
+        let rect = window.rect;
+        let line_number_width = self.line_number_width(window);
 
-        // Only update cells that have changed
-        for y in 0..self.render_buffer.height {
-            for x in 0..self.render_buffer.width {
-                let current = self.render_buffer.get_cell(x, y);
-                let previous = self.prev_render_buffer.get_cell(x, y);
-
-                if current != previous {
-                    if let Some(cell) = current {
-                        queue!(
-                            out,
-                            MoveTo(x as u16, y as u16),
-                            SetForegroundColor(
-                                self.theme
-                                    .style
-                                    .text_color
-                                    .unwrap_or(Color::Rgb {
-                                        r: 255,
-                                        g: 255,
-                                        b: 255
-                                    })
-                                    .into()
-                            ),
-                            SetBackgroundColor(cell.bg.into()),
-                            Print(cell.ch)
-                        )?;
+        let visible_lines = window.text_height();
+        let showbreak = self.options.showbreak.clone();
+        let layout = self.layout(window, &showbreak);
+        let visible_cols = layout.width;
+
+        let text_x = rect.x + line_number_width + 1;
+        let text_color = self.theme.style.text_color.unwrap_or(Color::Rgb {
+            r: 255,
+            g: 255,
+            b: 255,
+        });
+
+        // Only the current window shows the selection
+        let selection = if active { self.selection() } else { None };
+        let linewise = self.mode == Mode::VisualLine;
+        let selection_color = self.theme.get_selection_background_color();
+        let flash_style = self.theme.flash.clone();
+        let match_paren = self.theme.match_paren.clone();
+        let unmatched_color = self.theme.error.text_color.unwrap_or(text_color);
+
+        // Render text content (only visible portion)
+        self.update_brackets(window.buffer);
+        let buffer = &self.buffers[window.buffer];
+        let brackets = buffer.brackets.brackets();
+
+        // The bracket under the cursor and its partner stand out in the current window
+        let cursor_pair = buffer
+            .char_to_byte_position(window.cursor.y, window.cursor.x)
+            .and_then(|pos| brackets.binary_search_by_key(&pos, |b| b.pos).ok())
+            .and_then(|i| Some((brackets[i].pos, brackets[i].pair?)))
+            .filter(|_| active);
+
+        // While `f` and friends wait for a char, show where they could jump to
+        let flash = match self.last_key {
+            Some(KeyCode::Char(key @ ('f' | 'F' | 't' | 'T'))) if active && self.options.flash => {
+                let line = buffer.get_line(window.cursor.y).unwrap_or("");
+                find_candidates(line, window.cursor.x, key.is_ascii_lowercase())
+            }
+            _ => Vec::new(),
+        };
+        let tabstop = buffer.options.tabstop;
+        let mut render_y = 0;
+        let mut buffer_y = window.viewport_y;
+        while render_y < visible_lines && buffer_y < buffer.line_count() {
+            let line = buffer.get_line(buffer_y).unwrap_or("");
+            let chars: Vec<char> = line.chars().collect();
+            let char_bytes: Vec<usize> = line
+                .char_indices()
+                .map(|(i, _)| buffer.line_offsets[buffer_y] + i)
+                .collect();
+            let is_active = buffer_y == window.cursor.y; // Render line numbers/status column
+
+            let fg = if is_active {
+                Color::Rgb {
+                    r: 207,
+                    g: 159,
+                    b: 255,
+                }
+            } else {
+                //dark violet
+                Color::Rgb { r: 73, g: 6, b: 72 }
+            };
+
+            if !buffer.large {
+                self.highlighter.highlight(line)?;
+            }
+
+            let rows = layout.rows(buffer, buffer_y);
+            for (row_index, row) in rows.iter().enumerate() {
+                if render_y >= visible_lines {
+                    break;
+                }
+                let screen_y = rect.y + render_y;
+
+                // Only the first row of a line gets its number
+                let line_number = if row_index == 0 {
+                    (buffer_y + 1).to_string()
+                } else {
+                    String::new()
+                };
+                let padding = line_number_width - line_number.len();
+                for x in 0..line_number_width {
+                    let ch = if x >= padding {
+                        line_number.chars().nth(x - padding).unwrap_or(' ')
+                    } else {
+                        ' '
+                    };
+
+                    let cell = RenderCell {
+                        ch,
+                        fg: fg.into(),
+                        ..Default::default()
+                    };
+                    self.render_buffer.set_cell(rect.x + x, screen_y, cell);
+                }
+
+                let separator_cell = RenderCell {
+                    ch: '│',
+                    fg: Color::Rgb {
+                        // dark yellow for now
+                        r: 255,
+                        b: 0,
+                        g: 255,
+                    }
+                    .into(),
+                    ..Default::default()
+                };
+                self.render_buffer
+                    .set_cell(rect.x + line_number_width, screen_y, separator_cell);
+
+                // Continuation rows start with the showbreak marker instead of scrolling
+                let (offset, scroll) = if !layout.wrap {
+                    (0, window.viewport_x)
+                } else if row_index > 0 {
+                    for (i, ch) in showbreak.chars().take(visible_cols).enumerate() {
+                        let cell = RenderCell {
+                            ch,
+                            fg: Color::Rgb { r: 73, g: 6, b: 72 }.into(),
+                            ..Default::default()
+                        };
+                        self.render_buffer.set_cell(text_x + i, screen_y, cell);
+                    }
+                    (
+                        layout.showbreak_width(),
+                        buffer.visual_col(buffer_y, row.start),
+                    )
+                } else {
+                    (0, 0)
+                };
+
+                // Render visible portion of the row, tabs expand to blank cells
+                let mut vcol = buffer.visual_col(buffer_y, row.start);
+                for (col, &ch) in chars.iter().enumerate().take(row.end).skip(row.start) {
+                    if vcol >= scroll + visible_cols {
+                        break;
+                    }
+
+                    let position = Cursor {
+                        x: col,
+                        y: buffer_y,
+                    };
+                    let selected = selection.is_some_and(|(start, end)| {
+                        if linewise {
+                            (start.y..=end.y).contains(&buffer_y)
+                        } else {
+                            (start..=end).contains(&position)
+                        }
+                    });
+
+                    let width = char_width(ch, vcol, tabstop);
+                    let ch = if ch == '\t' { ' ' } else { ch };
+                    for cell_vcol in vcol..vcol + width {
+                        if cell_vcol < scroll || offset + cell_vcol - scroll >= visible_cols {
+                            continue;
+                        }
+
+                        let mut cell = RenderCell {
+                            ch,
+                            fg: text_color.into(),
+                            ..Default::default()
+                        };
+                        if selected {
+                            cell.bg = selection_color.into();
+                        }
+                        if matches!(ch, '(' | ')' | '[' | ']' | '{' | '}')
+                            && let Ok(i) =
+                                brackets.binary_search_by_key(&char_bytes[col], |b| b.pos)
+                        {
+                            let bracket = brackets[i];
+                            if bracket.pair.is_none() {
+                                cell.fg = unmatched_color.into();
+                                cell.bold = true;
+                            } else if self.options.rainbow
+                                && let Some(color) = self
+                                    .theme
+                                    .rainbow(bracket.depth)
+                                    .and_then(|style| style.text_color)
+                            {
+                                cell.fg = color.into();
+                            }
+                            if cursor_pair
+                                .is_some_and(|(a, b)| bracket.pos == a || bracket.pos == b)
+                            {
+                                if let Some(bg) = match_paren.background_color {
+                                    cell.bg = bg.into();
+                                }
+                                cell.bold = match_paren.bold;
+                            }
+                        }
+                        if buffer_y == window.cursor.y && flash.contains(&col) {
+                            cell.fg = flash_style.text_color.unwrap_or(text_color).into();
+                            cell.bold = flash_style.bold;
+                        }
+                        self.render_buffer.set_cell(
+                            text_x + offset + cell_vcol - scroll,
+                            screen_y,
+                            cell,
+                        );
                     }
+                    vcol += width;
                 }
+
+                render_y += 1;
+            }
+
+            buffer_y += 1;
+        }
+
+        // Render cursor at end of line if needed
+        if active && window.cursor.x == buffer.line_len(window.cursor.y) {
+            let (cursor_x, cursor_y) = self.cursor_screen_position(window);
+            if cursor_x < rect.x + rect.width {
+                let cell = RenderCell {
+                    fg: Color::Rgb { r: 0, b: 0, g: 0 }.into(),
+                    ..Default::default()
+                };
+                self.render_buffer.set_cell(cursor_x, cursor_y, cell);
+            }
+        }
+
+        // Render the window's status line on its bottom row
+        let (status, _) = self.status_line(window);
+        let style = &self.theme.statusline;
+        let (fg, bg) = if active {
+            (style.text_color, style.background_color)
+        } else {
+            // Other windows swap the colours so the current one stands out
+            (style.background_color, None)
+        };
+
+        let status_y = rect.y + rect.height.saturating_sub(1);
+        for (x, ch) in status.chars().take(rect.width).enumerate() {
+            // STATUS LINE CELL
+            let mut cell = RenderCell {
+                ch,
+                fg: fg.unwrap_or_default().into(),
+                ..Default::default()
+            };
+            if let Some(bg) = bg {
+                cell.bg = bg.into();
             }
+
+            self.render_buffer.set_cell(rect.x + x, status_y, cell);
         }
 
+        Ok(())
+    }
+
+    pub fn render<T: Terminal + ?Sized>(
+        &mut self,
+        terminal: &mut T,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.prepare_render_buffer()?;
+
         // Position cursor correctly
-        let (cursor_x, cursor_y) = if self.command_prompt.is_active() {
+        let cursor = if self.command_prompt.is_active() {
             let prompt_text = format!(":{}", self.command_prompt.get_command());
             let prompt_width = prompt_text.len();
             let prompt_x = (self.render_buffer.width.saturating_sub(prompt_width)) / 2;
             (
-                (prompt_x + 1 + self.command_prompt.get_cursor_pos()) as u16,
+                prompt_x + 1 + self.command_prompt.get_cursor_pos(),
                 0, // Top line
             )
         } else {
-            let line_number_width = self.buffer.line_count().to_string().len();
-            (
-                (line_number_width + 1 + self.cursor.x.saturating_sub(self.viewport_x)) as u16,
-                (self.cursor.y.saturating_sub(self.viewport_y)) as u16,
-            )
+            self.cursor_screen_position(&self.window)
         };
 
-        match self.mode {
-            Mode::Normal => {
-                queue!(
-                    out,
-                    MoveTo(cursor_x, cursor_y),
-                    cursor::SetCursorStyle::BlinkingBlock,
-                    cursor::Show
-                )?;
-            }
-            Mode::Insert => {
-                queue!(
-                    out,
-                    MoveTo(cursor_x, cursor_y),
-                    cursor::SetCursorStyle::BlinkingBar,
-                    cursor::Show
-                )?;
-            }
-            Mode::Command => {
-                queue!(
-                    out,
-                    MoveTo(cursor_x, cursor_y),
-                    cursor::SetCursorStyle::BlinkingUnderScore,
-                    cursor::Show
-                )?;
-            }
-        }
+        let cursor_shape = match self.mode {
+            Mode::Normal => CursorShape::Block,
+            Mode::Insert => CursorShape::Bar,
+            Mode::Replace => CursorShape::Underline,
+            Mode::Command => CursorShape::Underline,
+            Mode::Visual | Mode::VisualLine => CursorShape::Block,
+        };
+
+        terminal.draw(&Frame {
+            current: &self.render_buffer,
+            previous: &self.prev_render_buffer,
+            cursor,
+            cursor_shape,
+            clear: self.needs_clear,
+        })?;
+        self.needs_clear = false;
 
         // Swap buffers
         std::mem::swap(&mut self.render_buffer, &mut self.prev_render_buffer);
 
-        out.flush()?;
         Ok(())
     }
 }
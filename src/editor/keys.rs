@@ -0,0 +1,110 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+/// Keys written as `<Name>`, the rest stand for themselves.
+const NAMED_KEYS: [(&str, KeyCode); 13] = [
+    ("Esc", KeyCode::Esc),
+    ("CR", KeyCode::Enter),
+    ("Tab", KeyCode::Tab),
+    ("BS", KeyCode::Backspace),
+    ("Del", KeyCode::Delete),
+    ("Left", KeyCode::Left),
+    ("Right", KeyCode::Right),
+    ("Up", KeyCode::Up),
+    ("Down", KeyCode::Down),
+    ("Home", KeyCode::Home),
+    ("End", KeyCode::End),
+    ("Space", KeyCode::Char(' ')),
+    ("lt", KeyCode::Char('<')),
+];
+
+/// How `key` is written in a register or key file, like `<Esc>` or `<C-w>`.
+pub fn to_notation(key: KeyEvent) -> String {
+    match key.code {
+        KeyCode::Char(ch) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            format!("<C-{}>", ch)
+        }
+        KeyCode::Char('<') => "<lt>".to_string(),
+        KeyCode::Char(ch) => ch.to_string(),
+        code => NAMED_KEYS
+            .iter()
+            .find(|(_, named)| *named == code)
+            .map(|(name, _)| format!("<{}>", name))
+            .unwrap_or_default(),
+    }
+}
+
+/// Reads keys back from text written with [`to_notation`]. A line break
+/// is Enter, and a `<` that doesn't start a key name is just `<`.
+pub fn parse(text: &str) -> Vec<KeyEvent> {
+    let mut keys = Vec::new();
+    let mut rest = text;
+    while let Some(ch) = rest.chars().next() {
+        if let Some((key, len)) = parse_named(rest) {
+            keys.push(key);
+            rest = &rest[len..];
+            continue;
+        }
+
+        let code = match ch {
+            '\n' | '\r' => KeyCode::Enter,
+            '\t' => KeyCode::Tab,
+            '\x1b' => KeyCode::Esc,
+            ch => KeyCode::Char(ch),
+        };
+        keys.push(KeyEvent::new(code, KeyModifiers::NONE));
+        rest = &rest[ch.len_utf8()..];
+    }
+    keys
+}
+
+/// The `<...>` key at the start of `text` and how many bytes it takes up.
+fn parse_named(text: &str) -> Option<(KeyEvent, usize)> {
+    let inner = text.strip_prefix('<')?;
+    let end = inner.find('>')?;
+    let name = &inner[..end];
+    let len = end + 2;
+
+    if let Some(ctrl) = name.strip_prefix("C-").or(name.strip_prefix("c-")) {
+        let mut chars = ctrl.chars();
+        if let (Some(ch), None) = (chars.next(), chars.next()) {
+            return Some((KeyEvent::new(KeyCode::Char(ch), KeyModifiers::CONTROL), len));
+        }
+    }
+
+    NAMED_KEYS
+        .iter()
+        .find(|(named, _)| named.eq_ignore_ascii_case(name))
+        .map(|(_, code)| (KeyEvent::new(*code, KeyModifiers::NONE), len))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_notation_round_trips() {
+        let keys = parse("ia<lt>b<Esc><C-w>j<cr>x<y\n");
+        let codes: Vec<KeyCode> = keys.iter().map(|key| key.code).collect();
+        assert_eq!(
+            codes,
+            vec![
+                KeyCode::Char('i'),
+                KeyCode::Char('a'),
+                KeyCode::Char('<'),
+                KeyCode::Char('b'),
+                KeyCode::Esc,
+                KeyCode::Char('w'),
+                KeyCode::Char('j'),
+                KeyCode::Enter,
+                KeyCode::Char('x'),
+                KeyCode::Char('<'),
+                KeyCode::Char('y'),
+                KeyCode::Enter,
+            ]
+        );
+        assert_eq!(keys[5].modifiers, KeyModifiers::CONTROL);
+
+        let text: String = keys.into_iter().map(to_notation).collect();
+        assert_eq!(text, "ia<lt>b<Esc><C-w>j<CR>x<lt>y<CR>");
+    }
+}
@@ -1,4 +1,14 @@
+pub mod change;
 pub mod color;
+#[allow(clippy::module_inception)]
 pub mod editor;
+pub mod keys;
 pub mod mode;
+pub mod motion;
+pub mod options;
+pub mod register;
+pub mod substitute;
+pub mod textobject;
 pub mod theme;
+pub mod window;
+pub mod wrap;
@@ -2,5 +2,8 @@
 pub enum Mode {
     Normal,
     Insert,
+    Replace,
     Command,
+    Visual,
+    VisualLine,
 }
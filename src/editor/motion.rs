@@ -0,0 +1,576 @@
+use crossterm::event::KeyCode;
+
+use crate::{buffer::buffer::Buffer, cursor::Cursor, highlighter::Bracket};
+
+use super::options::KeywordChars;
+
+/// How an operator treats the text between the cursor and the end of a motion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MotionKind {
+    /// Up to but not including the target, like `w`.
+    Exclusive,
+    /// Up to and including the target, like `$`.
+    Inclusive,
+    /// Whole lines from the cursor's to the target's, like `j`.
+    Linewise,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Down,
+    Up,
+    Right,
+    /// `gj`
+    DisplayDown,
+    /// `gk`
+    DisplayUp,
+    /// `w`, or `W` for WORDs.
+    WordForward {
+        bigword: bool,
+    },
+    /// `b` and `B`
+    WordBackward {
+        bigword: bool,
+    },
+    /// `e` and `E`
+    WordEnd {
+        bigword: bool,
+    },
+    /// `ge` and `gE`
+    WordEndBackward {
+        bigword: bool,
+    },
+    /// `0`
+    LineStart,
+    /// `$`
+    LineEnd,
+    /// `gg`, the first line or the line given by the count.
+    FirstLine,
+    /// `G`, the last line or the line given by the count.
+    LastLine,
+    /// `f`, `F`, `t` and `T`, `repeat` is set when it comes from `;` or `,`.
+    FindChar {
+        search: CharSearch,
+        repeat: bool,
+    },
+    /// `` ` `` and `'` followed by a mark name, `'` goes to the line of the mark.
+    Mark {
+        name: char,
+        linewise: bool,
+    },
+    /// `%`, the bracket paired with the one under or after the cursor.
+    MatchBracket,
+    /// `;`, or `,` with `reverse`, repeats the last `f`, `F`, `t` or `T`.
+    RepeatFind {
+        reverse: bool,
+    },
+}
+
+/// A search for a char on the cursor line, as made by `f`, `F`, `t` and `T`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CharSearch {
+    pub ch: char,
+    pub forward: bool,
+    /// Stop before the char instead of on it, like `t`.
+    pub till: bool,
+}
+
+impl CharSearch {
+    /// The search a key waiting for its char stands for.
+    pub fn from_key(key: KeyCode, ch: char) -> Option<CharSearch> {
+        let (forward, till) = match key {
+            KeyCode::Char('f') => (true, false),
+            KeyCode::Char('F') => (false, false),
+            KeyCode::Char('t') => (true, true),
+            KeyCode::Char('T') => (false, true),
+            _ => return None,
+        };
+        Some(CharSearch { ch, forward, till })
+    }
+
+    /// The same search going the other way, for `,`.
+    pub fn reversed(self) -> CharSearch {
+        CharSearch {
+            forward: !self.forward,
+            ..self
+        }
+    }
+}
+
+impl Motion {
+    /// The motion a key stands for on its own, `g` prefixed motions are not included.
+    pub fn from_key(key: KeyCode) -> Option<Motion> {
+        Some(match key {
+            KeyCode::Char('h') | KeyCode::Left => Motion::Left,
+            KeyCode::Char('j') | KeyCode::Down => Motion::Down,
+            KeyCode::Char('k') | KeyCode::Up => Motion::Up,
+            KeyCode::Char('l') | KeyCode::Right => Motion::Right,
+            KeyCode::Char('w') => Motion::WordForward { bigword: false },
+            KeyCode::Char('W') => Motion::WordForward { bigword: true },
+            KeyCode::Char('b') => Motion::WordBackward { bigword: false },
+            KeyCode::Char('B') => Motion::WordBackward { bigword: true },
+            KeyCode::Char('e') => Motion::WordEnd { bigword: false },
+            KeyCode::Char('E') => Motion::WordEnd { bigword: true },
+            KeyCode::Char('0') => Motion::LineStart,
+            KeyCode::Char('$') => Motion::LineEnd,
+            KeyCode::Char('G') => Motion::LastLine,
+            KeyCode::Char('%') => Motion::MatchBracket,
+            KeyCode::Char(';') => Motion::RepeatFind { reverse: false },
+            KeyCode::Char(',') => Motion::RepeatFind { reverse: true },
+            _ => return None,
+        })
+    }
+
+    /// Whether the motion goes somewhere relative to the cursor, so staying
+    /// put means it failed. `0`, `$`, `gg` and `G` go to a fixed place instead.
+    pub fn moves_relative(&self) -> bool {
+        !matches!(
+            self,
+            Motion::LineStart
+                | Motion::LineEnd
+                | Motion::FirstLine
+                | Motion::LastLine
+                | Motion::Mark { .. }
+        )
+    }
+
+    /// Whether the motion is a jump that goes in the jump list.
+    pub fn is_jump(&self) -> bool {
+        matches!(
+            self,
+            Motion::FirstLine | Motion::LastLine | Motion::MatchBracket | Motion::Mark { .. }
+        )
+    }
+
+    pub fn kind(&self) -> MotionKind {
+        match self {
+            Motion::Down | Motion::Up | Motion::FirstLine | Motion::LastLine => {
+                MotionKind::Linewise
+            }
+            Motion::Mark { linewise: true, .. } => MotionKind::Linewise,
+            Motion::LineEnd
+            | Motion::WordEnd { .. }
+            | Motion::WordEndBackward { .. }
+            | Motion::MatchBracket => MotionKind::Inclusive,
+            Motion::FindChar { search, .. } if search.forward => MotionKind::Inclusive,
+            Motion::Left
+            | Motion::Right
+            | Motion::DisplayDown
+            | Motion::DisplayUp
+            | Motion::WordForward { .. }
+            | Motion::WordBackward { .. }
+            | Motion::LineStart
+            | Motion::Mark { .. }
+            | Motion::FindChar { .. }
+            | Motion::RepeatFind { .. } => MotionKind::Exclusive,
+        }
+    }
+}
+
+/// Which kind of word a char belongs to, chars of the same class make up a word.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CharClass {
+    Whitespace,
+    Punctuation,
+    Word,
+}
+
+/// Class of `ch`, with `bigword` everything but white space is one class like for `W`.
+pub fn char_class(ch: char, bigword: bool, keyword: &KeywordChars) -> CharClass {
+    if ch.is_whitespace() {
+        CharClass::Whitespace
+    } else if bigword || keyword.contains(ch) {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+/// Steps through the chars of a buffer for word motions. Runs of a class end
+/// at line breaks, and an empty line counts as a word of its own.
+struct WordWalker<'a> {
+    buffer: &'a Buffer,
+    lines: Vec<Vec<char>>,
+    bigword: bool,
+}
+
+impl<'a> WordWalker<'a> {
+    fn new(buffer: &'a Buffer, bigword: bool) -> Self {
+        let lines = (0..buffer.line_count())
+            .map(|y| buffer.get_line(y).unwrap_or("").chars().collect())
+            .collect();
+        Self {
+            buffer,
+            lines,
+            bigword,
+        }
+    }
+
+    /// Class of the char at `pos`, `None` on an empty line.
+    fn class(&self, pos: Cursor) -> Option<CharClass> {
+        let ch = self.lines[pos.y].get(pos.x)?;
+        Some(char_class(
+            *ch,
+            self.bigword,
+            &self.buffer.options.iskeyword,
+        ))
+    }
+
+    fn is_word(&self, pos: Cursor) -> bool {
+        self.class(pos)
+            .is_some_and(|class| class != CharClass::Whitespace)
+    }
+
+    fn next(&self, pos: Cursor) -> Option<Cursor> {
+        if pos.x + 1 < self.lines[pos.y].len() {
+            Some(Cursor {
+                x: pos.x + 1,
+                y: pos.y,
+            })
+        } else if pos.y + 1 < self.lines.len() {
+            Some(Cursor { x: 0, y: pos.y + 1 })
+        } else {
+            None
+        }
+    }
+
+    fn prev(&self, pos: Cursor) -> Option<Cursor> {
+        if pos.x > 0 {
+            Some(Cursor {
+                x: (pos.x - 1).min(self.lines[pos.y].len().saturating_sub(1)),
+                y: pos.y,
+            })
+        } else if pos.y > 0 {
+            Some(Cursor {
+                x: self.lines[pos.y - 1].len().saturating_sub(1),
+                y: pos.y - 1,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// The next position in the same run of chars as `pos`, going forward or back.
+    fn same_run(&self, pos: Cursor, forward: bool) -> Option<Cursor> {
+        let next = if forward {
+            self.next(pos)?
+        } else {
+            self.prev(pos)?
+        };
+        (next.y == pos.y && self.class(next) == self.class(pos)).then_some(next)
+    }
+
+    /// Past the last char of the buffer, where a `w` off the end goes.
+    fn end(&self) -> Cursor {
+        let y = self.lines.len() - 1;
+        Cursor {
+            x: self.lines[y].len(),
+            y,
+        }
+    }
+}
+
+/// `w` and `W`: the start of the next word. Off the end of the buffer it is
+/// the position past the last char.
+pub fn word_forward(buffer: &Buffer, pos: Cursor, bigword: bool) -> Cursor {
+    let walker = WordWalker::new(buffer, bigword);
+
+    // Leave the word under the cursor
+    let mut pos = pos;
+    if walker.is_word(pos) {
+        while let Some(next) = walker.same_run(pos, true) {
+            pos = next;
+        }
+    }
+    let Some(mut pos) = walker.next(pos) else {
+        return walker.end();
+    };
+
+    // Skip white space, stopping at an empty line
+    while walker.class(pos) == Some(CharClass::Whitespace) {
+        match walker.next(pos) {
+            Some(next) => pos = next,
+            None => return walker.end(),
+        }
+    }
+    pos
+}
+
+/// `e` and `E`: the end of this word, or of the next one when already there.
+pub fn word_end(buffer: &Buffer, pos: Cursor, bigword: bool) -> Cursor {
+    let walker = WordWalker::new(buffer, bigword);
+
+    let Some(mut pos) = walker.next(pos) else {
+        return pos;
+    };
+    // Empty lines don't stop `e`
+    while !walker.is_word(pos) {
+        match walker.next(pos) {
+            Some(next) => pos = next,
+            None => return pos,
+        }
+    }
+    while let Some(next) = walker.same_run(pos, true) {
+        pos = next;
+    }
+    pos
+}
+
+/// The last char of the word under the cursor, which is where `cw` changes up to.
+pub fn end_of_word(buffer: &Buffer, pos: Cursor, bigword: bool) -> Cursor {
+    let walker = WordWalker::new(buffer, bigword);
+
+    let mut pos = pos;
+    while let Some(next) = walker.same_run(pos, true) {
+        pos = next;
+    }
+    pos
+}
+
+/// `b` and `B`: the start of this word, or of the one before when already there.
+pub fn word_backward(buffer: &Buffer, pos: Cursor, bigword: bool) -> Cursor {
+    let walker = WordWalker::new(buffer, bigword);
+
+    let Some(mut pos) = walker.prev(pos) else {
+        return pos;
+    };
+    while walker.class(pos) == Some(CharClass::Whitespace) {
+        match walker.prev(pos) {
+            Some(prev) => pos = prev,
+            None => return pos,
+        }
+    }
+    while let Some(prev) = walker.same_run(pos, false) {
+        pos = prev;
+    }
+    pos
+}
+
+/// `ge` and `gE`: the end of the word before this one.
+pub fn word_end_backward(buffer: &Buffer, pos: Cursor, bigword: bool) -> Cursor {
+    let walker = WordWalker::new(buffer, bigword);
+
+    // Leave the word under the cursor
+    let mut pos = pos;
+    if walker.is_word(pos) {
+        while let Some(prev) = walker.same_run(pos, false) {
+            pos = prev;
+        }
+    }
+    let Some(mut pos) = walker.prev(pos) else {
+        return Cursor::default();
+    };
+
+    // Skip white space, stopping at an empty line
+    while walker.class(pos) == Some(CharClass::Whitespace) {
+        match walker.prev(pos) {
+            Some(prev) => pos = prev,
+            None => return pos,
+        }
+    }
+    pos
+}
+
+/// Column `search` lands on in `line` going from `col`, for the `count`th
+/// match. `None` when there aren't that many matches on the line.
+///
+/// A repeated `t` or `T` skips a match right next to the cursor, otherwise
+/// `;` would never get past it.
+pub fn find_char(
+    line: &str,
+    col: usize,
+    search: CharSearch,
+    count: usize,
+    repeat: bool,
+) -> Option<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let skip = usize::from(search.till && repeat);
+
+    let mut matches: Box<dyn Iterator<Item = usize>> = if search.forward {
+        Box::new((col + 1 + skip..chars.len()).filter(|&x| chars[x] == search.ch))
+    } else {
+        Box::new(
+            (0..col.saturating_sub(skip))
+                .rev()
+                .filter(|&x| chars[x] == search.ch),
+        )
+    };
+    let target = matches.nth(count.max(1) - 1)?;
+
+    Some(match (search.till, search.forward) {
+        (false, _) => target,
+        (true, true) => target - 1,
+        (true, false) => target + 1,
+    })
+}
+
+/// Columns on `line` that a single `f` (or `F` when not `forward`) from `col`
+/// reaches, the first of each char. These get highlighted while the char is typed.
+pub fn find_candidates(line: &str, col: usize, forward: bool) -> Vec<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    let columns: Box<dyn Iterator<Item = usize>> = if forward {
+        Box::new(col + 1..chars.len())
+    } else {
+        Box::new((0..col.min(chars.len())).rev())
+    };
+
+    let mut seen = Vec::new();
+    let mut candidates = Vec::new();
+    for x in columns {
+        let ch = chars[x];
+        if !ch.is_whitespace() && !seen.contains(&ch) {
+            seen.push(ch);
+            candidates.push(x);
+        }
+    }
+    candidates
+}
+
+/// `%`: where the partner of the first bracket at or after `pos` on its
+/// line is. `None` if there is no bracket or it has no partner.
+pub fn match_bracket(buffer: &Buffer, brackets: &[Bracket], pos: Cursor) -> Option<Cursor> {
+    let start = buffer.char_to_byte_position(pos.y, pos.x)?;
+    let line_end = buffer.line_offsets[pos.y] + buffer.get_line(pos.y)?.len();
+    let bracket = brackets
+        .iter()
+        .find(|bracket| (start..line_end).contains(&bracket.pos))?;
+    Some(buffer.byte_to_cursor(bracket.pair?))
+}
+
+/// An operator waiting for a motion or text object to act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
+    /// `>`, shifts whole lines right by `shiftwidth`.
+    Indent,
+    /// `<`
+    Outdent,
+}
+
+impl Operator {
+    pub fn from_key(key: KeyCode) -> Option<Operator> {
+        Some(match key {
+            KeyCode::Char('d') => Operator::Delete,
+            KeyCode::Char('c') => Operator::Change,
+            KeyCode::Char('y') => Operator::Yank,
+            KeyCode::Char('>') => Operator::Indent,
+            KeyCode::Char('<') => Operator::Outdent,
+            _ => return None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{editor::theme::Theme, highlighter::Highlighter};
+
+    fn at(x: usize, y: usize) -> Cursor {
+        Cursor { x, y }
+    }
+
+    #[test]
+    fn test_word_motions_stop_at_punctuation() {
+        let buffer = Buffer::new("test".to_string(), "foo.bar(baz)  qux".to_string());
+
+        assert_eq!(word_forward(&buffer, at(0, 0), false), at(3, 0));
+        assert_eq!(word_forward(&buffer, at(3, 0), false), at(4, 0));
+        assert_eq!(word_forward(&buffer, at(0, 0), true), at(14, 0));
+        assert_eq!(word_end(&buffer, at(0, 0), false), at(2, 0));
+        assert_eq!(word_end(&buffer, at(2, 0), false), at(3, 0));
+        assert_eq!(word_end(&buffer, at(0, 0), true), at(11, 0));
+        assert_eq!(word_backward(&buffer, at(14, 0), false), at(11, 0));
+        assert_eq!(word_backward(&buffer, at(14, 0), true), at(0, 0));
+        assert_eq!(word_end_backward(&buffer, at(14, 0), false), at(11, 0));
+        assert_eq!(word_end_backward(&buffer, at(5, 0), false), at(3, 0));
+    }
+
+    #[test]
+    fn test_word_motions_cross_lines() {
+        let buffer = Buffer::new("test".to_string(), "one  \n\n  two\nthree".to_string());
+
+        // An empty line is a word for `w`, `b` and `ge` but not for `e`
+        assert_eq!(word_forward(&buffer, at(0, 0), false), at(0, 1));
+        assert_eq!(word_forward(&buffer, at(0, 1), false), at(2, 2));
+        assert_eq!(word_forward(&buffer, at(2, 2), false), at(0, 3));
+        assert_eq!(word_end(&buffer, at(2, 0), false), at(4, 2));
+        assert_eq!(word_backward(&buffer, at(2, 2), false), at(0, 1));
+        assert_eq!(word_backward(&buffer, at(0, 3), false), at(2, 2));
+        assert_eq!(word_end_backward(&buffer, at(0, 3), false), at(4, 2));
+        assert_eq!(word_end_backward(&buffer, at(2, 2), false), at(0, 1));
+    }
+
+    #[test]
+    fn test_word_motions_at_buffer_edges() {
+        let buffer = Buffer::new("test".to_string(), "ab cd".to_string());
+
+        assert_eq!(word_forward(&buffer, at(3, 0), false), at(5, 0));
+        assert_eq!(word_end(&buffer, at(4, 0), false), at(4, 0));
+        assert_eq!(word_backward(&buffer, at(0, 0), false), at(0, 0));
+        assert_eq!(word_end_backward(&buffer, at(1, 0), false), at(0, 0));
+
+        let empty = Buffer::new("test".to_string(), String::new());
+        assert_eq!(word_forward(&empty, at(0, 0), false), at(0, 0));
+        assert_eq!(word_backward(&empty, at(0, 0), false), at(0, 0));
+    }
+
+    #[test]
+    fn test_word_motions_with_unicode_and_iskeyword() {
+        let mut buffer = Buffer::new("test".to_string(), "héllo wörld-ok 日本語!".to_string());
+
+        assert_eq!(word_forward(&buffer, at(0, 0), false), at(6, 0));
+        assert_eq!(word_forward(&buffer, at(6, 0), false), at(11, 0));
+        assert_eq!(word_end(&buffer, at(15, 0), false), at(17, 0));
+
+        buffer.options.iskeyword = KeywordChars::parse("@,48-57,_,192-255,-").unwrap();
+        assert_eq!(word_forward(&buffer, at(6, 0), false), at(15, 0));
+    }
+
+    #[test]
+    fn test_match_bracket() {
+        let buffer = Buffer::new(
+            "test".to_string(),
+            "let s = \")\"; f(a[0],\n  b) ( x".to_string(),
+        );
+        let brackets = Highlighter::new(Theme::default())
+            .unwrap()
+            .brackets(&buffer.text);
+
+        // The `)` in the string is skipped
+        assert_eq!(match_bracket(&buffer, &brackets, at(0, 0)), Some(at(3, 1)));
+        assert_eq!(
+            match_bracket(&buffer, &brackets, at(17, 0)),
+            Some(at(16, 0))
+        );
+        assert_eq!(match_bracket(&buffer, &brackets, at(3, 1)), Some(at(14, 0)));
+        // An unmatched bracket, and no bracket after the cursor
+        assert_eq!(match_bracket(&buffer, &brackets, at(4, 1)), None);
+        assert_eq!(match_bracket(&buffer, &brackets, at(19, 0)), None);
+    }
+
+    #[test]
+    fn test_find_char() {
+        let line = "foo(a, b), bar(c)";
+        let f = |ch, forward, till| CharSearch { ch, forward, till };
+
+        assert_eq!(find_char(line, 0, f(',', true, false), 1, false), Some(5));
+        assert_eq!(find_char(line, 0, f(',', true, false), 2, false), Some(9));
+        assert_eq!(find_char(line, 0, f(',', true, false), 3, false), None);
+        assert_eq!(find_char(line, 0, f(')', true, true), 1, false), Some(7));
+        assert_eq!(
+            find_char(line, 16, f('(', false, false), 1, false),
+            Some(14)
+        );
+        assert_eq!(find_char(line, 16, f('(', false, true), 2, false), Some(4));
+        // Without skipping, `;` after `t)` would stay put
+        assert_eq!(find_char(line, 7, f(')', true, true), 1, false), Some(7));
+        assert_eq!(find_char(line, 7, f(')', true, true), 1, true), Some(15));
+        assert_eq!(find_char(line, 4, f('(', false, true), 1, true), None);
+
+        assert_eq!(find_candidates("abab c", 0, true), vec![1, 2, 5]);
+        assert_eq!(find_candidates("abab c", 5, false), vec![3, 2]);
+    }
+}
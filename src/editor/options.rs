@@ -0,0 +1,377 @@
+use anyhow::bail;
+
+use crate::buffer::format::{FileEncoding, FileFormat};
+
+/// The value half of a `:set` argument.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SetValue<'a> {
+    On,
+    Off,
+    Value(&'a str),
+}
+
+/// Splits a single `:set` argument such as `ts=4`, `et` or `noet`.
+pub fn parse_set_arg(arg: &str) -> (&str, SetValue<'_>) {
+    if let Some((name, value)) = arg.split_once('=') {
+        (name, SetValue::Value(value))
+    } else if let Some(name) = arg.strip_prefix("no") {
+        (name, SetValue::Off)
+    } else {
+        (arg, SetValue::On)
+    }
+}
+
+fn parse_bool(name: &str, value: SetValue) -> anyhow::Result<bool> {
+    match value {
+        SetValue::On => Ok(true),
+        SetValue::Off => Ok(false),
+        SetValue::Value(_) => bail!("Option {} takes no value", name),
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(name: &str, value: SetValue) -> anyhow::Result<T> {
+    match value {
+        SetValue::Value(v) => v
+            .parse()
+            .map_err(|_| anyhow::anyhow!("Invalid number for {}: {}", name, v)),
+        _ => bail!("Option {} needs a value", name),
+    }
+}
+
+fn parse_string(name: &str, value: SetValue) -> anyhow::Result<String> {
+    match value {
+        SetValue::Value(v) => Ok(v.to_string()),
+        _ => bail!("Option {} needs a value", name),
+    }
+}
+
+/// How `:w` treats the file it writes over, see [`crate::buffer::file::write_file`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackupCopy {
+    /// Overwrite in place only when renaming would lose hard links or the owner.
+    #[default]
+    Auto,
+    /// Always overwrite the file in place.
+    Yes,
+    /// Always write a new file and rename it over the old one.
+    No,
+}
+
+impl BackupCopy {
+    fn parse(value: &str) -> anyhow::Result<Self> {
+        Ok(match value {
+            "auto" => BackupCopy::Auto,
+            "yes" => BackupCopy::Yes,
+            "no" => BackupCopy::No,
+            _ => bail!("Invalid backupcopy: {}", value),
+        })
+    }
+}
+
+impl std::fmt::Display for BackupCopy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            BackupCopy::Auto => "auto",
+            BackupCopy::Yes => "yes",
+            BackupCopy::No => "no",
+        })
+    }
+}
+
+/// Options shared by the whole editor.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Lay out long lines over several screen rows instead of scrolling sideways.
+    pub wrap: bool,
+    /// Wrap long lines at a word boundary rather than at the last column.
+    pub linebreak: bool,
+    /// Marker shown at the start of each wrapped continuation row.
+    pub showbreak: String,
+    /// Capture the mouse for clicking, selecting and scrolling.
+    pub mouse: bool,
+    /// Highlight the chars `f`, `F`, `t` and `T` can jump to while waiting for one.
+    pub flash: bool,
+    /// Colour brackets by how deeply they are nested.
+    pub rainbow: bool,
+    /// Keep the old contents of a written file as `name~`.
+    pub backup: bool,
+    /// Whether a write renames a new file into place or overwrites the old one.
+    pub backupcopy: BackupCopy,
+    /// Keep unsaved changes in a swap file to recover them after a crash.
+    pub swapfile: bool,
+    /// Directory for swap files, empty for [`crate::buffer::swap::default_dir`].
+    pub directory: String,
+    /// Milliseconds without typing after which swap files are written.
+    pub updatetime: u64,
+    /// Size in MiB from which files open in large-file mode.
+    pub largefile: u64,
+    /// Take buffer options from `.editorconfig` files when opening files.
+    pub editorconfig: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            wrap: false,
+            linebreak: false,
+            showbreak: String::new(),
+            mouse: false,
+            flash: false,
+            rainbow: false,
+            backup: false,
+            backupcopy: BackupCopy::default(),
+            swapfile: true,
+            directory: String::new(),
+            updatetime: 4000,
+            largefile: 10,
+            editorconfig: true,
+        }
+    }
+}
+
+impl Options {
+    /// Sets the option `name`, returns `Ok(false)` if it is not an editor option.
+    pub fn set(&mut self, name: &str, value: SetValue) -> anyhow::Result<bool> {
+        match name {
+            "wrap" => self.wrap = parse_bool(name, value)?,
+            "linebreak" | "lbr" => self.linebreak = parse_bool(name, value)?,
+            "showbreak" | "sbr" => self.showbreak = parse_string(name, value)?,
+            "mouse" => self.mouse = parse_bool(name, value)?,
+            "flash" => self.flash = parse_bool(name, value)?,
+            "rainbow" => self.rainbow = parse_bool(name, value)?,
+            "backup" | "bk" => self.backup = parse_bool(name, value)?,
+            "backupcopy" | "bkc" => {
+                self.backupcopy = BackupCopy::parse(&parse_string(name, value)?)?;
+            }
+            "swapfile" | "swf" => self.swapfile = parse_bool(name, value)?,
+            "directory" | "dir" => self.directory = parse_string(name, value)?,
+            "updatetime" | "ut" => self.updatetime = parse_number(name, value)?,
+            "largefile" | "lf" => self.largefile = parse_number(name, value)?,
+            "editorconfig" | "ec" => self.editorconfig = parse_bool(name, value)?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Current value of the option `name` as shown by `:set name?`.
+    pub fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "wrap" => self.wrap.to_string(),
+            "linebreak" | "lbr" => self.linebreak.to_string(),
+            "showbreak" | "sbr" => self.showbreak.clone(),
+            "mouse" => self.mouse.to_string(),
+            "flash" => self.flash.to_string(),
+            "rainbow" => self.rainbow.to_string(),
+            "backup" | "bk" => self.backup.to_string(),
+            "backupcopy" | "bkc" => self.backupcopy.to_string(),
+            "swapfile" | "swf" => self.swapfile.to_string(),
+            "directory" | "dir" => self.directory.clone(),
+            "updatetime" | "ut" => self.updatetime.to_string(),
+            "largefile" | "lf" => self.largefile.to_string(),
+            "editorconfig" | "ec" => self.editorconfig.to_string(),
+            _ => return None,
+        })
+    }
+}
+
+/// The chars that make up a keyword, parsed from an `iskeyword` value such as
+/// `@,48-57,_,192-255`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeywordChars {
+    spec: String,
+    /// Ranges of char codes, `^` ranges take chars back out.
+    ranges: Vec<(u32, u32, bool)>,
+}
+
+impl KeywordChars {
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let char_code = |part: &str| -> Option<u32> {
+            let mut chars = part.chars();
+            match (chars.next(), chars.next()) {
+                (Some(ch), None) => Some(ch as u32),
+                _ => part.parse().ok(),
+            }
+        };
+
+        let mut ranges = Vec::new();
+        // A lone comma is a separator, `,` as a char is only reachable as 44
+        for part in spec.split(',').filter(|part| !part.is_empty()) {
+            let (exclude, part) = match part.strip_prefix('^') {
+                Some(rest) if !rest.is_empty() => (true, rest),
+                _ => (false, part),
+            };
+
+            if part == "@" {
+                // Letters below 256, anything above is decided by Unicode
+                ranges.push((u32::from(b'A'), u32::from(b'Z'), exclude));
+                ranges.push((u32::from(b'a'), u32::from(b'z'), exclude));
+                continue;
+            }
+
+            let (from, to) = match part.split_once('-').filter(|(from, _)| !from.is_empty()) {
+                Some((from, to)) => (char_code(from), char_code(to)),
+                None => (char_code(part), char_code(part)),
+            };
+            match (from, to) {
+                (Some(from), Some(to)) if from <= to => ranges.push((from, to, exclude)),
+                _ => bail!("Invalid iskeyword part: {}", part),
+            }
+        }
+
+        Ok(Self {
+            spec: spec.to_string(),
+            ranges,
+        })
+    }
+
+    pub fn contains(&self, ch: char) -> bool {
+        let code = ch as u32;
+        if code >= 256 {
+            return ch.is_alphanumeric();
+        }
+
+        self.ranges
+            .iter()
+            .fold(false, |keyword, &(from, to, exclude)| {
+                if (from..=to).contains(&code) {
+                    !exclude
+                } else {
+                    keyword
+                }
+            })
+    }
+}
+
+impl Default for KeywordChars {
+    fn default() -> Self {
+        Self::parse("@,48-57,_,192-255").expect("default iskeyword parses")
+    }
+}
+
+impl std::fmt::Display for KeywordChars {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.spec)
+    }
+}
+
+/// Options that belong to a single buffer.
+#[derive(Debug, Clone)]
+pub struct BufferOptions {
+    /// Number of screen columns a tab character spans.
+    pub tabstop: usize,
+    /// Columns used for each step of indentation, 0 means use `tabstop`.
+    pub shiftwidth: usize,
+    /// Insert spaces instead of tab characters.
+    pub expandtab: bool,
+    /// Columns a Tab or Backspace in insert mode counts for, 0 disables it and
+    /// a negative value means use `shiftwidth`.
+    pub softtabstop: isize,
+    /// Chars that are part of a word for `w`, `iw` and the like.
+    pub iskeyword: KeywordChars,
+    /// Refuse to write the buffer unless forced with `:w!`.
+    pub readonly: bool,
+    /// Allow changes to the text at all, off for things only to look at.
+    pub modifiable: bool,
+    /// Line endings the file is written with.
+    pub fileformat: FileFormat,
+    /// Encoding the file is written in.
+    pub fileencoding: FileEncoding,
+    /// Add a line break at the end of the file on writing if it has none.
+    pub fixendofline: bool,
+    /// Remove white space at the ends of lines on writing.
+    pub trimwhitespace: bool,
+    /// Redo the indentation of every line with tabs or spaces, as `expandtab`
+    /// says, on writing.
+    pub fixindent: bool,
+}
+
+impl Default for BufferOptions {
+    fn default() -> Self {
+        Self {
+            tabstop: 8,
+            shiftwidth: 8,
+            expandtab: false,
+            softtabstop: 0,
+            iskeyword: KeywordChars::default(),
+            readonly: false,
+            modifiable: true,
+            fileformat: FileFormat::default(),
+            fileencoding: FileEncoding::default(),
+            fixendofline: false,
+            trimwhitespace: false,
+            fixindent: false,
+        }
+    }
+}
+
+impl BufferOptions {
+    /// Sets the option `name`, returns `Ok(false)` if it is not a buffer option.
+    pub fn set(&mut self, name: &str, value: SetValue) -> anyhow::Result<bool> {
+        match name {
+            "tabstop" | "ts" => {
+                let tabstop: usize = parse_number(name, value)?;
+                if tabstop == 0 {
+                    bail!("Option tabstop must be positive");
+                }
+                self.tabstop = tabstop;
+            }
+            "shiftwidth" | "sw" => self.shiftwidth = parse_number(name, value)?,
+            "expandtab" | "et" => self.expandtab = parse_bool(name, value)?,
+            "softtabstop" | "sts" => self.softtabstop = parse_number(name, value)?,
+            "iskeyword" | "isk" => {
+                self.iskeyword = KeywordChars::parse(&parse_string(name, value)?)?;
+            }
+            "readonly" | "ro" => self.readonly = parse_bool(name, value)?,
+            "modifiable" | "ma" => self.modifiable = parse_bool(name, value)?,
+            "fileformat" | "ff" => {
+                self.fileformat = FileFormat::parse(&parse_string(name, value)?)?;
+            }
+            "fileencoding" | "fenc" => {
+                self.fileencoding = FileEncoding::parse(&parse_string(name, value)?)?;
+            }
+            "fixendofline" | "fixeol" => self.fixendofline = parse_bool(name, value)?,
+            "trimwhitespace" | "tws" => self.trimwhitespace = parse_bool(name, value)?,
+            "fixindent" | "fi" => self.fixindent = parse_bool(name, value)?,
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Current value of the option `name` as shown by `:set name?`.
+    pub fn get(&self, name: &str) -> Option<String> {
+        Some(match name {
+            "tabstop" | "ts" => self.tabstop.to_string(),
+            "shiftwidth" | "sw" => self.shiftwidth.to_string(),
+            "expandtab" | "et" => self.expandtab.to_string(),
+            "softtabstop" | "sts" => self.softtabstop.to_string(),
+            "iskeyword" | "isk" => self.iskeyword.to_string(),
+            "readonly" | "ro" => self.readonly.to_string(),
+            "modifiable" | "ma" => self.modifiable.to_string(),
+            "fileformat" | "ff" => self.fileformat.to_string(),
+            "fileencoding" | "fenc" => self.fileencoding.to_string(),
+            "fixendofline" | "fixeol" => self.fixendofline.to_string(),
+            "trimwhitespace" | "tws" => self.trimwhitespace.to_string(),
+            "fixindent" | "fi" => self.fixindent.to_string(),
+            _ => return None,
+        })
+    }
+
+    pub fn shift_width(&self) -> usize {
+        if self.shiftwidth == 0 {
+            self.tabstop
+        } else {
+            self.shiftwidth
+        }
+    }
+
+    /// Effective soft tab stop, `None` when Tab and Backspace behave normally.
+    pub fn soft_tab_stop(&self) -> Option<usize> {
+        match self.softtabstop {
+            0 => None,
+            sts if sts < 0 => Some(self.shift_width()),
+            sts => Some(sts as usize),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use std::collections::HashMap;
+
+/// The register yanks and deletes go to when none is named.
+pub const UNNAMED: char = '"';
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Register {
+    pub text: String,
+    /// Holds whole lines, which are put above or below the cursor line.
+    pub linewise: bool,
+}
+
+#[derive(Default)]
+pub struct Registers {
+    registers: HashMap<char, Register>,
+}
+
+impl Registers {
+    pub fn get(&self, name: char) -> Option<&Register> {
+        self.registers.get(&name.to_ascii_lowercase())
+    }
+
+    /// Whether `"name` names a register: a letter, a digit or `"` itself.
+    pub fn is_valid(name: char) -> bool {
+        name.is_ascii_alphanumeric() || name == UNNAMED
+    }
+
+    /// Stores `register` under `name`, the unnamed register always gets a copy.
+    /// An uppercase name appends to the lowercase register.
+    pub fn set(&mut self, name: char, register: Register) {
+        let register = self.store(name, register);
+        if name != UNNAMED {
+            self.registers.insert(UNNAMED, register);
+        }
+    }
+
+    /// Stores keys recorded with `q`, which leaves the unnamed register alone.
+    pub fn record(&mut self, name: char, keys: String) {
+        let register = Register {
+            text: keys,
+            linewise: false,
+        };
+        self.store(name, register);
+    }
+
+    fn store(&mut self, name: char, register: Register) -> Register {
+        let register = match self.registers.get(&name.to_ascii_lowercase()) {
+            Some(existing) if name.is_ascii_uppercase() => Register {
+                text: existing.text.clone() + &register.text,
+                linewise: existing.linewise || register.linewise,
+            },
+            _ => register,
+        };
+        self.registers
+            .insert(name.to_ascii_lowercase(), register.clone());
+        register
+    }
+}
@@ -0,0 +1,119 @@
+use anyhow::{anyhow, bail};
+use regex::Regex;
+
+/// The `/pattern/replacement/flags` part of a `:s` command.
+#[derive(Debug, Clone)]
+pub struct Substitute {
+    pub regex: Regex,
+    pub replacement: String,
+    /// `g`: every match on a line instead of only the first.
+    pub global: bool,
+}
+
+impl Substitute {
+    /// Parses `args` as written after `:s`. Any char other than a letter,
+    /// digit or white space can stand in for `/`, a `\` before it makes it
+    /// part of the text. Flags are `g` and `i` for ignoring case.
+    pub fn parse(args: &str) -> anyhow::Result<Substitute> {
+        let mut chars = args.chars();
+        let delimiter = chars
+            .next()
+            .filter(|ch| !ch.is_alphanumeric() && !ch.is_whitespace() && *ch != '\\')
+            .ok_or_else(|| anyhow!("Invalid substitute: {}", args))?;
+
+        let parts = split_unescaped(chars.as_str(), delimiter);
+        let (pattern, replacement, flags) = match parts.as_slice() {
+            [pattern] => (pattern.as_str(), "", ""),
+            [pattern, replacement] => (pattern.as_str(), replacement.as_str(), ""),
+            [pattern, replacement, flags] => (pattern.as_str(), replacement.as_str(), flags.trim()),
+            _ => bail!("Trailing characters: {}", args),
+        };
+        if pattern.is_empty() {
+            bail!("Empty pattern");
+        }
+
+        let mut global = false;
+        let mut ignore_case = false;
+        for flag in flags.chars() {
+            match flag {
+                'g' => global = true,
+                'i' => ignore_case = true,
+                _ => bail!("Invalid flag: {}", flag),
+            }
+        }
+
+        let source = if ignore_case {
+            format!("(?i){}", pattern)
+        } else {
+            pattern.to_string()
+        };
+        let regex = Regex::new(&source).map_err(|_| anyhow!("Invalid pattern: {}", pattern))?;
+
+        Ok(Substitute {
+            regex,
+            replacement: replacement.to_string(),
+            global,
+        })
+    }
+
+    /// `line` with the replacement made, and how many matches there were.
+    /// `None` when nothing matched.
+    pub fn apply(&self, line: &str) -> Option<(String, usize)> {
+        let matches = self.regex.find_iter(line);
+        let count = if self.global {
+            matches.count()
+        } else {
+            matches.take(1).count()
+        };
+        if count == 0 {
+            return None;
+        }
+
+        let limit = if self.global { 0 } else { 1 };
+        let replaced = self.regex.replacen(line, limit, self.replacement.as_str());
+        Some((replaced.into_owned(), count))
+    }
+}
+
+/// Splits `text` at every `delimiter` that isn't escaped with `\`, turning
+/// `\` and the delimiter into just the delimiter. Other escapes are kept
+/// for the regex.
+fn split_unescaped(text: &str, delimiter: char) -> Vec<String> {
+    let mut parts = vec![String::new()];
+    let mut chars = text.chars().peekable();
+    while let Some(ch) = chars.next() {
+        match ch {
+            '\\' if chars.peek() == Some(&delimiter) => {
+                parts.last_mut().unwrap().push(delimiter);
+                chars.next();
+            }
+            ch if ch == delimiter => parts.push(String::new()),
+            ch => parts.last_mut().unwrap().push(ch),
+        }
+    }
+    parts
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_substitute_parses_and_replaces() {
+        let sub = Substitute::parse("/foo/bar/").unwrap();
+        assert_eq!(sub.apply("foo foo"), Some(("bar foo".to_string(), 1)));
+        assert_eq!(sub.apply("nothing"), None);
+
+        let sub = Substitute::parse("#(\\w+)\\#/#<$1>#gi").unwrap();
+        assert_eq!(sub.apply("A#/ b#/"), Some(("<A> <b>".to_string(), 2)));
+
+        // Only `$` is special in the replacement
+        let sub = Substitute::parse("/x/a\\b").unwrap();
+        assert_eq!(sub.apply("x").unwrap().0, "a\\b");
+
+        assert!(Substitute::parse("").is_err());
+        assert!(Substitute::parse("//x/").is_err());
+        assert!(Substitute::parse("/a/b/q").is_err());
+        assert!(Substitute::parse("/(/b/").is_err());
+    }
+}
@@ -0,0 +1,519 @@
+use std::ops::Range;
+
+use crate::buffer::buffer::Buffer;
+
+use super::motion::{CharClass, char_class};
+
+/// The text a text object such as `iw` or `a(` covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TextObject {
+    /// Bytes of the buffer text.
+    pub range: Range<usize>,
+    /// Covers whole lines, like `ip`.
+    pub linewise: bool,
+}
+
+impl TextObject {
+    fn chars(range: Range<usize>) -> Self {
+        Self {
+            range,
+            linewise: false,
+        }
+    }
+}
+
+/// Bytes of the line around `pos`, without its newline.
+fn line_bounds(text: &str, pos: usize) -> Range<usize> {
+    let start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let end = text[pos..].find('\n').map_or(text.len(), |i| pos + i);
+    start..end
+}
+
+/// Bytes of whole lines `first..=last`, including the newline of the last.
+fn lines_range(buffer: &Buffer, first: usize, last: usize) -> Range<usize> {
+    let end = buffer
+        .line_offsets
+        .get(last + 1)
+        .copied()
+        .unwrap_or(buffer.text.len());
+    buffer.line_offsets[first]..end
+}
+
+/// Length of the spaces and tabs `text` starts with.
+fn leading_blanks(text: &str) -> usize {
+    text.len() - text.trim_start_matches([' ', '\t']).len()
+}
+
+/// Length of the spaces and tabs `text` ends with.
+fn trailing_blanks(text: &str) -> usize {
+    text.len() - text.trim_end_matches([' ', '\t']).len()
+}
+
+/// `iw` and `aw`, or `iW` and `aW` with `bigword`.
+pub fn word(
+    buffer: &Buffer,
+    pos: usize,
+    around: bool,
+    bigword: bool,
+    count: usize,
+) -> Option<TextObject> {
+    let text = &buffer.text;
+    let line = line_bounds(text, pos);
+    if line.is_empty() {
+        return None;
+    }
+    // Past the end of the line counts as the last char
+    let pos = if pos >= line.end {
+        text[..line.end].char_indices().next_back()?.0
+    } else {
+        pos
+    };
+
+    let keyword = &buffer.options.iskeyword;
+    let class_at = |i: usize| char_class(text[i..].chars().next().unwrap_or(' '), bigword, keyword);
+    let run_start = |mut i: usize| {
+        let class = class_at(i);
+        while let Some((j, ch)) = text[line.start..i].char_indices().next_back() {
+            if char_class(ch, bigword, keyword) != class {
+                break;
+            }
+            i = line.start + j;
+        }
+        i
+    };
+    let run_end = |i: usize| {
+        let class = class_at(i);
+        text[i..line.end]
+            .chars()
+            .take_while(|&ch| char_class(ch, bigword, keyword) == class)
+            .map(char::len_utf8)
+            .sum::<usize>()
+            + i
+    };
+
+    let on_space = class_at(pos) == CharClass::Whitespace;
+    let mut start = run_start(pos);
+    let mut end = run_end(pos);
+
+    if around {
+        if on_space {
+            // White space and the word after it
+            if end < line.end {
+                end = run_end(end);
+            }
+        } else if end < line.end && class_at(end) == CharClass::Whitespace {
+            end = run_end(end);
+        } else if let Some((before, ch)) = text[line.start..start].char_indices().next_back()
+            && ch.is_whitespace()
+        {
+            // Without white space after the word the white space before it goes
+            start = run_start(line.start + before);
+        }
+    }
+
+    for _ in 1..count {
+        if end >= line.end {
+            break;
+        }
+        end = run_end(end);
+        if around && end < line.end && (on_space || class_at(end) == CharClass::Whitespace) {
+            end = run_end(end);
+        }
+    }
+
+    Some(TextObject::chars(start..end))
+}
+
+/// Sentences in `text`, each ending after its `.`, `!` or `?` and any closing
+/// quotes or brackets.
+fn sentences(text: &str) -> Vec<Range<usize>> {
+    let chars: Vec<(usize, char)> = text.char_indices().collect();
+    let mut spans = Vec::new();
+    let mut start = None;
+
+    let mut i = 0;
+    while i < chars.len() {
+        let (byte, ch) = chars[i];
+        if start.is_none() {
+            if ch.is_whitespace() {
+                i += 1;
+                continue;
+            }
+            start = Some(byte);
+        }
+
+        if matches!(ch, '.' | '!' | '?') {
+            let mut j = i + 1;
+            while j < chars.len() && matches!(chars[j].1, ')' | ']' | '"' | '\'') {
+                j += 1;
+            }
+            if j == chars.len() || chars[j].1.is_whitespace() {
+                let end = chars.get(j).map_or(text.len(), |&(byte, _)| byte);
+                spans.extend(start.take().map(|start| start..end));
+                i = j;
+                continue;
+            }
+        }
+        i += 1;
+    }
+
+    if let Some(start) = start {
+        spans.push(start..text.trim_end().len());
+    }
+    spans
+}
+
+/// `is` and `as`, sentences don't run past a blank line.
+pub fn sentence(buffer: &Buffer, pos: usize, around: bool) -> Option<TextObject> {
+    let text = &buffer.text;
+    let line = buffer.byte_to_cursor(pos).y;
+    let blank = |y: usize| buffer.get_line(y).unwrap_or("").trim().is_empty();
+    if blank(line) {
+        return None;
+    }
+
+    let mut first = line;
+    while first > 0 && !blank(first - 1) {
+        first -= 1;
+    }
+    let mut last = line;
+    while last + 1 < buffer.line_count() && !blank(last + 1) {
+        last += 1;
+    }
+    let paragraph = lines_range(buffer, first, last);
+
+    let spans = sentences(&text[paragraph.clone()]);
+    let span = spans
+        .iter()
+        .find(|span| pos < paragraph.start + span.end)
+        .or(spans.last())?;
+    let mut range = paragraph.start + span.start..paragraph.start + span.end;
+
+    if around {
+        let rest = &text[range.end..paragraph.end];
+        let trailing = rest.len() - rest.trim_start().len();
+        if trailing > 0 {
+            range.end += trailing;
+        } else {
+            let before = &text[paragraph.start..range.start];
+            range.start -= before.len() - before.trim_end().len();
+        }
+    }
+
+    Some(TextObject::chars(range))
+}
+
+/// `ip` and `ap` on `line`, a paragraph being a run of blank or non-blank lines.
+pub fn paragraph(buffer: &Buffer, line: usize, around: bool, count: usize) -> Option<TextObject> {
+    let last = buffer.line_count().checked_sub(1)?;
+    let blank = |y: usize| buffer.get_line(y).unwrap_or("").trim().is_empty();
+    let run_end = |y: usize| {
+        let mut end = y;
+        while end < last && blank(end + 1) == blank(y) {
+            end += 1;
+        }
+        end
+    };
+
+    let mut start = line;
+    while start > 0 && blank(start - 1) == blank(line) {
+        start -= 1;
+    }
+    let mut end = run_end(line);
+
+    if around {
+        if end < last {
+            end = run_end(end + 1);
+        } else if !blank(line) && start > 0 {
+            // Nothing blank after the last paragraph, take the blank lines before it
+            start -= 1;
+            while start > 0 && blank(start - 1) {
+                start -= 1;
+            }
+        }
+    }
+
+    for _ in 1..count {
+        if end >= last {
+            break;
+        }
+        end = run_end(end + 1);
+        if around && end < last {
+            end = run_end(end + 1);
+        }
+    }
+
+    Some(TextObject {
+        range: lines_range(buffer, start, end),
+        linewise: true,
+    })
+}
+
+/// `i"` and `a"` and the like, quotes pair up from the start of the line.
+pub fn quote(buffer: &Buffer, pos: usize, quote: char, around: bool) -> Option<TextObject> {
+    let text = &buffer.text;
+    let line = line_bounds(text, pos);
+
+    let quotes: Vec<usize> = text[line.clone()]
+        .char_indices()
+        .map(|(i, ch)| (line.start + i, ch))
+        .filter(|&(i, ch)| ch == quote && !text[..i].ends_with('\\'))
+        .map(|(i, _)| i)
+        .collect();
+    // The quoted text around the cursor, or else the next one on the line
+    let pair = quotes.chunks_exact(2).find(|pair| pos <= pair[1])?;
+    let (open, close) = (pair[0], pair[1]);
+
+    if !around {
+        return Some(TextObject::chars(open + quote.len_utf8()..close));
+    }
+
+    let mut range = open..close + quote.len_utf8();
+    let trailing = leading_blanks(&text[range.end..line.end]);
+    if trailing > 0 {
+        range.end += trailing;
+    } else {
+        range.start -= trailing_blanks(&text[line.start..range.start]);
+    }
+    Some(TextObject::chars(range))
+}
+
+/// The unmatched `open` before `end`.
+fn enclosing_open(text: &str, end: usize, open: char, close: char) -> Option<usize> {
+    let mut depth = 0;
+    for (i, ch) in text[..end].char_indices().rev() {
+        if ch == close {
+            depth += 1;
+        } else if ch == open {
+            if depth == 0 {
+                return Some(i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// The `close` matching the `open` at `open_pos`.
+fn matching_close(text: &str, open_pos: usize, open: char, close: char) -> Option<usize> {
+    let from = open_pos + open.len_utf8();
+    let mut depth = 0;
+    for (i, ch) in text[from..].char_indices() {
+        if ch == open {
+            depth += 1;
+        } else if ch == close {
+            if depth == 0 {
+                return Some(from + i);
+            }
+            depth -= 1;
+        }
+    }
+    None
+}
+
+/// Inside of the block `open..=close`. A block over several lines leaves the
+/// line breaks after the opening and before the closing bracket alone.
+pub fn block_inner(text: &str, open: usize, close: usize) -> Range<usize> {
+    let open_len = text[open..].chars().next().map_or(1, char::len_utf8);
+    let mut inner = open + open_len..close;
+
+    if text[inner.clone()].starts_with('\n') {
+        inner.start += 1;
+    }
+    if let Some(newline) = text[inner.clone()].rfind('\n') {
+        let last_line = inner.start + newline + 1;
+        if text[last_line..inner.end].trim().is_empty() {
+            inner.end = last_line;
+        }
+    }
+    if inner.start > inner.end {
+        inner.start = inner.end;
+    }
+
+    inner
+}
+
+/// `i(`, `a{` and the like, with a count going out that many blocks.
+pub fn bracket(
+    buffer: &Buffer,
+    pos: usize,
+    open: char,
+    close: char,
+    around: bool,
+    count: usize,
+) -> Option<TextObject> {
+    let text = &buffer.text;
+
+    // On a bracket the block is the one it belongs to
+    let mut open_pos = if text[pos..].starts_with(open) {
+        pos
+    } else {
+        enclosing_open(text, pos, open, close)?
+    };
+    for _ in 1..count {
+        open_pos = enclosing_open(text, open_pos, open, close)?;
+    }
+    let close_pos = matching_close(text, open_pos, open, close)?;
+
+    Some(TextObject::chars(if around {
+        open_pos..close_pos + close.len_utf8()
+    } else {
+        block_inner(text, open_pos, close_pos)
+    }))
+}
+
+/// `it` and `at`, the contents of an XML or HTML tag.
+pub fn tag(buffer: &Buffer, pos: usize, around: bool, count: usize) -> Option<TextObject> {
+    let text = &buffer.text;
+
+    // Pair opening and closing tags up, skipping any that aren't closed
+    let mut open_tags: Vec<(&str, Range<usize>)> = Vec::new();
+    let mut pairs: Vec<(Range<usize>, Range<usize>)> = Vec::new();
+    let mut rest = 0;
+    while let Some(offset) = text[rest..].find('<') {
+        let start = rest + offset;
+        let Some(len) = text[start..].find('>') else {
+            break;
+        };
+        let end = start + len + 1;
+        let contents = &text[start + 1..end - 1];
+
+        let (closing, name) = match contents.strip_prefix('/') {
+            Some(name) => (true, name.trim()),
+            None => (false, contents.split_whitespace().next().unwrap_or("")),
+        };
+        // Comparisons, comments and the like aren't tags
+        if contents.contains('<') || !name.starts_with(|c: char| c.is_alphabetic()) {
+            rest = start + 1;
+            continue;
+        }
+        rest = end;
+
+        if contents.ends_with('/') {
+            continue;
+        }
+        if !closing {
+            open_tags.push((name, start..end));
+        } else if let Some(i) = open_tags.iter().rposition(|(open, _)| *open == name) {
+            let open = open_tags[i].1.clone();
+            open_tags.truncate(i);
+            pairs.push((open, start..end));
+        }
+    }
+
+    let mut around_pos: Vec<_> = pairs
+        .into_iter()
+        .filter(|(open, close)| open.start <= pos && pos < close.end)
+        .collect();
+    around_pos.sort_by_key(|(open, close)| close.end - open.start);
+    let (open, close) = around_pos.into_iter().nth(count.saturating_sub(1))?;
+
+    Some(TextObject::chars(if around {
+        open.start..close.end
+    } else {
+        open.end..close.start
+    }))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn object_text(buffer: &Buffer, object: Option<TextObject>) -> &str {
+        &buffer.text[object.expect("object found").range]
+    }
+
+    #[test]
+    fn test_word_and_sentence_objects() {
+        let buffer = Buffer::new(
+            "test".to_string(),
+            "foo.bar  baz. Second one!  Third".to_string(),
+        );
+
+        assert_eq!(
+            object_text(&buffer, word(&buffer, 5, false, false, 1)),
+            "bar"
+        );
+        assert_eq!(
+            object_text(&buffer, word(&buffer, 5, true, false, 1)),
+            "bar  "
+        );
+        assert_eq!(
+            object_text(&buffer, word(&buffer, 5, false, true, 1)),
+            "foo.bar"
+        );
+        assert_eq!(
+            object_text(&buffer, word(&buffer, 0, false, false, 3)),
+            "foo.bar"
+        );
+        assert_eq!(
+            object_text(&buffer, word(&buffer, 28, true, false, 1)),
+            "  Third"
+        );
+
+        assert_eq!(
+            object_text(&buffer, sentence(&buffer, 16, false)),
+            "Second one!"
+        );
+        assert_eq!(
+            object_text(&buffer, sentence(&buffer, 16, true)),
+            "Second one!  "
+        );
+    }
+
+    #[test]
+    fn test_paragraph_objects() {
+        let buffer = Buffer::new("test".to_string(), "a\nb\n\n\nc\n".to_string());
+
+        let inner = paragraph(&buffer, 1, false, 1).unwrap();
+        assert!(inner.linewise);
+        assert_eq!(object_text(&buffer, Some(inner)), "a\nb\n");
+        assert_eq!(
+            object_text(&buffer, paragraph(&buffer, 0, true, 1)),
+            "a\nb\n\n\n"
+        );
+        assert_eq!(
+            object_text(&buffer, paragraph(&buffer, 2, true, 1)),
+            "\n\nc\n"
+        );
+    }
+
+    #[test]
+    fn test_quote_bracket_and_tag_objects() {
+        let buffer = Buffer::new(
+            "test".to_string(),
+            "say(\"hi \\\" there\", f(x)) <a><b>text</b></a>".to_string(),
+        );
+
+        assert_eq!(
+            object_text(&buffer, quote(&buffer, 6, '"', false)),
+            "hi \\\" there"
+        );
+        assert_eq!(
+            object_text(&buffer, quote(&buffer, 0, '"', true)),
+            "\"hi \\\" there\""
+        );
+        assert_eq!(
+            object_text(&buffer, bracket(&buffer, 22, '(', ')', false, 1)),
+            "x"
+        );
+        assert_eq!(
+            object_text(&buffer, bracket(&buffer, 22, '(', ')', true, 2)),
+            "(\"hi \\\" there\", f(x))"
+        );
+        assert_eq!(object_text(&buffer, tag(&buffer, 33, false, 1)), "text");
+        assert_eq!(
+            object_text(&buffer, tag(&buffer, 33, true, 2)),
+            "<a><b>text</b></a>"
+        );
+    }
+
+    #[test]
+    fn test_block_inner_keeps_line_breaks() {
+        let text = "fn f() {\n    body;\n}";
+        let open = text.find('{').unwrap();
+        let close = text.rfind('}').unwrap();
+
+        assert_eq!(&text[block_inner(text, open, close)], "    body;\n");
+    }
+}
@@ -7,6 +7,12 @@ pub struct Theme {
     pub gutter: Style,
     pub statusline: Style,
     pub command_prompt: Style,
+    pub error: Style,
+    pub selection: Style,
+    /// The bracket under the cursor and its partner.
+    pub match_paren: Style,
+    /// Chars `f` can jump to, highlighted while its char is being typed.
+    pub flash: Style,
 }
 
 #[derive(Debug, Default, Clone, PartialEq, Eq)]
@@ -17,7 +23,15 @@ pub struct Style {
     pub italic: bool,
 }
 
+/// Scopes rainbow brackets are coloured from, one per nesting level.
+pub const RAINBOW_SCOPES: [&str; 4] = ["rainbow.1", "rainbow.2", "rainbow.3", "rainbow.4"];
+
 impl Theme {
+    /// Style for brackets nested `depth` pairs deep when rainbow brackets are on.
+    pub fn rainbow(&self, depth: usize) -> Option<Style> {
+        self.get_style(RAINBOW_SCOPES[depth % RAINBOW_SCOPES.len()])
+    }
+
     pub fn get_style(&self, scope: &str) -> Option<Style> {
         match scope {
             "function" => Some(Style {
@@ -55,12 +69,48 @@ impl Theme {
                 italic: true,
                 ..Style::default()
             }),
+            "rainbow.1" => Some(Style {
+                text_color: Some(Color::Rgb {
+                    r: 255,
+                    g: 215,
+                    b: 0,
+                }), // Gold
+                ..Style::default()
+            }),
+            "rainbow.2" => Some(Style {
+                text_color: Some(Color::Rgb {
+                    r: 218,
+                    g: 112,
+                    b: 214,
+                }), // Orchid
+                ..Style::default()
+            }),
+            "rainbow.3" => Some(Style {
+                text_color: Some(Color::Rgb {
+                    r: 23,
+                    g: 159,
+                    b: 255,
+                }), // Sky blue
+                ..Style::default()
+            }),
+            "rainbow.4" => Some(Style {
+                text_color: Some(Color::Rgb {
+                    r: 80,
+                    g: 250,
+                    b: 123,
+                }), // Green
+                ..Style::default()
+            }),
             _ => None,
         }
     }
 
     pub fn get_selection_background_color(&self) -> Color {
-        todo!()
+        self.selection.background_color.unwrap_or(Color::Rgb {
+            r: 92,
+            g: 60,
+            b: 110,
+        })
     }
 }
 
@@ -108,7 +158,6 @@ impl Default for Theme {
                 }),
                 bold: false,
                 italic: false,
-                ..Default::default()
             },
             command_prompt: Style {
                 text_color: Some(Color::Rgb {
@@ -123,6 +172,40 @@ impl Default for Theme {
                 }),
                 ..Default::default()
             },
+            error: Style {
+                text_color: Some(Color::Rgb {
+                    r: 255,
+                    g: 85,
+                    b: 85,
+                }),
+                ..Default::default()
+            },
+            selection: Style {
+                background_color: Some(Color::Rgb {
+                    r: 92,
+                    g: 60,
+                    b: 110,
+                }),
+                ..Default::default()
+            },
+            match_paren: Style {
+                background_color: Some(Color::Rgb {
+                    r: 98,
+                    g: 114,
+                    b: 164,
+                }),
+                bold: true,
+                ..Default::default()
+            },
+            flash: Style {
+                text_color: Some(Color::Rgb {
+                    r: 255,
+                    g: 184,
+                    b: 108,
+                }), // Orange
+                bold: true,
+                ..Default::default()
+            },
         }
     }
 }
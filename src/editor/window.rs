@@ -0,0 +1,353 @@
+use std::collections::HashMap;
+
+use crate::cursor::Cursor;
+
+pub type WindowId = usize;
+
+/// An area of the screen.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        (self.x..self.x + self.width).contains(&x) && (self.y..self.y + self.height).contains(&y)
+    }
+}
+
+/// A view of a buffer with its own cursor and scroll position.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub id: WindowId,
+    /// Index into the editor's buffers.
+    pub buffer: usize,
+    pub cursor: Cursor,
+    pub viewport_x: usize,
+    pub viewport_y: usize,
+    /// Where the window was last laid out, the bottom row is its status line.
+    pub rect: Rect,
+    /// Places jumped away from, oldest first, for `Ctrl-o` and `Ctrl-i`.
+    pub jumps: Vec<Jump>,
+    /// The entry `Ctrl-o` and `Ctrl-i` go from, the end when not going through them.
+    pub jump_index: usize,
+}
+
+/// An entry in a jump list, its position is kept as a mark in the buffer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Jump {
+    pub buffer: usize,
+    pub id: usize,
+}
+
+impl Window {
+    pub fn new(id: WindowId, buffer: usize) -> Self {
+        Self {
+            id,
+            buffer,
+            cursor: Cursor::default(),
+            viewport_x: 0,
+            viewport_y: 0,
+            rect: Rect::default(),
+            jumps: Vec::new(),
+            jump_index: 0,
+        }
+    }
+
+    /// Number of rows available for text, without the status line.
+    pub fn text_height(&self) -> usize {
+        self.rect.height.saturating_sub(1)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDirection {
+    /// Windows stacked on top of each other, as made by `:split`.
+    Horizontal,
+    /// Windows side by side, as made by `:vsplit`.
+    Vertical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+/// How windows are arranged on screen.
+#[derive(Debug, Clone)]
+pub enum WindowTree {
+    Leaf(WindowId),
+    /// Children with their share of the space, relative to each other.
+    Split {
+        direction: SplitDirection,
+        children: Vec<(WindowTree, f32)>,
+    },
+}
+
+impl WindowTree {
+    /// Splits the window `target` in two, putting `new` above or left of it.
+    pub fn split(&mut self, target: WindowId, new: WindowId, direction: SplitDirection) -> bool {
+        match self {
+            WindowTree::Leaf(id) if *id == target => {
+                *self = WindowTree::Split {
+                    direction,
+                    children: vec![
+                        (WindowTree::Leaf(new), 1.0),
+                        (WindowTree::Leaf(target), 1.0),
+                    ],
+                };
+                true
+            }
+            WindowTree::Leaf(_) => false,
+            WindowTree::Split {
+                direction: split_direction,
+                children,
+            } => {
+                // Splitting along the same direction adds a sibling instead of nesting
+                if *split_direction == direction
+                    && let Some(i) = children.iter().position(
+                        |(child, _)| matches!(child, WindowTree::Leaf(id) if *id == target),
+                    )
+                {
+                    let weight = children[i].1 / 2.0;
+                    children[i].1 = weight;
+                    children.insert(i, (WindowTree::Leaf(new), weight));
+                    return true;
+                }
+
+                children
+                    .iter_mut()
+                    .any(|(child, _)| child.split(target, new, direction))
+            }
+        }
+    }
+
+    /// Removes the window `target`, its space goes to a neighbour.
+    pub fn remove(&mut self, target: WindowId) -> bool {
+        let WindowTree::Split { children, .. } = self else {
+            // The last window can't be removed
+            return false;
+        };
+
+        if let Some(i) = children
+            .iter()
+            .position(|(child, _)| matches!(child, WindowTree::Leaf(id) if *id == target))
+        {
+            let (_, weight) = children.remove(i);
+            let neighbour = i.min(children.len() - 1);
+            children[neighbour].1 += weight;
+        } else if !children.iter_mut().any(|(child, _)| child.remove(target)) {
+            return false;
+        }
+
+        if children.len() == 1 {
+            let (only, _) = children.remove(0);
+            *self = only;
+        }
+        true
+    }
+
+    /// All windows, in order from top left to bottom right.
+    pub fn windows(&self) -> Vec<WindowId> {
+        match self {
+            WindowTree::Leaf(id) => vec![*id],
+            WindowTree::Split { children, .. } => children
+                .iter()
+                .flat_map(|(child, _)| child.windows())
+                .collect(),
+        }
+    }
+
+    /// Gives every window the same amount of space.
+    pub fn equalize(&mut self) {
+        if let WindowTree::Split { children, .. } = self {
+            for (child, weight) in children {
+                *weight = 1.0;
+                child.equalize();
+            }
+        }
+    }
+
+    /// Lays the windows out over `area`, returns their rects and the separator
+    /// columns between side by side windows.
+    pub fn arrange(&self, area: Rect) -> (Vec<(WindowId, Rect)>, Vec<Rect>) {
+        let mut windows = Vec::new();
+        let mut separators = Vec::new();
+        self.arrange_into(area, &mut windows, &mut separators);
+        (windows, separators)
+    }
+
+    fn arrange_into(
+        &self,
+        area: Rect,
+        windows: &mut Vec<(WindowId, Rect)>,
+        separators: &mut Vec<Rect>,
+    ) {
+        let (direction, children) = match self {
+            WindowTree::Leaf(id) => {
+                windows.push((*id, area));
+                return;
+            }
+            WindowTree::Split {
+                direction,
+                children,
+            } => (*direction, children),
+        };
+
+        let vertical = direction == SplitDirection::Vertical;
+        let separator_count = if vertical { children.len() - 1 } else { 0 };
+        let total = if vertical { area.width } else { area.height }.saturating_sub(separator_count);
+        let weight_sum: f32 = children.iter().map(|(_, weight)| weight).sum();
+
+        let mut position = if vertical { area.x } else { area.y };
+        let mut remaining = total;
+        for (i, (child, weight)) in children.iter().enumerate() {
+            let size = if i + 1 == children.len() {
+                remaining
+            } else {
+                ((total as f32 * weight / weight_sum).round() as usize).min(remaining)
+            };
+            remaining -= size;
+
+            let rect = if vertical {
+                Rect {
+                    x: position,
+                    width: size,
+                    ..area
+                }
+            } else {
+                Rect {
+                    y: position,
+                    height: size,
+                    ..area
+                }
+            };
+            child.arrange_into(rect, windows, separators);
+            position += size;
+
+            if vertical && i + 1 < children.len() {
+                separators.push(Rect {
+                    x: position,
+                    width: 1,
+                    ..area
+                });
+                position += 1;
+            }
+        }
+    }
+}
+
+/// A tab page with its own set of windows.
+#[derive(Debug, Clone)]
+pub struct TabPage {
+    /// The window that had the cursor when the tab page was left.
+    pub window: Window,
+    pub windows: HashMap<WindowId, Window>,
+    pub window_tree: WindowTree,
+}
+
+/// The window next to `from` in `direction`, preferring the one level with
+/// the screen position (`x`, `y`).
+pub fn neighbour(
+    rects: &[(WindowId, Rect)],
+    from: Rect,
+    direction: Direction,
+    (x, y): (usize, usize),
+) -> Option<WindowId> {
+    let candidates: Vec<&(WindowId, Rect)> = rects
+        .iter()
+        .filter(|(_, rect)| {
+            let overlaps_rows = rect.y < from.y + from.height && from.y < rect.y + rect.height;
+            let overlaps_cols = rect.x < from.x + from.width && from.x < rect.x + rect.width;
+            match direction {
+                // Side by side windows have a separator column between them
+                Direction::Left => rect.x + rect.width + 1 == from.x && overlaps_rows,
+                Direction::Right => from.x + from.width + 1 == rect.x && overlaps_rows,
+                Direction::Up => rect.y + rect.height == from.y && overlaps_cols,
+                Direction::Down => from.y + from.height == rect.y && overlaps_cols,
+            }
+        })
+        .collect();
+
+    candidates
+        .iter()
+        .find(|(_, rect)| match direction {
+            Direction::Left | Direction::Right => (rect.y..rect.y + rect.height).contains(&y),
+            Direction::Up | Direction::Down => (rect.x..rect.x + rect.width).contains(&x),
+        })
+        .or(candidates.first())
+        .map(|(id, _)| *id)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_arrange_and_remove() {
+        let mut tree = WindowTree::Leaf(0);
+        assert!(tree.split(0, 1, SplitDirection::Vertical));
+        assert!(tree.split(0, 2, SplitDirection::Horizontal));
+        assert_eq!(tree.windows(), vec![1, 2, 0]);
+
+        let area = Rect {
+            x: 0,
+            y: 0,
+            width: 41,
+            height: 20,
+        };
+        let (rects, separators) = tree.arrange(area);
+        assert_eq!(
+            rects,
+            vec![
+                (
+                    1,
+                    Rect {
+                        x: 0,
+                        y: 0,
+                        width: 20,
+                        height: 20
+                    }
+                ),
+                (
+                    2,
+                    Rect {
+                        x: 21,
+                        y: 0,
+                        width: 20,
+                        height: 10
+                    }
+                ),
+                (
+                    0,
+                    Rect {
+                        x: 21,
+                        y: 10,
+                        width: 20,
+                        height: 10
+                    }
+                ),
+            ]
+        );
+        assert_eq!(separators.len(), 1);
+        assert_eq!(
+            neighbour(&rects, rects[0].1, Direction::Right, (5, 15)),
+            Some(0)
+        );
+        assert_eq!(
+            neighbour(&rects, rects[2].1, Direction::Up, (30, 12)),
+            Some(2)
+        );
+
+        assert!(tree.remove(2));
+        assert!(tree.remove(1));
+        assert_eq!(tree.windows(), vec![0]);
+        assert!(!tree.remove(0));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::buffer::buffer::{Buffer, char_width};
+
+/// Characters after which `linebreak` may wrap a line, like vim's `breakat`.
+const BREAK_AT: &[char] = &[
+    ' ', '\t', '!', '@', '*', '-', '+', ';', ':', ',', '.', '/', '?',
+];
+
+/// A single screen row of a buffer line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayRow {
+    /// First char of the row.
+    pub start: usize,
+    /// One past the last char of the row.
+    pub end: usize,
+}
+
+/// How buffer lines are laid out over screen rows.
+pub struct Layout<'a> {
+    /// Width of the text area in columns.
+    pub width: usize,
+    /// Lay lines out over several rows, otherwise every line is a single row.
+    pub wrap: bool,
+    pub linebreak: bool,
+    pub showbreak: &'a str,
+}
+
+impl Layout<'_> {
+    /// Columns the `showbreak` marker takes at the start of continuation rows.
+    pub fn showbreak_width(&self) -> usize {
+        self.showbreak.chars().count()
+    }
+
+    /// Splits `line` of `buffer` into the screen rows it is displayed on.
+    pub fn rows(&self, buffer: &Buffer, line: usize) -> Vec<DisplayRow> {
+        let chars: Vec<char> = buffer.get_line(line).unwrap_or("").chars().collect();
+        if !self.wrap {
+            return vec![DisplayRow {
+                start: 0,
+                end: chars.len(),
+            }];
+        }
+
+        let tabstop = buffer.options.tabstop;
+        let mut vcols = Vec::with_capacity(chars.len() + 1);
+        let mut vcol = 0;
+        for &ch in &chars {
+            vcols.push(vcol);
+            vcol += char_width(ch, vcol, tabstop);
+        }
+        vcols.push(vcol);
+
+        let mut rows = Vec::new();
+        let mut start = 0;
+        while start < chars.len() {
+            let available = if rows.is_empty() {
+                self.width
+            } else {
+                self.width.saturating_sub(self.showbreak_width())
+            }
+            .max(1);
+
+            let mut end = start;
+            while end < chars.len() && vcols[end + 1] - vcols[start] <= available {
+                end += 1;
+            }
+            // A char wider than the whole row still has to go somewhere
+            end = end.max(start + 1);
+
+            if self.linebreak
+                && end < chars.len()
+                && !BREAK_AT.contains(&chars[end])
+                && let Some(word_start) = (start + 1..end)
+                    .rev()
+                    .find(|&i| BREAK_AT.contains(&chars[i - 1]))
+            {
+                end = word_start;
+            }
+
+            rows.push(DisplayRow { start, end });
+            start = end;
+        }
+
+        if rows.is_empty() {
+            rows.push(DisplayRow { start: 0, end: 0 });
+        }
+        rows
+    }
+
+    /// Index of the row in `rows` that shows the char at `col`.
+    pub fn row_of(rows: &[DisplayRow], col: usize) -> usize {
+        rows.iter()
+            .rposition(|row| row.start <= col)
+            .unwrap_or_default()
+    }
+
+    /// Screen column within its row at which the char at `col` is drawn.
+    pub fn screen_col(
+        &self,
+        buffer: &Buffer,
+        line: usize,
+        rows: &[DisplayRow],
+        col: usize,
+    ) -> usize {
+        let row = Self::row_of(rows, col);
+        let offset = if row > 0 { self.showbreak_width() } else { 0 };
+
+        offset + buffer.visual_col(line, col) - buffer.visual_col(line, rows[row].start)
+    }
+
+    /// Char in `rows[row]` drawn at `screen_col`, clamped to the row.
+    pub fn col_at(
+        &self,
+        buffer: &Buffer,
+        line: usize,
+        rows: &[DisplayRow],
+        row: usize,
+        screen_col: usize,
+    ) -> usize {
+        let display_row = rows[row];
+        let offset = if row > 0 { self.showbreak_width() } else { 0 };
+        let row_vcol = buffer.visual_col(line, display_row.start);
+        let target = row_vcol + screen_col.saturating_sub(offset);
+
+        let col = buffer.col_at_visual(line, target);
+        if row + 1 < rows.len() {
+            col.clamp(display_row.start, display_row.end.saturating_sub(1))
+        } else {
+            col.clamp(display_row.start, display_row.end)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rows_wrap_at_width_and_word_boundaries() {
+        let buffer = Buffer::new("test".to_string(), "hello wide world".to_string());
+        let mut layout = Layout {
+            width: 8,
+            wrap: true,
+            linebreak: false,
+            showbreak: "> ",
+        };
+
+        let rows = layout.rows(&buffer, 0);
+        assert_eq!(
+            rows,
+            vec![
+                DisplayRow { start: 0, end: 8 },
+                DisplayRow { start: 8, end: 14 },
+                DisplayRow { start: 14, end: 16 },
+            ]
+        );
+        assert_eq!(layout.screen_col(&buffer, 0, &rows, 9), 3);
+        assert_eq!(layout.col_at(&buffer, 0, &rows, 2, 3), 15);
+
+        layout.linebreak = true;
+        assert_eq!(
+            layout.rows(&buffer, 0),
+            vec![
+                DisplayRow { start: 0, end: 6 },
+                DisplayRow { start: 6, end: 11 },
+                DisplayRow { start: 11, end: 16 },
+            ]
+        );
+    }
+}
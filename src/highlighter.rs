@@ -1,11 +1,56 @@
-use tree_sitter::{Parser, Query, QueryCursor};
+use std::ops::Range;
+
+use tree_sitter::{InputEdit, Node, Parser, Query, QueryCursor, Tree};
 use tree_sitter_rust::{HIGHLIGHT_QUERY, language};
 
 use crate::editor::theme::{StyleInfo, Theme};
 
+/// Captures like `@function.outer` that make up the syntax text objects.
+const TEXTOBJECTS_QUERY: &str = include_str!("../queries/rust/textobjects.scm");
+
+/// A bracket in the code and where its partner is, both as byte offsets.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Bracket {
+    pub pos: usize,
+    /// `None` for a bracket without a partner.
+    pub pair: Option<usize>,
+    /// How many pairs it is nested in.
+    pub depth: usize,
+}
+
+/// The brackets of a buffer's text, kept until the text changes.
+#[derive(Default)]
+pub struct BracketCache {
+    /// The last syntax tree, edited along with the text so the next parse can reuse it.
+    tree: Option<Tree>,
+    brackets: Vec<Bracket>,
+    /// The buffer's change count and whether it was parsed as Rust when
+    /// `brackets` was worked out, `None` before that.
+    key: Option<(u64, bool)>,
+}
+
+impl BracketCache {
+    pub fn brackets(&self) -> &[Bracket] {
+        &self.brackets
+    }
+
+    /// Keeps the tree in step with an edit of the text.
+    pub fn edit(&mut self, edit: &InputEdit) {
+        if let Some(tree) = &mut self.tree {
+            tree.edit(edit);
+        }
+    }
+
+    /// Drops the tree when the whole text is replaced, there is nothing left to reuse.
+    pub fn reset(&mut self) {
+        self.tree = None;
+    }
+}
+
 pub struct Highlighter {
     parser: Parser,
     query: Query,
+    textobjects: Query,
     theme: Theme,
 }
 
@@ -17,10 +62,12 @@ impl Highlighter {
         parser.set_language(lang)?;
 
         let query = Query::new(lang, HIGHLIGHT_QUERY)?;
+        let textobjects = Query::new(lang, TEXTOBJECTS_QUERY)?;
 
         Ok(Highlighter {
             parser,
             query,
+            textobjects,
             theme,
         })
     }
@@ -48,4 +95,288 @@ impl Highlighter {
 
         Ok(colors)
     }
+
+    /// Bytes of the smallest syntax text object `name`, such as `function` or
+    /// `parameter`, around `pos`. Only the outer part is given when the query
+    /// has no inner capture for it.
+    pub fn text_object(
+        &mut self,
+        code: &str,
+        name: &str,
+        inner: bool,
+        pos: usize,
+    ) -> Option<Range<usize>> {
+        let tree = self.parser.parse(code, None)?;
+        let capture_index = |suffix: &str| {
+            self.textobjects
+                .capture_names()
+                .iter()
+                .position(|capture| *capture == format!("{}.{}", name, suffix))
+        };
+        let (outer_index, inner_index) = (capture_index("outer"), capture_index("inner"));
+
+        let mut best: Option<(Range<usize>, Option<Range<usize>>)> = None;
+        let mut cursor = QueryCursor::new();
+        for mat in cursor.matches(&self.textobjects, tree.root_node(), code.as_bytes()) {
+            let node = |index: Option<usize>| {
+                mat.captures
+                    .iter()
+                    .find(|cap| Some(cap.index as usize) == index)
+                    .map(|cap| cap.node)
+            };
+
+            // Arguments only capture the inner part, the outer one adds a separator
+            let (outer, inner) = match (node(outer_index), node(inner_index)) {
+                (Some(outer), inner) => (outer.byte_range(), inner.map(|n| n.byte_range())),
+                (None, Some(inner)) => (with_separator(inner), Some(inner.byte_range())),
+                (None, None) => continue,
+            };
+
+            if outer.contains(&pos)
+                && best
+                    .as_ref()
+                    .is_none_or(|(best, _)| outer.len() < best.len())
+            {
+                best = Some((outer, inner));
+            }
+        }
+
+        let (outer, inner_range) = best?;
+        Some(if inner {
+            inner_range.unwrap_or(outer)
+        } else {
+            outer
+        })
+    }
+
+    /// All `()`, `[]` and `{}` brackets in `code`, in order. They come from the
+    /// syntax tree, so the ones in strings and comments are left out.
+    pub fn brackets(&mut self, code: &str) -> Vec<Bracket> {
+        self.parser
+            .parse(code, None)
+            .map_or_else(Vec::new, |tree| tree_brackets(&tree))
+    }
+
+    /// Works out the brackets in `cache` again if `code` changed since, going
+    /// by the buffer's `changes`. Rust is parsed, reusing the old tree, other
+    /// text is only scanned for brackets.
+    pub fn update_brackets(
+        &mut self,
+        cache: &mut BracketCache,
+        code: &str,
+        changes: u64,
+        rust: bool,
+    ) {
+        if cache.key == Some((changes, rust)) {
+            return;
+        }
+
+        if rust {
+            cache.tree = self.parser.parse(code, cache.tree.as_ref());
+            cache.brackets = cache.tree.as_ref().map_or_else(Vec::new, tree_brackets);
+        } else {
+            cache.tree = None;
+            cache.brackets = scan_brackets(code);
+        }
+        cache.key = Some((changes, rust));
+    }
+}
+
+/// The brackets in a syntax tree, see [`Highlighter::brackets`].
+fn tree_brackets(tree: &Tree) -> Vec<Bracket> {
+    // Brackets pair up with a sibling, so each node's children are matched on their own
+    let mut brackets = Vec::new();
+    let mut nodes = vec![tree.root_node()];
+    while let Some(node) = nodes.pop() {
+        let mut open: Vec<(usize, char)> = Vec::new();
+        let mut cursor = node.walk();
+        for child in node.children(&mut cursor) {
+            if child.child_count() > 0 {
+                nodes.push(child);
+                continue;
+            }
+            // Nodes inserted to recover from a syntax error aren't in the text
+            if child.is_missing() || child.is_named() {
+                continue;
+            }
+
+            let pos = child.start_byte();
+            let close = match child.kind() {
+                "(" => ')',
+                "[" => ']',
+                "{" => '}',
+                ")" | "]" | "}" => {
+                    let ch = child.kind().chars().next().unwrap_or_default();
+                    let mut pair = None;
+                    if open.last().is_some_and(|(_, close)| *close == ch) {
+                        let (index, _) = open.pop().unwrap();
+                        let opening: &mut Bracket = &mut brackets[index];
+                        opening.pair = Some(pos);
+                        pair = Some(opening.pos);
+                    }
+                    brackets.push(Bracket {
+                        pos,
+                        pair,
+                        depth: 0,
+                    });
+                    continue;
+                }
+                _ => continue,
+            };
+            open.push((brackets.len(), close));
+            brackets.push(Bracket {
+                pos,
+                pair: None,
+                depth: 0,
+            });
+        }
+    }
+
+    brackets.sort_by_key(|bracket| bracket.pos);
+    set_depths(&mut brackets);
+    brackets
+}
+
+/// All `()`, `[]` and `{}` brackets in `code`, in order, for text that isn't
+/// parsed. A closing bracket pairs with the last open one if it is of its kind.
+fn scan_brackets(code: &str) -> Vec<Bracket> {
+    let mut brackets: Vec<Bracket> = Vec::new();
+    let mut open: Vec<(usize, char)> = Vec::new();
+    for (pos, ch) in code.char_indices() {
+        let close = match ch {
+            '(' => ')',
+            '[' => ']',
+            '{' => '}',
+            ')' | ']' | '}' => {
+                let mut pair = None;
+                if open.last().is_some_and(|(_, close)| *close == ch) {
+                    let (index, _) = open.pop().unwrap();
+                    let opening: &mut Bracket = &mut brackets[index];
+                    opening.pair = Some(pos);
+                    pair = Some(opening.pos);
+                }
+                brackets.push(Bracket {
+                    pos,
+                    pair,
+                    depth: 0,
+                });
+                continue;
+            }
+            _ => continue,
+        };
+        open.push((brackets.len(), close));
+        brackets.push(Bracket {
+            pos,
+            pair: None,
+            depth: 0,
+        });
+    }
+
+    set_depths(&mut brackets);
+    brackets
+}
+
+/// Sets how deeply each of `brackets`, in order, is nested.
+fn set_depths(brackets: &mut [Bracket]) {
+    let mut depth = 0;
+    for bracket in brackets {
+        match bracket.pair {
+            Some(pair) if pair > bracket.pos => {
+                bracket.depth = depth;
+                depth += 1;
+            }
+            Some(_) => {
+                depth -= 1;
+                bracket.depth = depth;
+            }
+            None => bracket.depth = depth,
+        }
+    }
+}
+
+/// Bytes of a list item with the comma after it, or before it for the last item.
+fn with_separator(node: Node) -> Range<usize> {
+    let mut range = node.byte_range();
+
+    if let Some(next) = node.next_sibling()
+        && next.kind() == ","
+    {
+        range.end = next
+            .next_sibling()
+            .filter(|after| after.is_named())
+            .map_or(next.end_byte(), |after| after.start_byte());
+    } else if let Some(previous) = node.prev_sibling()
+        && previous.kind() == ","
+    {
+        range.start = previous
+            .prev_sibling()
+            .map_or(previous.start_byte(), |before| before.end_byte());
+    }
+
+    range
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::buffer::buffer::Buffer;
+
+    #[test]
+    fn test_cached_brackets_follow_edits() {
+        let mut highlighter = Highlighter::new(Theme::default()).unwrap();
+        let mut buffer = Buffer::new(
+            "test".to_string(),
+            "fn f() {\n    g(\")\");\n}\n".to_string(),
+        );
+        let mut update = |buffer: &mut Buffer| {
+            highlighter.update_brackets(&mut buffer.brackets, &buffer.text, buffer.changes, true);
+            buffer.brackets.brackets().to_vec()
+        };
+        assert_eq!(update(&mut buffer).len(), 6);
+
+        // The old tree is edited and parsed again, the result is as for a fresh parse
+        buffer.insert(13, "[1], (");
+        buffer.delete(0..3);
+        let brackets = update(&mut buffer);
+        assert_eq!(
+            brackets,
+            Highlighter::new(Theme::default())
+                .unwrap()
+                .brackets(&buffer.text)
+        );
+        assert_eq!(brackets.len(), 9);
+
+        // Without a counted change the brackets are kept as they were
+        buffer.text.push('(');
+        assert_eq!(update(&mut buffer), brackets);
+    }
+
+    #[test]
+    fn test_brackets_skip_strings_and_comments() {
+        let mut highlighter = Highlighter::new(Theme::default()).unwrap();
+        let code = "fn f(a: [u8; 2]) { g(\"(\"); } // )\nfn h() { (";
+        let brackets = highlighter.brackets(code);
+        let pairs: Vec<(usize, Option<usize>, usize)> = brackets
+            .iter()
+            .map(|bracket| (bracket.pos, bracket.pair, bracket.depth))
+            .collect();
+
+        assert_eq!(
+            pairs,
+            vec![
+                (4, Some(15), 0),
+                (8, Some(14), 1),
+                (14, Some(8), 1),
+                (15, Some(4), 0),
+                (17, Some(27), 0),
+                (20, Some(24), 1),
+                (24, Some(20), 1),
+                (27, Some(17), 0),
+                (38, Some(39), 0),
+                (39, Some(38), 0),
+                (41, None, 0),
+                (43, None, 0),
+            ]
+        );
+    }
 }
@@ -1,6 +1,8 @@
 pub mod buffer;
+pub mod cli;
 pub mod editor;
 
 pub mod command_prompt;
 pub mod cursor;
 pub mod highlighter;
+pub mod terminal;
@@ -1,6 +1,9 @@
 use crossterm::{
     cursor::{EnableBlinking, Show},
-    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{
+        self, DisableFocusChange, DisableMouseCapture, EnableFocusChange, EnableMouseCapture,
+        Event, KeyCode, KeyModifiers,
+    },
     execute,
     terminal::{
         EnableLineWrap, EnterAlternateScreen, LeaveAlternateScreen, disable_raw_mode,
@@ -8,10 +11,158 @@ use crossterm::{
     },
 };
 use std::time::Duration;
-use std::{env, io::stdout};
-use violet::editor::editor::Editor;
+use std::{
+    env, fs,
+    io::{self, Read, stdout},
+    process,
+};
+use violet::{
+    buffer::{format, swap},
+    cli::{self, Args},
+    editor::{editor::Editor, keys},
+    terminal::{Terminal, crossterm_terminal::CrosstermTerminal},
+};
+
+/// `violet -r` without a file: lists the swap files left behind, in the
+/// `directory` the config sets like the editor would write them to.
+fn list_swaps(args: &Args) {
+    let mut editor = Editor::new(None, 80, 24);
+    editor.set_headless();
+    read_config(&mut editor, args);
+    for error in editor.take_errors() {
+        eprintln!("violet: {}", error);
+    }
+
+    let dir = editor.swap_dir();
+    let swaps = swap::list(&dir);
+    if swaps.is_empty() {
+        println!("No swap files in {}", dir.display());
+        return;
+    }
+
+    println!("Swap files in {}:", dir.display());
+    for (_, swap) in swaps {
+        let running = if swap.is_running() {
+            ", still running"
+        } else {
+            ""
+        };
+        println!("  {} (pid {}{})", swap.path.display(), swap.pid, running);
+    }
+    println!("Recover one with: violet -r <file>");
+}
+
+/// Reads the config asked for on the command line, or the default one.
+fn read_config(editor: &mut Editor, args: &Args) {
+    if args.clean {
+        // Nothing from the surroundings, like `.editorconfig` files, either
+        editor.set_clean();
+        return;
+    }
+    let config = args
+        .config
+        .clone()
+        .or_else(|| cli::default_config().filter(|config| config.exists()));
+    if let Some(config) = config
+        && let Err(e) = editor.run_command(&format!("source {}", config.display()))
+    {
+        editor.error(e.to_string());
+    }
+}
+
+/// Reads the config, opens the files from the command line, then runs the
+/// commands and types the keys asked for. Returns whether that quit the editor.
+fn start(editor: &mut Editor, args: &Args, mut stdin_text: Option<String>) -> bool {
+    if args.read_only {
+        editor.set_view_mode();
+    }
+    read_config(editor, args);
+
+    for file in &args.files {
+        if file.path == "-" {
+            if let Some(text) = stdin_text.take() {
+                editor.open_text("stdin", text);
+            }
+        } else {
+            editor.open_arg(&file.path, file.line, file.col);
+        }
+    }
+    if args.recover {
+        editor.recover();
+    }
+
+    for command in &args.commands {
+        match editor.run_start_command(command) {
+            Ok(true) => return true,
+            Ok(false) => {}
+            Err(e) => editor.error(e.to_string()),
+        }
+    }
+
+    if let Some(script) = &args.script {
+        match fs::read_to_string(script) {
+            Ok(text) => {
+                for key in keys::parse(&text) {
+                    if editor.handle_keypress(key) {
+                        return true;
+                    }
+                }
+            }
+            Err(e) => editor.error(format!("Can't read {}: {}", script.display(), e)),
+        }
+    }
+    false
+}
+
+/// `violet --headless`: edits without a terminal, errors go to stderr and
+/// make the exit status 1.
+fn run_headless(args: &Args, stdin_text: Option<String>) -> i32 {
+    let mut editor = Editor::new(None, 80, 24);
+    editor.set_headless();
+    start(&mut editor, args, stdin_text);
+    editor.remove_swaps();
+
+    let errors = editor.take_errors();
+    for error in &errors {
+        eprintln!("violet: {}", error);
+    }
+    if errors.is_empty() { 0 } else { 1 }
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args = match Args::parse(env::args().skip(1)) {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("violet: {}\nTry 'violet --help' for more information.", e);
+            process::exit(2);
+        }
+    };
+    if args.help {
+        print!("{}", cli::HELP);
+        return Ok(());
+    }
+    if args.version {
+        println!("violet {}", env!("CARGO_PKG_VERSION"));
+        return Ok(());
+    }
+    if args.recover && args.files.is_empty() {
+        list_swaps(&args);
+        return Ok(());
+    }
+
+    // Has to be read before the terminal is taken over, keys come from the tty after
+    let stdin_text = if args.files.iter().any(|file| file.path == "-") {
+        let mut bytes = Vec::new();
+        io::stdin().read_to_end(&mut bytes)?;
+        Some(format::decode(&bytes).text)
+    } else {
+        None
+    };
+
+    if args.headless {
+        process::exit(run_headless(&args, stdin_text));
+    }
+
     enable_raw_mode()?;
     let mut stdout = stdout();
     execute!(
@@ -19,35 +170,56 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         EnterAlternateScreen,
         Show,
         EnableBlinking,
-        EnableLineWrap
+        EnableLineWrap,
+        EnableFocusChange
     )?;
 
-    let args: Vec<String> = env::args().collect();
-    let filename = args.get(1).cloned();
+    let mut terminal = CrosstermTerminal::new(stdout);
+    let (width, height) = terminal.size()?;
+    let mut editor = Editor::new(None, width, height);
+    let quit = start(&mut editor, &args, stdin_text);
 
-    let mut editor = Editor::new(filename);
-
-    let mut should_quit = false;
+    let mut mouse_captured = false;
+    let mut should_quit = quit;
     while !should_quit {
-        editor.render(&mut stdout)?;
+        if editor.mouse_enabled() != mouse_captured {
+            mouse_captured = editor.mouse_enabled();
+            if mouse_captured {
+                execute!(io::stdout(), EnableMouseCapture)?;
+            } else {
+                execute!(io::stdout(), DisableMouseCapture)?;
+            }
+        }
+
+        editor.render(&mut terminal)?;
 
         if event::poll(Duration::from_millis(16))? {
-            if let Event::Key(KeyEvent {
-                code, modifiers, ..
-            }) = event::read()?
-            {
-                if code == KeyCode::Char('c') && modifiers.contains(KeyModifiers::CONTROL) {
+            match event::read()? {
+                Event::Key(key)
+                    if key.code == KeyCode::Char('c')
+                        && key.modifiers.contains(KeyModifiers::CONTROL) =>
+                {
                     // lets not close on signal ctrl c lol
                     should_quit = false;
-                } else {
-                    should_quit = editor.handle_keypress(code);
                 }
+                Event::Key(key) => should_quit = editor.handle_keypress(key),
+                Event::Mouse(mouse) => editor.handle_mouse(mouse),
+                Event::Resize(width, height) => editor.resize(width as usize, height as usize),
+                // Other programs may have changed files in the meantime
+                Event::FocusGained => editor.checktime(),
+                _ => {}
             }
+        } else {
+            editor.idle();
         }
     }
+    editor.remove_swaps();
 
+    if mouse_captured {
+        execute!(io::stdout(), DisableMouseCapture)?;
+    }
     disable_raw_mode()?;
-    execute!(stdout, LeaveAlternateScreen, Show)?;
+    execute!(io::stdout(), DisableFocusChange, LeaveAlternateScreen, Show)?;
 
     Ok(())
 }
@@ -0,0 +1,78 @@
+use std::io::{self, Write};
+
+use crossterm::{
+    cursor::{self, MoveTo},
+    queue,
+    style::{Attribute, Print, SetAttribute, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType},
+};
+
+use super::{CursorShape, Frame, Terminal};
+
+/// A real terminal, drawn to with crossterm escape sequences.
+pub struct CrosstermTerminal<W: Write> {
+    out: W,
+}
+
+impl<W: Write> CrosstermTerminal<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+}
+
+impl<W: Write> Terminal for CrosstermTerminal<W> {
+    fn size(&self) -> io::Result<(usize, usize)> {
+        let (width, height) = terminal::size()?;
+        Ok((width as usize, height as usize))
+    }
+
+    fn draw(&mut self, frame: &Frame) -> io::Result<()> {
+        let out = &mut self.out;
+
+        if frame.clear {
+            queue!(out, Clear(ClearType::All))?;
+        }
+
+        // Only update cells that have changed
+        for y in 0..frame.current.height {
+            for x in 0..frame.current.width {
+                let current = frame.current.get_cell(x, y);
+                let previous = frame.previous.get_cell(x, y);
+
+                if current != previous
+                    && let Some(cell) = current
+                {
+                    queue!(
+                        out,
+                        MoveTo(x as u16, y as u16),
+                        SetAttribute(Attribute::Reset),
+                        SetForegroundColor(cell.fg),
+                        SetBackgroundColor(cell.bg),
+                    )?;
+                    if cell.bold {
+                        queue!(out, SetAttribute(Attribute::Bold))?;
+                    }
+                    if cell.italic {
+                        queue!(out, SetAttribute(Attribute::Italic))?;
+                    }
+                    queue!(out, Print(cell.ch))?;
+                }
+            }
+        }
+
+        let (cursor_x, cursor_y) = frame.cursor;
+        let style = match frame.cursor_shape {
+            CursorShape::Block => cursor::SetCursorStyle::BlinkingBlock,
+            CursorShape::Bar => cursor::SetCursorStyle::BlinkingBar,
+            CursorShape::Underline => cursor::SetCursorStyle::BlinkingUnderScore,
+        };
+        queue!(
+            out,
+            MoveTo(cursor_x as u16, cursor_y as u16),
+            style,
+            cursor::Show
+        )?;
+
+        out.flush()
+    }
+}
@@ -0,0 +1,114 @@
+use std::io;
+
+use crossterm::style::Color;
+
+use crate::buffer::{render_buffer::RenderBuffer, render_cell::RenderCell};
+
+use super::{CursorShape, Frame, Terminal};
+
+/// A run of cells on one row that share the same style.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StyleSpan {
+    pub x: usize,
+    pub y: usize,
+    pub text: String,
+    pub fg: Color,
+    pub bg: Color,
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// A fixed size screen kept in memory, for running the editor without a TTY.
+pub struct MemoryTerminal {
+    screen: RenderBuffer,
+    cursor: (usize, usize),
+    cursor_shape: CursorShape,
+}
+
+impl MemoryTerminal {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            screen: RenderBuffer::new(width, height),
+            cursor: (0, 0),
+            cursor_shape: CursorShape::Block,
+        }
+    }
+
+    /// The last frame that was drawn.
+    pub fn screen(&self) -> &RenderBuffer {
+        &self.screen
+    }
+
+    pub fn cursor(&self) -> (usize, usize) {
+        self.cursor
+    }
+
+    pub fn cursor_shape(&self) -> CursorShape {
+        self.cursor_shape
+    }
+
+    /// The screen as text, one line per row with trailing blanks trimmed.
+    pub fn text(&self) -> String {
+        self.screen
+            .buffer
+            .iter()
+            .map(|row| {
+                let line: String = row.iter().map(|cell| cell.ch).collect();
+                line.trim_end().to_string()
+            })
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    /// The screen as runs of equally styled cells, row by row.
+    pub fn spans(&self) -> Vec<StyleSpan> {
+        let same_style = |a: &RenderCell, b: &RenderCell| {
+            a.fg == b.fg && a.bg == b.bg && a.bold == b.bold && a.italic == b.italic
+        };
+
+        let mut spans = Vec::new();
+        for (y, row) in self.screen.buffer.iter().enumerate() {
+            let mut start = 0;
+            while start < row.len() {
+                let first = &row[start];
+                let end = row[start..]
+                    .iter()
+                    .position(|cell| !same_style(first, cell))
+                    .map_or(row.len(), |len| start + len);
+
+                spans.push(StyleSpan {
+                    x: start,
+                    y,
+                    text: row[start..end].iter().map(|cell| cell.ch).collect(),
+                    fg: first.fg,
+                    bg: first.bg,
+                    bold: first.bold,
+                    italic: first.italic,
+                });
+                start = end;
+            }
+        }
+
+        spans
+    }
+}
+
+impl Terminal for MemoryTerminal {
+    fn size(&self) -> io::Result<(usize, usize)> {
+        Ok((self.screen.width, self.screen.height))
+    }
+
+    fn draw(&mut self, frame: &Frame) -> io::Result<()> {
+        self.screen
+            .resize(frame.current.width, frame.current.height);
+        for (y, row) in frame.current.buffer.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                self.screen.set_cell(x, y, cell.clone());
+            }
+        }
+
+        self.cursor = frame.cursor;
+        self.cursor_shape = frame.cursor_shape;
+        Ok(())
+    }
+}
@@ -0,0 +1,33 @@
+pub mod crossterm_terminal;
+pub mod memory_terminal;
+
+use std::io;
+
+use crate::buffer::render_buffer::RenderBuffer;
+
+/// Shape of the cursor, which tells the user what mode they are in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CursorShape {
+    Block,
+    Bar,
+    Underline,
+}
+
+/// Everything a terminal needs to put one rendered frame on screen.
+pub struct Frame<'a> {
+    pub current: &'a RenderBuffer,
+    /// What was drawn last frame, so only changed cells need updating.
+    pub previous: &'a RenderBuffer,
+    pub cursor: (usize, usize),
+    pub cursor_shape: CursorShape,
+    /// Clear the whole screen before drawing, e.g. after a resize.
+    pub clear: bool,
+}
+
+/// Where the editor gets drawn.
+pub trait Terminal {
+    /// Size of the screen as (width, height).
+    fn size(&self) -> io::Result<(usize, usize)>;
+
+    fn draw(&mut self, frame: &Frame) -> io::Result<()>;
+}
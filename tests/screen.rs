@@ -0,0 +1,982 @@
+use crossterm::event::{KeyModifiers, MouseButton, MouseEvent, MouseEventKind};
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+use violet::{
+    buffer::swap::{self, Swap},
+    editor::{editor::Editor, keys},
+    terminal::{CursorShape, memory_terminal::MemoryTerminal},
+};
+
+/// Feeds `keys` to the editor, written like `ihello<Esc>` or `<C-w>j`.
+fn feed(editor: &mut Editor, keys: &str) {
+    for key in keys::parse(keys) {
+        editor.handle_keypress(key);
+    }
+}
+
+/// A directory of its own for a test, removed again when the test ends,
+/// also when it fails.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = std::env::temp_dir().join(format!("violet-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for TempDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+fn screen(width: usize, height: usize, keys: &str) -> MemoryTerminal {
+    let mut terminal = MemoryTerminal::new(width, height);
+    let mut editor = Editor::new(None, width, height);
+    feed(&mut editor, keys);
+    editor.render(&mut terminal).unwrap();
+    terminal
+}
+
+#[test]
+fn test_typed_text_is_drawn_with_gutter_and_status() {
+    let terminal = screen(40, 5, "ihello<CR>world<Esc>");
+
+    assert_eq!(
+        terminal.text(),
+        "\
+1│hello
+2│world
+
+NORMAL | Untitled | Line: 2/2 Col: 6/5
+"
+    );
+    assert_eq!(terminal.cursor(), (7, 1));
+    assert_eq!(terminal.cursor_shape(), CursorShape::Block);
+}
+
+#[test]
+fn test_tabs_expand_to_tabstop() {
+    let terminal = screen(40, 4, ":set ts=4<CR>ia<Tab>b<Esc>");
+
+    assert_eq!(terminal.text().lines().next(), Some("1│a   b"));
+    assert_eq!(terminal.cursor(), (7, 0));
+}
+
+#[test]
+fn test_long_lines_wrap_with_showbreak() {
+    let terminal = screen(12, 5, ":set wrap sbr=+<CR>iabcdefghijklmn<Esc>");
+
+    assert_eq!(
+        terminal.text(),
+        "\
+1│abcdefghi
+ │+jklmn
+
+NORMAL | Unt
+"
+    );
+}
+
+#[test]
+fn test_errors_are_styled_on_the_message_line() {
+    let terminal = screen(40, 4, ":nope<CR>");
+
+    let message = terminal
+        .spans()
+        .into_iter()
+        .find(|span| span.y == 3 && span.x == 0)
+        .unwrap();
+    assert_eq!(message.text.trim_end(), "Not an editor command: nope");
+    assert_eq!(
+        message.fg,
+        crossterm::style::Color::Rgb {
+            r: 255,
+            g: 85,
+            b: 85
+        }
+    );
+}
+
+#[test]
+fn test_mouse_drag_selects_and_visual_delete_removes_it() {
+    let mut terminal = MemoryTerminal::new(40, 5);
+    let mut editor = Editor::new(None, 40, 5);
+    feed(&mut editor, ":set mouse<CR>ihello big world<CR>bye<Esc>");
+
+    let mouse = |kind, column| MouseEvent {
+        kind,
+        column,
+        row: 0,
+        modifiers: KeyModifiers::NONE,
+    };
+    editor.handle_mouse(mouse(MouseEventKind::Down(MouseButton::Left), 8));
+    editor.handle_mouse(mouse(MouseEventKind::Drag(MouseButton::Left), 11));
+    feed(&mut editor, "d");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│hello world\n2│bye\n"));
+
+    feed(&mut editor, "jVyP");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│hello world\n2│bye\n3│bye\n"));
+}
+
+#[test]
+fn test_split_windows_have_their_own_cursor_and_status() {
+    let terminal = screen(
+        51,
+        9,
+        "ione<CR>two<Esc>:vsplit<CR>:split<CR>k<C-w>lG<C-w>h<C-w>j",
+    );
+
+    assert_eq!(
+        terminal.text(),
+        "\
+1│one                    │1│one
+2│two                    │2│two
+                         │
+Untitled | Line: 1/2 Col:│
+1│one                    │
+2│two                    │
+                         │
+NORMAL | Untitled | Line:│Untitled | Line: 2/2 Col:
+"
+    );
+    assert_eq!(terminal.cursor(), (5, 5));
+}
+
+#[test]
+fn test_tab_pages_keep_their_own_layout() {
+    let dir = TempDir::new("tabs");
+    let mut terminal = MemoryTerminal::new(40, 6);
+    let mut editor = Editor::new(None, 40, 6);
+    feed(
+        &mut editor,
+        &format!(
+            ":set noswf<CR>ione<Esc>:w {}<CR>:tabnew<CR>:vsplit<CR>ix<Esc>gT",
+            dir.join("null").display()
+        ),
+    );
+    editor.render(&mut terminal).unwrap();
+
+    assert_eq!(
+        terminal.text(),
+        " 1 null  2 Untitled +
+1│one
+
+
+NORMAL | [1:null] 2:Untitled | Line: 1/1
+"
+    );
+
+    feed(&mut editor, "gt");
+    editor.render(&mut terminal).unwrap();
+    assert_eq!(
+        terminal.text().lines().nth(1),
+        Some("1│x                 │1│x")
+    );
+}
+
+#[test]
+fn test_operators_with_text_objects() {
+    let terminal = screen(
+        40,
+        8,
+        "ifn a(x: u8, y: u8) {<CR>    call(\"one two\");<CR>}<Esc>gg05ldaajci\"three<Esc>yyP",
+    );
+
+    assert_eq!(
+        terminal.text(),
+        "\
+1│fn a(y: u8) {
+2│    call(\"three\");
+3│    call(\"three\");
+4│}
+
+
+NORMAL | Untitled | Line: 2/4 Col: 1/18
+"
+    );
+}
+
+#[test]
+fn test_counts_and_syntax_objects() {
+    let terminal = screen(
+        40,
+        8,
+        "ione two three four<CR>fn f() {<CR>    body();<CR>}<CR>x<Esc>ggd2awjjdifG0dd",
+    );
+
+    assert_eq!(
+        terminal.text(),
+        "\
+1│three four
+2│fn f() {
+3│}
+
+
+
+NORMAL | Untitled | Line: 3/3 Col: 1/1
+"
+    );
+}
+
+#[test]
+fn test_word_operators() {
+    let terminal = screen(
+        40,
+        5,
+        "ione two three.four<CR>five<Esc>gg0dwcwX<Esc>wwdwjbdw",
+    );
+
+    assert_eq!(
+        terminal.text().lines().take(2).collect::<Vec<_>>(),
+        vec!["1│X threefour", "2│"]
+    );
+}
+
+#[test]
+fn test_find_char_motions_repeat_and_take_operators() {
+    let terminal = screen(40, 5, "icall(a, b), next(c, d)<Esc>0f(ldt);,cf,X<Esc>");
+
+    assert_eq!(terminal.text().lines().next(), Some("1│call()X d)"));
+}
+
+#[test]
+fn test_bracket_pairs_are_highlighted_and_jumped_between() {
+    let dir = TempDir::new("brackets");
+    let file = dir.join("main.rs");
+    std::fs::write(&file, "").unwrap();
+
+    let mut terminal = MemoryTerminal::new(40, 5);
+    let mut editor = Editor::new(None, 40, 5);
+    editor.run_command("set noswf").unwrap();
+    editor.open_arg(&file.display().to_string(), None, None);
+    feed(&mut editor, "if(x[1], \")\") {<Esc>0%");
+    editor.render(&mut terminal).unwrap();
+
+    // On the `)` that pairs with the `(`, the one in the string doesn't count
+    assert_eq!(terminal.cursor(), (13, 0));
+    let screen = terminal.screen();
+    let match_bg = crossterm::style::Color::Rgb {
+        r: 98,
+        g: 114,
+        b: 164,
+    };
+    assert_eq!(screen.get_cell(3, 0).unwrap().bg, match_bg);
+    assert_eq!(screen.get_cell(13, 0).unwrap().bg, match_bg);
+    assert_ne!(screen.get_cell(11, 0).unwrap().bg, match_bg);
+    // The `{` has no partner
+    assert_eq!(
+        screen.get_cell(15, 0).unwrap().fg,
+        crossterm::style::Color::Rgb {
+            r: 255,
+            g: 85,
+            b: 85
+        }
+    );
+
+    feed(&mut editor, "d%");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│f {\n"));
+
+    // Other text isn't parsed, a bracket in quotes counts like any other
+    let mut editor = Editor::new(None, 40, 5);
+    feed(&mut editor, "if(x[1], \")\") {<Esc>0%");
+    editor.render(&mut terminal).unwrap();
+    assert_eq!(terminal.cursor(), (11, 0));
+}
+
+#[test]
+fn test_editing_commands_with_counts_and_repeat() {
+    let terminal = screen(
+        40,
+        6,
+        ":set sw=2 et<CR>iab<Esc>3Ax<Esc>0~2rYIbegin <Esc>2oline<Esc>kJ>>.ggRZZ<BS>Q<Esc>jA!<Esc>.",
+    );
+
+    assert_eq!(
+        terminal.text().lines().take(3).collect::<Vec<_>>(),
+        vec!["1│ZQgin AYYxx", "2│    line line!!", ""]
+    );
+
+    let terminal = screen(40, 4, "iabc def<CR>ghi<Esc>k0sX<Esc>wC!<Esc>2gJ");
+    assert!(terminal.text().starts_with("1│Xbc !ghi\n\n"));
+}
+
+#[test]
+fn test_dot_repeats_changes_with_registers_and_counts() {
+    let terminal = screen(
+        40,
+        4,
+        "ione two three four five<Esc>0cwONE<Esc>w.w2.0\"adiw$\"ap.0x3.",
+    );
+
+    assert!(terminal.text().starts_with("1│ ONE fiveONEONE\n"));
+}
+
+#[test]
+fn test_macros_repeat_until_a_motion_fails() {
+    let mut terminal = MemoryTerminal::new(40, 8);
+    let mut editor = Editor::new(None, 40, 8);
+    feed(&mut editor, "ia,b<CR>c,d<CR>e<CR>f,g<Esc>ggqq0f,r;j");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("NORMAL recording @q |"));
+
+    // The third run stops on the line without a comma
+    feed(&mut editor, "q5@q");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│a;b\n2│c;d\n3│e\n4│f,g\n"));
+
+    // The macro is register text that can be put, changed and yanked back
+    feed(&mut editor, "Go<Esc>\"qp0f;rx0\"qy$k@q");
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .starts_with("1│a;b\n2│c;d\n3│e\n4│fxg\n5│0f,rxj\n")
+    );
+}
+
+#[test]
+fn test_marks_follow_edits_and_jumps_go_back_and_forth() {
+    let mut terminal = MemoryTerminal::new(40, 8);
+    let mut editor = Editor::new(None, 40, 8);
+    feed(
+        &mut editor,
+        "ione<CR>two<CR>three<CR>four<Esc>ggjlmaG'aiX<Esc>`aiY<Esc>",
+    );
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .starts_with("1│one\n2│XtYwo\n3│three\n4│four\n")
+    );
+
+    feed(&mut editor, "ggd'a");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│three\n2│four\n"));
+
+    // `''` swaps between the last two places jumped between
+    feed(&mut editor, "G''iA<Esc>''iB<Esc>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│Athree\n2│Bfour\n"));
+
+    feed(&mut editor, "gg<C-o>iC<Esc><C-i>iD<Esc>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│DAthree\n2│BCfour\n"));
+
+    feed(&mut editor, "'z");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Mark not set: z"));
+}
+
+#[test]
+fn test_write_reports_what_it_wrote_and_why_it_failed() {
+    let terminal = screen(40, 4, "ihi<Esc>:w<CR>");
+    assert!(terminal.text().contains("No file name"));
+
+    let dir = TempDir::new("screen");
+    let path = dir.join("out.txt");
+    let terminal = screen(
+        60,
+        4,
+        &format!("ihi<CR>there<Esc>:w {}<CR>", path.display()),
+    );
+    assert!(terminal.text().contains("2L, 8B written"));
+    assert!(terminal.text().contains("| out.txt |"));
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "hi\nthere");
+
+    let missing = dir.join("missing").join("out.txt");
+    let terminal = screen(80, 4, &format!("ihi<Esc>:w {}<CR>", missing.display()));
+    assert!(terminal.text().contains("Can't write"));
+}
+
+#[test]
+fn test_swap_files_keep_unsaved_changes_for_recovery() {
+    let dir = TempDir::new("recover");
+    let file = dir.join("notes.txt");
+    std::fs::write(&file, "saved\n").unwrap();
+    let setup = format!(
+        ":set dir={} ut=0<CR>:e {}<CR>",
+        dir.display(),
+        file.display()
+    );
+
+    let mut editor = Editor::new(None, 80, 6);
+    feed(&mut editor, &format!("{}iunsaved <Esc>", setup));
+    editor.idle();
+    let swap_file = dir.join(format!(
+        "{}.swp",
+        file.display().to_string().replace('/', "%")
+    ));
+    assert!(swap_file.exists());
+
+    // The editor "crashed" without cleaning up, the next one finds the swap file
+    let mut terminal = MemoryTerminal::new(120, 6);
+    let mut editor = Editor::new(None, 120, 6);
+    feed(&mut editor, &setup);
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Swap file found for notes.txt"));
+
+    feed(&mut editor, "x");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("[r]ecover"));
+
+    feed(&mut editor, "r");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│unsaved saved\n"));
+
+    // Once written the swap file isn't needed any more
+    feed(&mut editor, ":w<CR>");
+    assert!(!swap_file.exists());
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "unsaved saved\n");
+
+    feed(&mut editor, "ia<Esc>");
+    editor.idle();
+    let mut editor = Editor::new(None, 120, 6);
+    feed(&mut editor, &format!("{}o:w<CR>", setup));
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("'readonly' option is set"));
+
+    let mut editor = Editor::new(None, 120, 6);
+    feed(&mut editor, &format!("{}d", setup));
+    assert!(!swap_file.exists());
+
+    // Files opened together, like from the command line, are asked about in turn
+    let other = dir.join("other.txt");
+    std::fs::write(&other, "other\n").unwrap();
+    let open_both = |editor: &mut Editor| {
+        editor
+            .run_command(&format!("set dir={} ut=0", dir.display()))
+            .unwrap();
+        editor.open_arg(&file.display().to_string(), None, None);
+        editor.open_arg(&other.display().to_string(), None, None);
+    };
+    let mut editor = Editor::new(None, 120, 6);
+    open_both(&mut editor);
+    feed(&mut editor, "ione <Esc>:b 2<CR>itwo <Esc>");
+    editor.idle();
+    let mut editor = Editor::new(None, 120, 6);
+    open_both(&mut editor);
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Swap file found for notes.txt"));
+    feed(&mut editor, "r");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Swap file found for other.txt"));
+    feed(&mut editor, "r");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│one unsaved saved\n"));
+    feed(&mut editor, ":b 2<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│two other\n"));
+
+    // A swap file whose name another file's path makes too isn't offered
+    let odd = dir.join("odd.txt");
+    std::fs::write(&odd, "odd\n").unwrap();
+    Swap {
+        pid: 1,
+        path: dir.join("elsewhere.txt"),
+        text: "not odd\n".to_string(),
+    }
+    .write(&swap::swap_path(&dir, &odd))
+    .unwrap();
+    let mut editor = Editor::new(None, 120, 6);
+    feed(
+        &mut editor,
+        &format!(":set dir={}<CR>:e {}<CR>", dir.display(), odd.display()),
+    );
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│odd\n"));
+    assert!(terminal.text().contains("belongs to"));
+}
+
+#[test]
+fn test_files_changed_on_disk_are_reloaded_or_asked_about() {
+    let dir = TempDir::new("checktime");
+    let file = dir.join("lib.rs");
+    std::fs::write(&file, "old\n").unwrap();
+
+    let mut terminal = MemoryTerminal::new(100, 6);
+    let mut editor = Editor::new(None, 100, 6);
+    feed(
+        &mut editor,
+        &format!(":set noswf<CR>:e {}<CR>", file.display()),
+    );
+
+    // Unmodified buffers just follow the file, the watcher notices on its own
+    std::fs::write(&file, "formatted\n").unwrap();
+    for _ in 0..100 {
+        editor.idle();
+        editor.render(&mut terminal).unwrap();
+        if terminal.text().starts_with("1│formatted\n") {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+    assert!(terminal.text().contains("lib.rs changed on disk, reloaded"));
+
+    // Writing over someone else's change takes a `!`
+    feed(&mut editor, "imine <Esc>");
+    std::fs::write(&file, "checked out\n").unwrap();
+    feed(&mut editor, ":w<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .contains("changed on disk since it was read")
+    );
+
+    feed(&mut editor, ":checktime<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .contains("[l]oad it from disk, [k]eep the buffer")
+    );
+    feed(&mut editor, "l");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│checked out\n"));
+
+    feed(&mut editor, "ix<Esc>:e!<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│checked out\n"));
+
+    feed(&mut editor, "0imine <Esc>");
+    std::fs::write(&file, "again\n").unwrap();
+    feed(&mut editor, ":checkt<CR>k:w<CR>");
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "mine checked out\n"
+    );
+}
+
+#[test]
+fn test_line_endings_and_encodings_are_kept_or_converted() {
+    let dir = TempDir::new("format");
+    let dos = dir.join("dos.txt");
+    std::fs::write(&dos, "one\r\ntwo\r\n").unwrap();
+
+    let mut terminal = MemoryTerminal::new(60, 6);
+    let mut editor = Editor::new(Some(dos.display().to_string()), 60, 6);
+    feed(&mut editor, ":set noswf<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│one\n2│two\n"));
+    assert!(terminal.text().contains("NORMAL | dos.txt [dos] | Line"));
+
+    feed(&mut editor, "jothree<Esc>:w<CR>");
+    assert_eq!(std::fs::read(&dos).unwrap(), b"one\r\ntwo\r\nthree\r\n");
+    feed(&mut editor, ":set ff=unix<CR>:w<CR>");
+    assert_eq!(std::fs::read(&dos).unwrap(), b"one\ntwo\nthree\n");
+
+    let latin1 = dir.join("latin1.txt");
+    std::fs::write(&latin1, b"caf\xE9\n").unwrap();
+    let mut editor = Editor::new(Some(latin1.display().to_string()), 60, 6);
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│café\n"));
+    assert!(terminal.text().contains("latin1.txt [latin1] |"));
+
+    feed(&mut editor, ":set fenc=utf-8<CR>:w<CR>");
+    assert_eq!(std::fs::read_to_string(&latin1).unwrap(), "café\n");
+    feed(&mut editor, "A日本<Esc>:set fenc=latin1<CR>:w<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Can't write '日' as latin1"));
+
+    // Bytes that had to be replaced would be lost by writing
+    let broken = dir.join("broken.txt");
+    std::fs::write(&broken, b"\xEF\xBB\xBFcaf\xE9\n").unwrap();
+    let mut editor = Editor::new(Some(broken.display().to_string()), 60, 6);
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .contains("broken.txt isn't valid utf-8-bom, opened read-only")
+    );
+    feed(&mut editor, ":set noswf<CR>x:w<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("'readonly' option is set"));
+}
+
+#[test]
+fn test_missing_unreadable_binary_files_and_directories_open_differently() {
+    let dir = TempDir::new("open");
+    std::fs::create_dir_all(dir.join("src")).unwrap();
+    std::fs::write(dir.join("notes.txt"), "notes\n").unwrap();
+    std::fs::write(dir.join("app.bin"), b"\x7fELF\0\0\x01").unwrap();
+    let path = |name: &str| dir.join(name).display().to_string();
+
+    let mut terminal = MemoryTerminal::new(80, 6);
+    let mut editor = Editor::new(Some(path("new.txt")), 80, 6);
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("\"new.txt\" [New]"));
+
+    // A file where a directory should be can't be read, and won't be written over
+    let mut editor = Editor::new(Some(path("notes.txt/inner")), 80, 6);
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Can't open"));
+    feed(&mut editor, ":set noswf<CR>");
+    editor.render(&mut terminal).unwrap();
+    feed(&mut editor, ":w<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("'readonly' option is set"));
+    // Opening it again goes to the buffer it already has
+    feed(&mut editor, &format!(":e {}<CR>", path("notes.txt/inner")));
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("NORMAL | inner [RO] | Line"));
+
+    feed(&mut editor, &format!(":e {}<CR>", path("app.bin")));
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("app.bin looks like a binary file"));
+
+    let mut editor = Editor::new(Some(dir.display().to_string()), 80, 6);
+    feed(&mut editor, ":set noswf<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .starts_with("1│../\n2│src/\n3│app.bin\n4│notes.txt\n")
+    );
+
+    feed(&mut editor, "jjj<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│notes\n"));
+}
+
+#[test]
+fn test_readonly_buffers_need_a_bang_and_nomodifiable_ones_refuse_edits() {
+    let dir = TempDir::new("readonly");
+    let file = dir.join("log.txt");
+    std::fs::write(&file, "line\n").unwrap();
+
+    let mut terminal = MemoryTerminal::new(80, 6);
+    let mut editor = Editor::new(None, 80, 6);
+    feed(
+        &mut editor,
+        &format!(":set noswf<CR>:view {}<CR>", file.display()),
+    );
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("[2:log.txt] [RO] |"));
+
+    feed(&mut editor, "inew <Esc>:w<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .contains("'readonly' option is set (add ! to override)")
+    );
+    feed(&mut editor, ":w!<CR>");
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "new line\n");
+
+    feed(&mut editor, ":set noma<CR>");
+    for keys in ["x", "dd", "i", "A", "p", "Vd", "."] {
+        feed(&mut editor, keys);
+        editor.render(&mut terminal).unwrap();
+        assert!(terminal.text().starts_with("1│new line\n"), "{}", keys);
+        assert!(terminal.text().contains("'modifiable' is off"), "{}", keys);
+    }
+    assert!(terminal.text().contains("[2:log.txt] [-] |"));
+}
+
+#[test]
+fn test_command_line_files_positions_and_config() {
+    let dir = TempDir::new("args");
+    let path = |name: &str| dir.join(name).display().to_string();
+    std::fs::write(
+        path("main.rs"),
+        "fn one() {}\n\nfn main() {\n    one();\n}\n",
+    )
+    .unwrap();
+    std::fs::write(path("lib.rs"), "pub mod a;\npub mod b;\n").unwrap();
+    std::fs::write(
+        path("config"),
+        "\" keep tests off the swap dir\nset noswf\n:set ts=2\n",
+    )
+    .unwrap();
+
+    let mut terminal = MemoryTerminal::new(60, 8);
+    let mut editor = Editor::new(None, 60, 8);
+    let source = format!("source {}", path("config"));
+    assert!(!editor.run_command(&source).unwrap());
+    editor.open_arg(&path("main.rs"), Some(4), None);
+    editor.open_arg(&path("lib.rs"), Some(2), Some(5));
+    editor.open_text("stdin", "piped\n".to_string());
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .contains("[1:main.rs] 2:lib.rs 3:stdin | Line: 4/6 Col: 5/10")
+    );
+
+    feed(&mut editor, ":bn<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(
+        terminal
+            .text()
+            .contains("[2:lib.rs] 3:stdin | Line: 2/3 Col: 5/10")
+    );
+    feed(&mut editor, ":bn<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│piped\n"));
+
+    feed(&mut editor, ":b 1<CR>:$<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Line: 6/6"));
+    assert!(!editor.run_start_command("/fn \\w+\\(\\)").unwrap());
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Line: 1/6 Col: 1/11"));
+    feed(&mut editor, ":/main/<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Line: 3/6 Col: 4/11"));
+
+    // Other names for an open file go to its buffer
+    std::fs::create_dir_all(dir.join("sub")).unwrap();
+    for name in ["./lib.rs", "sub/../lib.rs"] {
+        feed(&mut editor, &format!(":b 1<CR>:e {}<CR>", path(name)));
+        editor.render(&mut terminal).unwrap();
+        assert!(
+            terminal.text().contains("[2:lib.rs] 3:stdin | "),
+            "{}",
+            name
+        );
+        assert!(!terminal.text().contains("4:"), "{}", name);
+    }
+
+    let error = editor.run_command("/nowhere").unwrap_err();
+    assert_eq!(error.to_string(), "Pattern not found: nowhere");
+}
+
+#[test]
+fn test_headless_substitute_keys_and_errors() {
+    let dir = TempDir::new("headless");
+    let file = dir.join("names.txt");
+    std::fs::write(&file, "foo foo\nbar\n  foo/bar\n").unwrap();
+
+    let mut editor = Editor::new(None, 80, 24);
+    editor.set_headless();
+    editor.open_arg(&file.display().to_string(), None, None);
+    assert!(!editor.run_command(":%s/foo/baz/").unwrap());
+    assert!(!editor.run_command("2,$s/\\//-/g").unwrap());
+    assert!(editor.run_command("s/nothing/x/").is_err());
+    feed(&mut editor, "ggx:nope<CR>");
+    assert!(editor.run_command(":wq").unwrap());
+
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "az foo\nbar\n  baz-bar\n"
+    );
+    assert_eq!(
+        editor.take_errors(),
+        vec!["Not an editor command: nope".to_string()]
+    );
+}
+
+#[test]
+fn test_quit_refuses_with_unwritten_changes() {
+    let dir = TempDir::new("quit");
+    let file = dir.join("draft.txt");
+    std::fs::write(&file, "draft\n").unwrap();
+
+    let mut editor = Editor::new(None, 80, 24);
+    editor.set_headless();
+    editor.open_arg(&file.display().to_string(), None, None);
+    assert!(editor.run_command("q").unwrap());
+
+    feed(&mut editor, "x");
+    let error = editor.run_command("q").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "No write since last change for buffer \"draft.txt\" (add ! to override)"
+    );
+    assert!(editor.run_command("quit!").unwrap());
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "draft\n");
+}
+
+#[test]
+fn test_large_files_load_in_the_background_without_swap_files() {
+    let dir = TempDir::new("largefile");
+    let file = dir.join("big.log");
+    let text: String = (1..=2000).map(|n| format!("entry {} (ok)\n", n)).collect();
+    std::fs::write(&file, &text).unwrap();
+
+    let mut terminal = MemoryTerminal::new(60, 6);
+    let mut editor = Editor::new(None, 60, 6);
+    editor
+        .run_command(&format!("set lf=0 dir={}", dir.display()))
+        .unwrap();
+    editor.open_arg(&file.display().to_string(), Some(1500), Some(12));
+    // Options set in the meantime stay once it's read
+    editor.run_command("set noma").unwrap();
+
+    // Until the text is there it can't be changed or written
+    editor.render(&mut terminal).unwrap();
+    if terminal.text().contains("[loading") {
+        feed(&mut editor, "x:w!<CR>");
+        editor.render(&mut terminal).unwrap();
+        assert!(terminal.text().contains("big.log is still being read"));
+    }
+    for _ in 0..500 {
+        editor.idle();
+        editor.render(&mut terminal).unwrap();
+        if !terminal.text().contains("[loading") {
+            break;
+        }
+        std::thread::sleep(std::time::Duration::from_millis(10));
+    }
+    assert!(terminal.text().contains("1500│entry 1500 (ok)\n"));
+    assert!(
+        terminal
+            .text()
+            .contains("big.log [large] [-] | Line: 1500/2001 Col: 12")
+    );
+    editor.run_command("set ma").unwrap();
+
+    feed(&mut editor, "x:w<CR>");
+    let written = std::fs::read_to_string(&file).unwrap();
+    assert!(written.contains("\nentry 1500 ok)\n"));
+    // Only the file itself, no swap file was ever written
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn test_large_files_are_only_compared_by_time_and_size() {
+    let dir = TempDir::new("large-changes");
+    let file = dir.join("big.log");
+    std::fs::write(&file, "one\ntwo\n").unwrap();
+
+    let mut terminal = MemoryTerminal::new(40, 5);
+    let mut editor = Editor::new(None, 40, 5);
+    editor.set_headless();
+    editor.run_command("set lf=0 noswf").unwrap();
+    editor.open_arg(&file.display().to_string(), None, None);
+    feed(&mut editor, "x");
+
+    // Touched, and not read to find it still holds the same text
+    let touched = std::time::SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1000);
+    std::fs::File::options()
+        .write(true)
+        .open(&file)
+        .unwrap()
+        .set_modified(touched)
+        .unwrap();
+    let error = editor.run_command("w").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "big.log changed on disk since it was read, add ! to write anyway"
+    );
+
+    editor.run_command("e!").unwrap();
+    std::fs::write(&file, "three\n").unwrap();
+    editor.checktime();
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().starts_with("1│three\n"));
+    assert!(
+        terminal
+            .text()
+            .contains("big.log changed on disk, reloading")
+    );
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "three\n");
+}
+
+#[test]
+fn test_editorconfig_options_fix_the_text_on_writing() {
+    let dir = TempDir::new("fix");
+    std::fs::write(
+        dir.join(".editorconfig"),
+        "root = true\n\n[*.txt]\nindent_style = space\nindent_size = 2\n\
+         trim_trailing_whitespace = true\ninsert_final_newline = true\n",
+    )
+    .unwrap();
+    let file = dir.join("notes.txt");
+    std::fs::write(&file, "\tone  \n\t\ttwo\t\n \nend").unwrap();
+
+    let mut terminal = MemoryTerminal::new(60, 8);
+    let mut editor = Editor::new(None, 60, 8);
+    editor.run_command("set noswf").unwrap();
+    editor.open_arg(&file.display().to_string(), None, None);
+    feed(&mut editor, ":set sw? ts? et?<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("et=true"));
+
+    // A write that fails leaves the buffer as it was
+    assert!(editor.run_command(&format!("w {}", dir.display())).is_err());
+    feed(&mut editor, "$");
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("Line: 1/4 Col: 6/6"));
+    feed(&mut editor, "0");
+
+    feed(&mut editor, ":w<CR>");
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "\tone\n\t\ttwo\n\nend\n"
+    );
+
+    // Indentation is only redone when asked for
+    feed(&mut editor, ":set fi<CR>:w<CR>");
+    editor.render(&mut terminal).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&file).unwrap(),
+        "  one\n    two\n\nend\n"
+    );
+    // The buffer has the same text as the file
+    assert!(
+        terminal
+            .text()
+            .starts_with("1│  one\n2│    two\n3│\n4│end\n")
+    );
+
+    // Without `.editorconfig` nothing is touched
+    std::fs::write(&file, "\tone  \n").unwrap();
+    let mut editor = Editor::new(None, 60, 8);
+    editor.set_clean();
+    editor.run_command("set noswf").unwrap();
+    editor.open_arg(&file.display().to_string(), None, None);
+    editor.run_command("w").unwrap();
+    assert_eq!(std::fs::read_to_string(&file).unwrap(), "\tone  \n");
+}
+
+#[test]
+fn test_editorconfig_keeps_the_format_of_existing_files() {
+    let dir = TempDir::new("keep-format");
+    std::fs::write(
+        dir.join(".editorconfig"),
+        "root = true\n\n[*]\ncharset = utf-8\nend_of_line = lf\n",
+    )
+    .unwrap();
+    let file = dir.join("latin1.txt");
+    std::fs::write(&file, b"caf\xE9\r\n").unwrap();
+
+    let mut terminal = MemoryTerminal::new(60, 6);
+    let mut editor = Editor::new(None, 60, 6);
+    editor.run_command("set noswf").unwrap();
+    editor.open_arg(&file.display().to_string(), None, None);
+    editor.render(&mut terminal).unwrap();
+    assert!(terminal.text().contains("latin1.txt [dos] [latin1] |"));
+    editor.run_command("w").unwrap();
+    assert_eq!(std::fs::read(&file).unwrap(), b"caf\xE9\r\n");
+
+    // A new file gets them
+    let new = dir.join("new.txt");
+    editor.run_command(&format!("e {}", new.display())).unwrap();
+    feed(&mut editor, "ione<CR>two<Esc>");
+    editor.run_command("w").unwrap();
+    assert_eq!(std::fs::read(&new).unwrap(), b"one\ntwo");
+}